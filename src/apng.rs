@@ -0,0 +1,497 @@
+use crate::{chunk::{u16_at, u32_at, Chunk}, chunk_type::ChunkType, pixels::{Header, PixelError}, png::Png};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApngError {
+    Malformed { chunk: &'static str, length: usize },
+    InvalidDisposeOp(u8),
+    InvalidBlendOp(u8),
+    DuplicateControl(usize),
+    ControlAfterImageData(usize),
+    Sequence { index: usize, expected: u32, found: u32 },
+    OrphanData(usize),
+    NoFrameData(usize),
+    EmptyFrame(usize),
+    OutsideCanvas(usize),
+    DefaultImageMismatch,
+    NoFrames,
+    FrameCount { declared: u32, found: usize },
+    FrameSize { frame: usize, size: (u32, u32), expected: (u32, u32) },
+    DelayCount { delays: usize, frames: usize },
+}
+
+impl std::error::Error for ApngError {}
+
+impl fmt::Display for ApngError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApngError::Malformed { chunk: "fdAT", length } => write!(f, "fdAT chunk holds {} bytes, too few for a sequence number", length),
+            ApngError::Malformed { chunk, length } => write!(f, "{} chunk holds {} bytes instead of {}", chunk, length, expected_length(chunk)),
+            ApngError::InvalidDisposeOp(op) => write!(f, "Unknown dispose operation {}", op),
+            ApngError::InvalidBlendOp(op) => write!(f, "Unknown blend operation {}", op),
+            ApngError::DuplicateControl(index) => write!(f, "Chunk #{} is a second acTL chunk", index),
+            ApngError::ControlAfterImageData(index) => write!(f, "acTL chunk #{} comes after the image data", index),
+            ApngError::Sequence { index, expected, found } => write!(f, "Chunk #{} has sequence number {} instead of {}", index, found, expected),
+            ApngError::OrphanData(index) => write!(f, "fdAT chunk #{} does not belong to a frame", index),
+            ApngError::NoFrameData(frame) => write!(f, "Frame {} has no image data", frame),
+            ApngError::EmptyFrame(frame) => write!(f, "Frame {} has no pixels", frame),
+            ApngError::OutsideCanvas(frame) => write!(f, "Frame {} reaches past the edges of the image", frame),
+            ApngError::DefaultImageMismatch => write!(f, "First frame is the default image but does not cover the whole image"),
+            ApngError::NoFrames => write!(f, "acTL chunk declares an animation without frames"),
+            ApngError::FrameCount { declared, found } => write!(f, "acTL chunk declares {} frames but {} are present", declared, found),
+            ApngError::FrameSize { frame, size, expected } => write!(f, "Frame {} is {}x{} instead of {}x{}", frame, size.0, size.1, expected.0, expected.1),
+            ApngError::DelayCount { delays, frames } => write!(f, "{} delays given for {} frames, give one for all or one per frame", delays, frames),
+        }
+    }
+}
+
+fn expected_length(chunk: &str) -> usize {
+    if chunk == "acTL" { AnimationControl::LENGTH } else { FrameControl::LENGTH }
+}
+
+fn is(chunk: &Chunk, code: &str) -> bool {
+    chunk.chunk_type().bytes() == code.as_bytes()
+}
+
+// acTL, `plays` is 0 for an animation that loops forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub frames: u32,
+    pub plays: u32,
+}
+
+impl AnimationControl {
+    pub const LENGTH: usize = 8;
+
+    pub fn to_chunk(self) -> Chunk {
+        let data = [self.frames.to_be_bytes(), self.plays.to_be_bytes()].concat();
+        Chunk::new(ChunkType::from_str("acTL").unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+    type Error = ApngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+        if data.len() != AnimationControl::LENGTH {
+            return Err(ApngError::Malformed { chunk: "acTL", length: data.len() });
+        }
+        Ok(AnimationControl { frames: u32_at(data, 0), plays: u32_at(data, 4) })
+    }
+}
+
+// what happens to the frame's area before the next frame is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None,
+    Background,
+    Previous,
+}
+
+// whether the frame replaces its area or is composited over it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source,
+    Over,
+}
+
+// fcTL, the delay is a fraction of a second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+    pub delay: (u16, u16),
+    pub dispose: DisposeOp,
+    pub blend: BlendOp,
+}
+
+impl FrameControl {
+    pub const LENGTH: usize = 26;
+
+    // a denominator of 0 means hundredths of a second
+    pub fn delay_seconds(&self) -> f64 {
+        let (numerator, denominator) = self.delay;
+        numerator as f64 / if denominator == 0 { 100.0 } else { denominator as f64 }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(FrameControl::LENGTH);
+        for value in [self.sequence, self.width, self.height, self.x, self.y] {
+            data.extend(value.to_be_bytes());
+        }
+        data.extend(self.delay.0.to_be_bytes());
+        data.extend(self.delay.1.to_be_bytes());
+        data.push(self.dispose as u8);
+        data.push(self.blend as u8);
+        Chunk::new(ChunkType::from_str("fcTL").unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+    type Error = ApngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+        if data.len() != FrameControl::LENGTH {
+            return Err(ApngError::Malformed { chunk: "fcTL", length: data.len() });
+        }
+
+        let dispose = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            op => return Err(ApngError::InvalidDisposeOp(op)),
+        };
+        let blend = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            op => return Err(ApngError::InvalidBlendOp(op)),
+        };
+
+        Ok(FrameControl {
+            sequence: u32_at(data, 0),
+            width: u32_at(data, 4),
+            height: u32_at(data, 8),
+            x: u32_at(data, 12),
+            y: u32_at(data, 16),
+            delay: (u16_at(data, 20), u16_at(data, 22)),
+            dispose,
+            blend,
+        })
+    }
+}
+
+// fdAT, the sequence number and the piece of compressed frame data after it
+pub fn frame_data(chunk: &Chunk) -> Result<(u32, &[u8]), ApngError> {
+    let data = chunk.data();
+    if data.len() < 4 {
+        return Err(ApngError::Malformed { chunk: "fdAT", length: data.len() });
+    }
+    Ok((u32_at(data, 0), &data[4..]))
+}
+
+pub fn frame_data_chunk(sequence: u32, data: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str("fdAT").unwrap(), [&sequence.to_be_bytes()[..], data].concat())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub control: FrameControl,
+    // position of the fcTL chunk and of the IDAT or fdAT chunks holding the frame's pixels
+    pub index: usize,
+    pub data: Vec<usize>,
+    // the frame is drawn from the IDAT chunks, so a viewer without APNG support shows it too
+    pub default_image: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    pub control: AnimationControl,
+    pub width: u32,
+    pub height: u32,
+    pub frames: Vec<Frame>,
+}
+
+impl Animation {
+    // None for a still image, a file without acTL is one even if it carries frame chunks
+    pub fn parse(png: &Png) -> crate::Result<Option<Animation>> {
+        if png.chunk_by_type("acTL").is_none() {
+            return Ok(None);
+        }
+        let header = Header::try_from(png.chunk_at(0).ok_or(PixelError::MissingHeader)?)?;
+
+        let mut control = None;
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut sequence = 0;
+        let mut image_data = false;
+
+        for (index, chunk) in png.chunks().iter().enumerate() {
+            if is(chunk, "acTL") {
+                if control.is_some() {
+                    return Err(ApngError::DuplicateControl(index).into());
+                }
+                if image_data {
+                    return Err(ApngError::ControlAfterImageData(index).into());
+                }
+                control = Some(AnimationControl::try_from(chunk)?);
+            } else if is(chunk, "fcTL") {
+                let frame = FrameControl::try_from(chunk)?;
+                check_sequence(index, &mut sequence, frame.sequence)?;
+                frames.push(Frame { control: frame, index, data: Vec::new(), default_image: !image_data });
+            } else if is(chunk, "IDAT") {
+                image_data = true;
+                if let Some(frame) = frames.last_mut().filter(|frame| frame.default_image) {
+                    frame.data.push(index);
+                }
+            } else if is(chunk, "fdAT") {
+                let (found, _) = frame_data(chunk)?;
+                check_sequence(index, &mut sequence, found)?;
+                match frames.last_mut() {
+                    Some(frame) if !frame.default_image => frame.data.push(index),
+                    _ => return Err(ApngError::OrphanData(index).into()),
+                }
+            }
+        }
+
+        let control = control.unwrap();
+        if control.frames == 0 {
+            return Err(ApngError::NoFrames.into());
+        }
+        if control.frames as usize != frames.len() {
+            return Err(ApngError::FrameCount { declared: control.frames, found: frames.len() }.into());
+        }
+
+        for (number, frame) in frames.iter().enumerate() {
+            let FrameControl { width, height, x, y, .. } = frame.control;
+            if frame.data.is_empty() {
+                return Err(ApngError::NoFrameData(number).into());
+            }
+            if width == 0 || height == 0 {
+                return Err(ApngError::EmptyFrame(number).into());
+            }
+            if x as u64 + width as u64 > header.width as u64 || y as u64 + height as u64 > header.height as u64 {
+                return Err(ApngError::OutsideCanvas(number).into());
+            }
+            if frame.default_image && (x, y, width, height) != (0, 0, header.width, header.height) {
+                return Err(ApngError::DefaultImageMismatch.into());
+            }
+        }
+
+        Ok(Some(Animation { control, width: header.width, height: header.height, frames }))
+    }
+
+    // the frame's compressed pixels, ready for pixels::inflate with the frame's size
+    pub fn frame_data(&self, png: &Png, frame: usize) -> Vec<u8> {
+        let frame = &self.frames[frame];
+        frame.data
+            .iter()
+            .flat_map(|&index| {
+                let chunk = &png.chunks()[index];
+                if frame.default_image { chunk.data() } else { frame_data(chunk).unwrap().1 }
+            })
+            .copied()
+            .collect()
+    }
+
+    // the default image is only a placeholder when the first frame comes after it
+    pub fn shows_default_image(&self) -> bool {
+        self.frames.first().is_some_and(|frame| frame.default_image)
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames.iter().map(|frame| frame.control.delay_seconds()).sum()
+    }
+}
+
+fn check_sequence(index: usize, expected: &mut u32, found: u32) -> Result<(), ApngError> {
+    if found != *expected {
+        return Err(ApngError::Sequence { index, expected: *expected, found });
+    }
+    *expected += 1;
+    Ok(())
+}
+
+// brings an animation back in line after chunks were taken out of it: frame data left without its
+// fcTL and frames left without data are dropped, the sequence numbers and frame count are rewritten,
+// and an animation without frames becomes a still image; the dropped chunks are returned along with
+// their position before the repair
+pub fn repair(png: &mut Png) -> Vec<(usize, Chunk)> {
+    if png.chunk_by_type("acTL").is_none() {
+        return Vec::new();
+    }
+
+    // a frame is kept when data follows its fcTL before the next fcTL
+    let mut doomed = Vec::new();
+    let mut image_data = false;
+    let mut frame: Option<(usize, bool, bool)> = None;
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        if is(chunk, "fcTL") {
+            if let Some((start, _, false)) = frame {
+                doomed.push(start);
+            }
+            frame = Some((index, !image_data, false));
+        } else if is(chunk, "IDAT") {
+            image_data = true;
+            if let Some((_, true, has_data)) = frame.as_mut() {
+                *has_data = true;
+            }
+        } else if is(chunk, "fdAT") {
+            match frame.as_mut() {
+                Some((_, false, has_data)) if chunk.data().len() >= 4 => *has_data = true,
+                _ => doomed.push(index),
+            }
+        }
+    }
+    if let Some((start, _, false)) = frame {
+        doomed.push(start);
+    }
+
+    let frames = png.chunks_by_type("fcTL").len() - doomed.iter().filter(|&&index| is(&png.chunks()[index], "fcTL")).count();
+    if frames == 0 {
+        doomed.extend(png.chunks_by_type("acTL").into_iter().map(|(index, _)| index));
+    }
+    doomed.sort();
+
+    let mut dropped: Vec<(usize, Chunk)> = doomed.into_iter().rev().filter_map(|index| Some((index, png.remove_at(index)?))).collect();
+    dropped.reverse();
+    if frames == 0 {
+        return dropped;
+    }
+
+    let mut sequence = 0;
+    for index in 0..png.chunks().len() {
+        let chunk = &png.chunks()[index];
+        let renumbered = if is(chunk, "fcTL") {
+            FrameControl::try_from(chunk).ok().map(|control| FrameControl { sequence, ..control }.to_chunk())
+        } else if is(chunk, "fdAT") {
+            frame_data(chunk).ok().map(|(_, data)| frame_data_chunk(sequence, data))
+        } else {
+            continue;
+        };
+        sequence += 1;
+        if let Some(renumbered) = renumbered.filter(|renumbered| renumbered.data() != chunk.data()) {
+            png.replace_at(index, renumbered);
+        }
+    }
+
+    let (index, chunk) = png.chunks_by_type("acTL")[0];
+    if let Ok(control) = AnimationControl::try_from(chunk) {
+        png.replace_at(index, AnimationControl { frames: frames as u32, ..control }.to_chunk());
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, testing::{chunk, module_error}};
+
+    fn frame(sequence: u32, width: u32, height: u32, x: u32, y: u32) -> Chunk {
+        FrameControl { sequence, width, height, x, y, delay: (1, 10), dispose: DisposeOp::None, blend: BlendOp::Over }.to_chunk()
+    }
+
+    fn header() -> Chunk {
+        Header { width: 4, height: 4, bit_depth: 8, colour_type: 6, interlaced: false }.to_chunk()
+    }
+
+    // the default image is the first of three frames, the last one spans two fdAT chunks
+    fn animation() -> Png {
+        Png::from_chunks(vec![
+            header(),
+            AnimationControl { frames: 3, plays: 0 }.to_chunk(),
+            frame(0, 4, 4, 0, 0),
+            chunk("IDAT", &[1, 2]),
+            frame(1, 2, 2, 1, 1),
+            frame_data_chunk(2, &[3]),
+            frame(3, 1, 4, 3, 0),
+            frame_data_chunk(4, &[4]),
+            frame_data_chunk(5, &[5]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn apng_error(png: &Png) -> ApngError {
+        module_error!(Animation::parse(png), Error::Apng)
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let png = animation();
+        let animation = Animation::parse(&png).unwrap().unwrap();
+
+        assert_eq!(animation.control, AnimationControl { frames: 3, plays: 0 });
+        assert!(animation.shows_default_image());
+        assert_eq!(animation.frames.iter().map(|frame| frame.data.clone()).collect::<Vec<_>>(), [vec![3], vec![5], vec![7, 8]]);
+        assert_eq!(animation.frames[1].control.x, 1);
+        assert_eq!(animation.frame_data(&png, 2), [4, 5]);
+        assert!((animation.duration() - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_still_image() {
+        let png = Png::from_chunks(vec![header(), chunk("IDAT", &[1]), chunk("IEND", &[])]);
+        assert_eq!(Animation::parse(&png).unwrap(), None);
+    }
+
+    #[test]
+    fn test_controls_round_trip() {
+        let control = FrameControl { sequence: 7, width: 3, height: 2, x: 1, y: 0, delay: (5, 0), dispose: DisposeOp::Previous, blend: BlendOp::Source };
+        assert_eq!(FrameControl::try_from(&control.to_chunk()), Ok(control));
+        assert_eq!(control.delay_seconds(), 0.05);
+
+        let mut data = control.to_chunk().data().to_vec();
+        data[24] = 3;
+        assert_eq!(FrameControl::try_from(&chunk("fcTL", &data)), Err(ApngError::InvalidDisposeOp(3)));
+        assert_eq!(AnimationControl::try_from(&chunk("acTL", &[0; 5])), Err(ApngError::Malformed { chunk: "acTL", length: 5 }));
+    }
+
+    #[test]
+    fn test_invalid_animations() {
+        let mut png = animation();
+        png.replace_at(5, frame_data_chunk(3, &[3]));
+        assert_eq!(apng_error(&png), ApngError::Sequence { index: 5, expected: 2, found: 3 });
+
+        let mut png = animation();
+        png.replace_at(1, AnimationControl { frames: 2, plays: 0 }.to_chunk());
+        assert_eq!(apng_error(&png), ApngError::FrameCount { declared: 2, found: 3 });
+
+        let mut png = animation();
+        png.replace_at(6, frame(3, 2, 4, 3, 0));
+        assert_eq!(apng_error(&png), ApngError::OutsideCanvas(2));
+
+        let mut png = animation();
+        png.replace_at(2, frame(0, 2, 2, 0, 0));
+        assert_eq!(apng_error(&png), ApngError::DefaultImageMismatch);
+
+        let mut png = animation();
+        png.remove_at(4);
+        assert_eq!(apng_error(&png), ApngError::Sequence { index: 4, expected: 1, found: 2 });
+    }
+
+    #[test]
+    fn test_repair_after_removal() {
+        // the middle frame's data goes, its fcTL has to follow and the rest is renumbered
+        let mut png = animation();
+        png.remove_at(5);
+        let dropped = repair(&mut png);
+
+        assert_eq!(dropped.iter().map(|(index, chunk)| (*index, chunk.chunk_type().to_string())).collect::<Vec<_>>(), [(4, "fcTL".to_string())]);
+        assert_eq!(types(&png), ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fdAT", "IEND"]);
+        let animation = Animation::parse(&png).unwrap().unwrap();
+        assert_eq!(animation.control.frames, 2);
+        assert_eq!(animation.frames[1].control.sequence, 1);
+        assert_eq!(animation.frame_data(&png, 1), [4, 5]);
+    }
+
+    #[test]
+    fn test_repair_without_frames() {
+        let mut png = animation();
+        png.remove_all("fcTL");
+        repair(&mut png);
+        assert_eq!(types(&png), ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(Animation::parse(&png).unwrap(), None);
+    }
+
+    #[test]
+    fn test_append_stays_out_of_frames() {
+        // without IEND the chunk would go in front of the last one, right into the last frame
+        let mut png = animation();
+        png.remove_at(9);
+        png.append_chunk(chunk("ruSt", b"hi"));
+        assert_eq!(types(&png)[4..], ["fcTL", "fdAT", "ruSt", "fcTL", "fdAT", "fdAT"]);
+        assert!(Animation::parse(&png).unwrap().is_some());
+
+        let mut png = animation();
+        png.append_chunk(chunk("ruSt", b"hi"));
+        assert_eq!(types(&png)[8..], ["fdAT", "ruSt", "IEND"]);
+        assert!(Animation::parse(&png).unwrap().is_some());
+    }
+}
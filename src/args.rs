@@ -1,10 +1,103 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use crate::{colour::{Chromaticities, RenderingIntent}, commands::Selection, limits::Limits, selector::Selector, strip::Preset};
 
 #[derive(Parser)]
 pub struct Args {
     #[command(subcommand)]
     pub cmd: Commands,
+
+    #[command(flatten)]
+    pub limits: LimitArgs,
+
+    /// Keep the previous version of every rewritten image as <file>.bak
+    #[arg(long, global = true)]
+    pub backup: bool,
+}
+
+// parsing caps for untrusted files, anything left out keeps its default
+#[derive(clap::Args, Debug, Clone)]
+pub struct LimitArgs {
+    /// Reject chunks whose length field is larger than this many bytes
+    #[arg(long, global = true)]
+    pub max_chunk_length: Option<u32>,
+
+    /// Reject files larger than this many bytes
+    #[arg(long, global = true)]
+    pub max_total_size: Option<usize>,
+
+    /// Reject files with more chunks than this
+    #[arg(long, global = true)]
+    pub max_chunks: Option<usize>,
+
+    /// Stop inflating compressed chunk data past this many bytes
+    #[arg(long, global = true)]
+    pub max_decompressed_size: Option<usize>,
+}
+
+impl LimitArgs {
+    pub fn limits(&self) -> Limits {
+        let defaults = Limits::default();
+        Limits {
+            max_chunk_length: self.max_chunk_length.unwrap_or(defaults.max_chunk_length),
+            max_total_size: self.max_total_size.unwrap_or(defaults.max_total_size),
+            max_chunk_count: self.max_chunks.unwrap_or(defaults.max_chunk_count),
+            max_decompressed_size: self.max_decompressed_size.unwrap_or(defaults.max_decompressed_size),
+        }
+    }
+}
+
+// more inputs next to the positional path and how a run over many files is carried out
+#[derive(clap::Args, Debug, Clone)]
+pub struct Batch {
+    /// Also process this file, glob pattern or directory, may be repeated
+    #[arg(long = "file", value_name = "PATH")]
+    pub files: Vec<PathBuf>,
+
+    /// Descend into the subdirectories of directory inputs
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Number of files processed at the same time, one per core by default
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+
+    /// Write the outcome for every file to this JSON file
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+impl Batch {
+    pub fn paths(&self, first: &[PathBuf]) -> Vec<PathBuf> {
+        first.iter().chain(&self.files).cloned().collect()
+    }
+}
+
+// which chunks of the requested type a command works on, the first one when no flag is given
+#[derive(clap::Args, Debug, Clone)]
+pub struct Target {
+    /// Work on every chunk of the given type
+    #[arg(long, conflicts_with = "index")]
+    pub all: bool,
+
+    /// Work on the chunk at this position in the file (as listed by print)
+    #[arg(long)]
+    pub index: Option<usize>,
+
+    /// Work on every chunk matching a selector, e.g. "ancillary & private & size:>1k"
+    #[arg(long, conflicts_with_all = ["all", "index"])]
+    pub select: Option<Selector>,
+}
+
+impl Target {
+    pub fn selection(&self) -> Selection {
+        match (&self.select, self.all, self.index) {
+            (Some(selector), _, _) => Selection::Matching(selector.clone()),
+            (None, true, _) => Selection::All,
+            (None, false, Some(index)) => Selection::Index(index),
+            (None, false, None) => Selection::First,
+        }
+    }
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -14,20 +107,397 @@ pub enum Commands {
         filepath: PathBuf,
         chunk_code: String,
         message: String,
+
+        /// Split the message across chunks holding at most this many bytes each
+        #[arg(long)]
+        split_size: Option<usize>,
+
+        /// Protect the message with this many Reed-Solomon parity bytes per 255 byte codeword
+        #[arg(long)]
+        ecc: Option<u8>,
+
+        /// Interleave this many codewords together to survive bursts of damaged bytes
+        #[arg(long, default_value_t = 1, requires = "ecc")]
+        interleave: u16,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        batch: Batch,
     },
 
     /// Decode a hidden message inside an existing chunk
     Decode {
         filepath: PathBuf,
-        chunk_code: String,
+        #[arg(required_unless_present = "select")]
+        chunk_code: Option<String>,
+
+        #[command(flatten)]
+        target: Target,
+
+        #[command(flatten)]
+        batch: Batch,
     },
 
     /// Remove a chunk (container) for a hidden message
     Remove {
+        filepath: PathBuf,
+        #[arg(required_unless_present = "select")]
+        chunk_code: Option<String>,
+
+        #[command(flatten)]
+        target: Target,
+
+        /// Also remove critical chunks (IHDR, PLTE, IDAT, IEND), which leaves an image viewers cannot show
+        #[arg(long)]
+        force: bool,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        batch: Batch,
+    },
+
+    /// Replace the message inside an existing chunk
+    Replace {
         filepath: PathBuf,
         chunk_code: String,
+        message: String,
+
+        #[command(flatten)]
+        target: Target,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
 
     /// Print all messages contained in a PNG file
-    Print {filepath: PathBuf}
-}
\ No newline at end of file
+    Print {
+        #[arg(required = true)]
+        filepaths: Vec<PathBuf>,
+
+        /// Only print the chunks matching this selector
+        #[arg(long)]
+        select: Option<Selector>,
+
+        #[command(flatten)]
+        batch: Batch,
+    },
+
+    /// Check that PNG files are well formed, every chunk CRC included
+    Validate {
+        #[arg(required = true)]
+        filepaths: Vec<PathBuf>,
+
+        /// Also check the files against this TOML policy
+        #[arg(long)]
+        policy: Option<PathBuf>,
+
+        #[command(flatten)]
+        batch: Batch,
+    },
+
+    /// Remove ancillary chunks, everything but the critical chunks unless told to keep more
+    Strip {
+        filepath: PathBuf,
+
+        /// Ancillary chunks kept when no --keep or --drop rule matches them
+        #[arg(long, value_enum, default_value_t = Preset::None)]
+        preset: Preset,
+
+        /// Keep the chunks matching this selector, may be repeated
+        #[arg(long)]
+        keep: Vec<Selector>,
+
+        /// Remove the chunks matching this selector even when kept otherwise, may be repeated
+        #[arg(long)]
+        drop: Vec<Selector>,
+
+        /// Remove the chunks breaking this TOML policy and keep the ones it allows by name
+        #[arg(long)]
+        policy: Option<PathBuf>,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        batch: Batch,
+    },
+
+    /// Store a hash of the pixels and chunks in a seAl chunk so later changes can be told apart
+    Seal {
+        filepath: PathBuf,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        batch: Batch,
+    },
+
+    /// Report whether sealed images are intact, changed in their metadata only or in their pixels
+    Check {
+        #[arg(required = true)]
+        filepaths: Vec<PathBuf>,
+
+        #[command(flatten)]
+        batch: Batch,
+    },
+
+    /// Show the chunks added, removed, moved or modified between two PNG files
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+    },
+
+    /// Check whether two PNG files hold the same pixels, however they are encoded
+    Compare {
+        first: PathBuf,
+        second: PathBuf,
+
+        /// Write an image marking the differing pixels in red here
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Show the colour chunks (gAMA, cHRM, sRGB, iCCP, sBIT and the cICP, mDCV and cLLi HDR metadata), extract the ICC profile or replace them
+    Colour {
+        filepath: PathBuf,
+
+        /// Write the uncompressed ICC profile to this file
+        #[arg(long)]
+        extract_icc: Option<PathBuf>,
+
+        /// Remove every colour chunk before setting the ones given
+        #[arg(long)]
+        clear: bool,
+
+        /// Gamma the samples are encoded with, 0.45455 for the usual 1/2.2
+        #[arg(long)]
+        gamma: Option<f64>,
+
+        /// White point and red, green and blue primaries as eight comma separated x,y values
+        #[arg(long)]
+        chromaticities: Option<Chromaticities>,
+
+        /// Mark the image as sRGB with this rendering intent, replacing any ICC profile
+        #[arg(long)]
+        srgb: Option<RenderingIntent>,
+
+        /// Embed this ICC profile, replacing any sRGB chunk
+        #[arg(long)]
+        icc: Option<PathBuf>,
+
+        /// Name stored along with the ICC profile
+        #[arg(long, default_value = "ICC profile")]
+        icc_name: String,
+
+        /// Significant bits of each channel, comma separated
+        #[arg(long, value_delimiter = ',')]
+        significant_bits: Option<Vec<u8>>,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Write one copy of an image per recipient, each carrying the recipient's ID in its pixels
+    Watermark {
+        filepath: PathBuf,
+        #[arg(required = true)]
+        recipients: Vec<String>,
+
+        /// Secret deciding where the marks go, identify needs the same one
+        #[arg(short, long)]
+        key: String,
+
+        /// Directory the copies are written to, as <name>-<recipient>.png
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+
+    /// Tell which recipient a watermarked copy was made for
+    Identify {
+        filepath: PathBuf,
+        #[arg(required = true)]
+        recipients: Vec<String>,
+
+        /// Secret the copies were watermarked with
+        #[arg(short, long)]
+        key: String,
+    },
+
+    /// Hide a message in the least significant bits of the pixels instead of a chunk
+    Hide {
+        filepath: PathBuf,
+        message: String,
+
+        /// Protect the message with this many Reed-Solomon parity bytes per 255 byte codeword
+        #[arg(long)]
+        ecc: Option<u8>,
+
+        /// Interleave this many codewords together to survive bursts of damaged bytes
+        #[arg(long, default_value_t = 1, requires = "ecc")]
+        interleave: u16,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Read a message hidden in the pixels
+    Reveal {
+        filepath: PathBuf,
+    },
+
+    /// Create or apply a patch holding only the chunks that changed between two PNG files
+    Patch {
+        #[command(subcommand)]
+        action: PatchAction,
+    },
+
+    /// Render the frames of an animated PNG to files, or build an animated PNG from still images
+    Frames {
+        #[command(subcommand)]
+        action: FramesAction,
+    },
+
+    /// Search the data of ancillary chunks, text chunks decompressed, for a regular expression
+    Grep {
+        pattern: String,
+        #[arg(required = true)]
+        filepaths: Vec<PathBuf>,
+
+        /// Match the pattern literally instead of as a regular expression
+        #[arg(short = 'F', long)]
+        fixed_strings: bool,
+
+        /// Ignore case when matching
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Only search private chunk types
+        #[arg(long)]
+        private: bool,
+
+        #[command(flatten)]
+        batch: Batch,
+    },
+
+    /// Write the data of every chunk matching a selector to NNN-TYPE.bin files
+    Extract {
+        filepath: PathBuf,
+        selector: Selector,
+
+        /// Directory the chunk files are written to
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+
+    /// Write every chunk's data to NNN-TYPE.bin files plus a manifest of their order, types and CRCs
+    Unpack {
+        filepath: PathBuf,
+        dir: PathBuf,
+    },
+
+    /// Rebuild a PNG file from a directory written by unpack, recomputing every CRC
+    Pack {
+        dir: PathBuf,
+
+        /// Where the image is written, "-" writes it to stdout
+        output: PathBuf,
+    },
+
+    /// Split a payload file into volumes spread across several PNG files
+    Split {
+        payload: PathBuf,
+        #[arg(required = true)]
+        carriers: Vec<PathBuf>,
+    },
+
+    /// Join the volumes held by several PNG files (in any order) back into the payload
+    Join {
+        #[arg(required = true)]
+        filepaths: Vec<PathBuf>,
+
+        /// Write the payload to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Split a secret file into Shamir shares, one per PNG file, any `threshold` of which recover it
+    Share {
+        secret: PathBuf,
+        #[arg(required = true)]
+        carriers: Vec<PathBuf>,
+
+        /// Number of shares needed to recover the secret
+        #[arg(short, long)]
+        threshold: usize,
+    },
+
+    /// Combine the Shamir shares held by several PNG files back into the secret
+    Combine {
+        #[arg(required = true)]
+        filepaths: Vec<PathBuf>,
+
+        /// Write the secret to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+#[derive(Subcommand, Debug, Clone)]
+pub enum PatchAction {
+    /// Write the chunk insertions, deletions and replacements turning one PNG file into another
+    Create {
+        base: PathBuf,
+        new: PathBuf,
+
+        /// Where the patch is written, "-" writes it to stdout
+        patch: PathBuf,
+    },
+
+    /// Apply a patch, refusing any file other than the one it was created from
+    Apply {
+        filepath: PathBuf,
+        patch: PathBuf,
+
+        /// Write the image here instead of rewriting the input, "-" writes it to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FramesAction {
+    /// Write every frame as it is shown, dispose and blend operations applied, to <dir>/frame-NNN.png
+    Extract {
+        filepath: PathBuf,
+        dir: PathBuf,
+    },
+
+    /// Assemble PNG files of the same size into an animation, the first one is also the still image
+    Build {
+        #[arg(required = true)]
+        frames: Vec<PathBuf>,
+
+        /// Where the animation is written, "-" writes it to stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Milliseconds each frame is shown, one value for all frames or one per frame
+        #[arg(short, long, value_delimiter = ',', default_value = "100")]
+        delay: Vec<u16>,
+
+        /// Times the animation is played, 0 loops forever
+        #[arg(long, default_value_t = 0)]
+        plays: u32,
+    },
+}
@@ -0,0 +1,147 @@
+use std::{fs, io::{self, Write}, path::{Path, PathBuf}};
+use tempfile::NamedTempFile;
+
+// Rewrites a file so that a crash or a full disk leaves either the old or the new contents, never a mix:
+// the data goes to a temporary file next to the original, is synced, and is then renamed over it.
+// The original's permissions are kept, the modification time is the time of the write like any other save.
+pub fn write(path: &Path, bytes: &[u8], backup: bool) -> io::Result<()> {
+    // a symlink is followed so the file it points to is replaced rather than the link itself
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut file = NamedTempFile::new_in(&dir)?;
+    file.write_all(bytes)?;
+    match fs::metadata(&path) {
+        Ok(metadata) => file.as_file().set_permissions(metadata.permissions())?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+        Err(err) => return Err(err),
+    }
+    file.as_file().sync_all()?;
+
+    if backup && path.exists() {
+        keep_backup(&path)?;
+    }
+    file.persist(&path).map_err(|err| err.error)?;
+
+    sync_dir(&dir)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+// a hard link keeps the old contents alive once the rename swaps the new file in, copying is the fallback
+// for filesystems without links
+fn keep_backup(path: &Path) -> io::Result<()> {
+    let backup = backup_path(path);
+    match fs::remove_file(&backup) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {},
+    }
+
+    if fs::hard_link(path, &backup).is_err() {
+        fs::copy(path, &backup)?;
+    }
+    Ok(())
+}
+
+// the rename itself only survives a crash once the directory entry is on disk
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn leftovers(dir: &TempDir) -> Vec<PathBuf> {
+        fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().path()).collect()
+    }
+
+    #[test]
+    fn test_write_replaces_contents() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+
+        write(&path, b"new", false).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(leftovers(&dir), vec![path]);
+    }
+
+    #[test]
+    fn test_write_new_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("image.png");
+
+        write(&path, b"new", true).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!backup_path(&path).exists());
+    }
+
+    #[test]
+    fn test_write_keeps_backup() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"first").unwrap();
+
+        write(&path, b"second", true).unwrap();
+        write(&path, b"third", true).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"third");
+        assert_eq!(fs::read(dir.path().join("image.png.bak")).unwrap(), b"second");
+    }
+
+    #[test]
+    fn test_write_failure_leaves_original() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("missing").join("image.png");
+
+        assert!(write(&path, b"new", false).is_err());
+        assert!(leftovers(&dir).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write(&path, b"new", false).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_through_symlink() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("image.png");
+        let link = dir.path().join("link.png");
+        fs::write(&target, b"old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write(&link, b"new", false).unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+    }
+}
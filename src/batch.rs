@@ -0,0 +1,297 @@
+use crate::{atomic, commands::is_stdio, error::Error};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{error::Error as _, fmt, fs, io::{self, Write}, path::{Path, PathBuf}, sync::Mutex};
+
+#[derive(Debug)]
+pub enum BatchError {
+    NoMatches(String),
+    InvalidPattern(String, glob::PatternError),
+    SharedOutput(usize),
+    RepeatedFile(PathBuf),
+    ThreadPool(rayon::ThreadPoolBuildError),
+    Failed { failed: usize, total: usize },
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BatchError::InvalidPattern(_, err) => Some(err),
+            BatchError::ThreadPool(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BatchError::NoMatches(pattern) => write!(f, "No files match \"{}\"", pattern),
+            BatchError::InvalidPattern(pattern, _) => write!(f, "\"{}\" is not a valid glob pattern", pattern),
+            BatchError::SharedOutput(count) => write!(f, "A single --output cannot hold the results of {} input files", count),
+            BatchError::RepeatedFile(path) => write!(f, "{} is given more than once", path.display()),
+            BatchError::ThreadPool(_) => write!(f, "Worker threads could not be started"),
+            BatchError::Failed { failed, total } => write!(f, "{} of {} files failed", failed, total),
+        }
+    }
+}
+
+// what happened to one input file, in the shape written to the JSON manifest
+#[derive(Debug, Serialize)]
+pub struct Outcome {
+    pub path: PathBuf,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Failure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub code: &'static str,
+    pub exit_code: u8,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub causes: Vec<String>,
+}
+
+impl From<&Error> for Failure {
+    fn from(err: &Error) -> Self {
+        let mut causes = Vec::new();
+        let mut source = err.source();
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+
+        Failure { code: err.code(), exit_code: err.exit_code(), message: err.to_string(), causes }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    total: usize,
+    succeeded: usize,
+    failed: usize,
+    files: &'a [Outcome],
+}
+
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+// PNG files of a directory in name order, subdirectories are only entered when recursing
+fn walk(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.map(|entry| Ok(entry?.path())).collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                walk(&path, recursive, files)?;
+            }
+        } else if is_png(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// expands globs and directories into the files they stand for, plain paths are kept as they are
+// so a missing file is reported by the command working on it
+pub fn resolve(paths: &[PathBuf], recursive: bool) -> crate::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if is_stdio(path) || (!path.exists() && !is_pattern(path)) {
+            files.push(path.clone());
+        } else if path.is_dir() {
+            walk(path, recursive, &mut files)?;
+        } else if path.exists() {
+            files.push(path.clone());
+        } else {
+            let pattern = path.to_string_lossy();
+            let matches = glob::glob(&pattern).map_err(|err| BatchError::InvalidPattern(pattern.to_string(), err))?;
+
+            let before = files.len();
+            for entry in matches {
+                let entry = entry.map_err(io::Error::from)?;
+                if entry.is_dir() {
+                    walk(&entry, recursive, &mut files)?;
+                } else {
+                    files.push(entry);
+                }
+            }
+            if files.len() == before {
+                return Err(BatchError::NoMatches(pattern.to_string()).into());
+            }
+        }
+    }
+
+    // the same file reached twice, e.g. through overlapping globs, is only worked on once
+    let mut seen = std::collections::HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+    Ok(files)
+}
+
+// a single plain path keeps the one-file behaviour: output as before and errors end the process
+pub fn is_batch(paths: &[PathBuf], files: &[PathBuf]) -> bool {
+    paths.len() != 1 || files.len() != 1 || files[0] != paths[0]
+}
+
+// runs `job` over every file on a pool of `jobs` threads (one per core by default), a failing file
+// does not stop the others; each file's output is printed as soon as it is done, under a header
+// naming the file unless the output already names it on every line
+pub fn run<F>(files: &[PathBuf], jobs: Option<usize>, headers: bool, job: F) -> crate::Result<Vec<Outcome>>
+where
+    F: Fn(&PathBuf) -> crate::Result<Vec<String>> + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()
+        .map_err(BatchError::ThreadPool)?;
+    let console = Mutex::new(());
+
+    Ok(pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| {
+                let outcome = match job(path) {
+                    Ok(output) => Outcome { path: path.clone(), ok: true, output, error: None },
+                    Err(err) => Outcome { path: path.clone(), ok: false, output: Vec::new(), error: Some(Failure::from(&err)) },
+                };
+
+                let _lock = console.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                print_outcome(&outcome, headers);
+                outcome
+            })
+            .collect()
+    }))
+}
+
+fn print_outcome(outcome: &Outcome, headers: bool) {
+    match &outcome.error {
+        None => {
+            let mut stdout = io::stdout().lock();
+            if headers {
+                let _ = writeln!(stdout, "==> {} <==", outcome.path.display());
+            }
+            for line in &outcome.output {
+                let _ = writeln!(stdout, "{}", line);
+            }
+        },
+        Some(failure) => {
+            let mut stderr = io::stderr().lock();
+            if headers {
+                let _ = writeln!(stderr, "==> {} <==", outcome.path.display());
+                let _ = writeln!(stderr, "Error [{}]: {}", failure.code, failure.message);
+            } else {
+                let _ = writeln!(stderr, "{}: Error [{}]: {}", outcome.path.display(), failure.code, failure.message);
+            }
+            for cause in &failure.causes {
+                let _ = writeln!(stderr, "  caused by: {}", cause);
+            }
+        },
+    }
+}
+
+// prints the summary, writes the manifest if one was asked for and fails when any file did
+pub fn finish(outcomes: &[Outcome], manifest: Option<&PathBuf>) -> crate::Result<()> {
+    let failed = outcomes.iter().filter(|outcome| !outcome.ok).count();
+    let total = outcomes.len();
+    eprintln!("{} files processed, {} succeeded, {} failed", total, total - failed, failed);
+
+    if let Some(path) = manifest {
+        let manifest = Manifest { total, succeeded: total - failed, failed, files: outcomes };
+        let mut json = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+        json.push(b'\n');
+        atomic::write(path, &json, false)?;
+    }
+
+    if failed > 0 {
+        return Err(BatchError::Failed { failed, total }.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in ["b.png", "a.PNG", "notes.txt", "sub/c.png"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        dir
+    }
+
+    fn names(dir: &TempDir, files: &[PathBuf]) -> Vec<String> {
+        files.iter().map(|file| file.strip_prefix(dir.path()).unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn test_resolve_directory() {
+        let dir = tree();
+        let root = vec![dir.path().to_path_buf()];
+
+        assert_eq!(names(&dir, &resolve(&root, false).unwrap()), ["a.PNG", "b.png"]);
+        assert_eq!(names(&dir, &resolve(&root, true).unwrap()), ["a.PNG", "b.png", "sub/c.png"]);
+    }
+
+    #[test]
+    fn test_resolve_glob() {
+        let dir = tree();
+        let pattern = vec![dir.path().join("*.png"), dir.path().join("b.png")];
+
+        assert_eq!(names(&dir, &resolve(&pattern, false).unwrap()), ["b.png"]);
+        assert!(matches!(
+            resolve(&[dir.path().join("*.gif")], false),
+            Err(Error::Batch(BatchError::NoMatches(_)))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_keeps_plain_paths() {
+        let paths = vec![PathBuf::from("missing.png"), PathBuf::from("-")];
+
+        assert_eq!(resolve(&paths, false).unwrap(), paths);
+        assert!(!is_batch(&paths[..1], &paths[..1]));
+        assert!(is_batch(&paths, &paths));
+    }
+
+    #[test]
+    fn test_run_continues_past_failures() {
+        let files: Vec<PathBuf> = ["one", "two", "three"].iter().map(PathBuf::from).collect();
+        let outcomes = run(&files, Some(2), true, |path| match path.to_str() {
+            Some("two") => Err(Error::ChunkNotFound("ruSt".to_string())),
+            _ => Ok(vec![format!("done {}", path.display())]),
+        }).unwrap();
+
+        assert_eq!(outcomes.iter().map(|outcome| outcome.ok).collect::<Vec<_>>(), [true, false, true]);
+        assert_eq!(outcomes[2].output, ["done three"]);
+        assert_eq!(outcomes[1].error.as_ref().unwrap().code, "chunk-not-found");
+        assert!(matches!(finish(&outcomes, None), Err(Error::Batch(BatchError::Failed { failed: 1, total: 3 }))));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let dir = TempDir::new().unwrap();
+        let manifest = dir.path().join("manifest.json");
+        let outcomes = run(&[PathBuf::from("one")], None, false, |_| Ok(vec!["fine".to_string()])).unwrap();
+
+        finish(&outcomes, Some(&manifest)).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(manifest).unwrap()).unwrap();
+        assert_eq!(json["succeeded"], 1);
+        assert_eq!(json["files"][0]["path"], "one");
+        assert_eq!(json["files"][0]["output"][0], "fine");
+    }
+}
@@ -1,7 +1,16 @@
-use std::{io::BufReader, io::Read, string::FromUtf8Error};
-use crate::{chunk_type::ChunkType};
+use std::string::FromUtf8Error;
+use crate::{chunk_type::ChunkType, error::Error, limits::Limits};
 use core::fmt;
 
+// big-endian fields of chunk data, the caller has checked the data is long enough
+pub(crate) fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
 pub const CRC32_LOOKUP_TABLE: [u32; 256] = {
     let mut table: [u32; 256] = [0;256];
 
@@ -33,25 +42,6 @@ pub struct Chunk {
     crc: u32,
 }
 
-#[derive(Debug)]
-pub enum ChunkError {
-    ReadErr(std::io::Error),
-    DataLength(usize),
-    CRCMismatch(u32, u32)
-}
-
-impl std::error::Error for ChunkError {}
-
-impl fmt::Display for ChunkError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ChunkError::ReadErr(err) => write!(f, "Byte reading failed. Returned: {}", err),
-            ChunkError::DataLength(size) => write!(f, "Data size insufficient to parse into a valid chunk ({} < 12)", size),
-            ChunkError::CRCMismatch(correct, incorrect) => write!(f, "Chunk contains {:#x} as CRC value when it should contain {:#x}", incorrect, correct)
-        }
-    }
-}
-
 pub fn crc32(input_str: &[u8]) -> u32 {
     let mut crc: u32 = 0xFFFFFFFF;
 
@@ -72,9 +62,9 @@ impl Chunk {
 
         Chunk {
             length: data.len() as u32,
-            chunk_type: chunk_type,
-            data: data,
-            crc: crc   
+            chunk_type,
+            data,
+            crc
         }
     }
 
@@ -111,58 +101,88 @@ impl Chunk {
 }
 
 impl TryFrom<&[u8]> for Chunk {
-    type Error = ChunkError;
+    type Error = Error;
 
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        let length = data.len();
-        if length < 12 {
-            return Err(ChunkError::DataLength(data.len()));
-        }
+        Chunk::parse(data, true, &Limits::default())
+    }
+}
 
-        let mut reader = BufReader::new(data);
-        let mut length_buf: [u8; 4] = [0;4];
-        let mut chunk_buf: [u8; 4] = [0;4];
-        let mut crc_buf: [u8;4] = [0;4];
-        
-        reader.read_exact(&mut length_buf).map_err(|err| ChunkError::ReadErr(err))?;
-        let length = u32::from_be_bytes(length_buf);
-        
-        reader.read_exact(&mut chunk_buf).map_err(|err| ChunkError::ReadErr(err))?;
-        let chunk_type = ChunkType::try_from(chunk_buf).unwrap();
-        
-        let mut data: Vec<u8> = vec![0; length as usize];
-        reader.read_exact(&mut data).map_err(|err| ChunkError::ReadErr(err))?;
+impl Chunk {
+    // keeps the stored CRC even when it does not match, so damaged data can still be recovered
+    pub fn try_from_unchecked(data: &[u8]) -> crate::Result<Chunk> {
+        Chunk::parse(data, false, &Limits::default())
+    }
 
-        reader.read_exact(&mut crc_buf).map_err(|err| ChunkError::ReadErr(err))?;
-        let crc = u32::from_be_bytes(crc_buf);
-        let crc_stream: Vec<u8> = chunk_buf
+    pub fn try_from_with_limits(data: &[u8], limits: &Limits) -> crate::Result<Chunk> {
+        Chunk::parse(data, true, limits)
+    }
+
+    pub fn is_crc_valid(&self) -> bool {
+        self.crc == self.computed_crc()
+    }
+
+    fn computed_crc(&self) -> u32 {
+        let crc_stream: Vec<u8> = self.chunk_type
+                .bytes()
                 .into_iter()
-                .chain(data.clone())
+                .chain(self.data.iter().copied())
                 .collect();
 
-        if crc != crc32(&crc_stream) {
-            return Err(ChunkError::CRCMismatch(crc, crc32(&crc_stream)));
+        crc32(&crc_stream)
+    }
+
+    pub(crate) fn parse(data: &[u8], verify_crc: bool, limits: &Limits) -> crate::Result<Chunk> {
+        let length = match data.get(..4) {
+            Some(length_buf) => u32::from_be_bytes(length_buf.try_into().unwrap()),
+            None => return Err(Error::Truncated { needed: 12, available: data.len() }),
+        };
+        limits.check_chunk_length(length)?;
+        if data.len() < 12 {
+            return Err(Error::Truncated { needed: (length as usize).saturating_add(12), available: data.len() });
         }
 
-        Ok(Chunk {
-            length: length,
-            chunk_type: chunk_type,
-            data: data,
-            crc: crc
-        })
+        let chunk_buf: [u8; 4] = data[4..8].try_into().unwrap();
+        let chunk_type = ChunkType::try_from(chunk_buf)?;
+
+        // checked before allocating so a bogus length field cannot request gigabytes
+        let needed = (length as usize).saturating_add(12);
+        if data.len() < needed {
+            return Err(Error::Truncated { needed, available: data.len() });
+        }
+
+        let data_end = 8 + length as usize;
+        let chunk_data = data[8..data_end].to_vec();
+        let crc = u32::from_be_bytes(data[data_end..needed].try_into().unwrap());
+
+        let chunk = Chunk {
+            length,
+            chunk_type,
+            data: chunk_data,
+            crc
+        };
+
+        if verify_crc && !chunk.is_crc_valid() {
+            return Err(Error::CrcMismatch { stored: crc, computed: chunk.computed_crc() });
+        }
+
+        Ok(chunk)
+    }
+}
+
+impl Chunk {
+    // the Display layout with `data` standing in for the raw data, for chunks whose contents can be decoded
+    pub fn display_with(&self, data: &str) -> String {
+        format!(
+            "{{\n [Data Length]: {}\n [Chunk Type]: {}\n [Data]: {}\n [CRC32-ISO-HDLC]: {}\n}}\n",
+            self.length, String::from_utf8_lossy(&self.chunk_type.body), data, self.crc,
+        )
     }
 }
 
 impl std::fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{{\n")?;
-        write!(f, " [Data Length]: {}\n", self.length)?;
-        write!(f, " [Chunk Type]: {}\n", String::from_utf8(self.chunk_type.body.to_vec()).unwrap())?;
-        write!(f, " [Data]: {}\n", self.data_as_string().map_or("INVALID_UTF8_STRING".to_string(), |s| s))?;
-        write!(f, " [CRC32-ISO-HDLC]: {}\n", self.crc)?;
-        write!(f, "}}\n")?;
-
-        Ok(())
+        write!(f, "{}", self.display_with(&self.data_as_string().map_or("INVALID_UTF8_STRING".to_string(), |s| s)))
     }
 }
 
@@ -273,6 +293,47 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_unchecked_chunk_from_bytes() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        chunk_data[10] ^= 0xFF;
+
+        assert!(Chunk::try_from(chunk_data.as_ref()).is_err());
+
+        let chunk = Chunk::try_from_unchecked(chunk_data.as_ref()).unwrap();
+        assert!(!chunk.is_crc_valid());
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.as_bytes(), chunk_data);
+        assert!(testing_chunk().is_crc_valid());
+    }
+
+    #[test]
+    fn test_malformed_chunk_from_bytes() {
+        let mut chunk_data = testing_chunk().as_bytes();
+
+        chunk_data[5] = b'1';
+        assert!(matches!(Chunk::try_from(chunk_data.as_ref()), Err(Error::InvalidChunkType(_))));
+
+        let truncated = &testing_chunk().as_bytes()[..30];
+        assert!(matches!(Chunk::try_from(truncated), Err(Error::Truncated { needed: 54, available: 30 })));
+
+        // lengths past the spec maximum are refused before the data is even looked at
+        let huge = [0xFF, 0xFF, 0xFF, 0xFF, b'R', b'u', b'S', b't', 0, 0, 0, 0];
+        assert!(matches!(Chunk::try_from(&huge[..]), Err(Error::LimitExceeded { limit: "chunk length", .. })));
+        assert!(matches!(Chunk::try_from_with_limits(&huge[..], &Limits::UNLIMITED), Err(Error::Truncated { .. })));
+    }
+
+    #[test]
+    fn test_chunk_length_limit() {
+        let chunk_data = testing_chunk().as_bytes();
+        let limits = Limits { max_chunk_length: 41, ..Limits::default() };
+
+        assert!(matches!(
+            Chunk::try_from_with_limits(chunk_data.as_ref(), &limits),
+            Err(Error::LimitExceeded { limit: "chunk length", value: 42, max: 41 })
+        ));
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -1,3 +1,4 @@
+use crate::error::Error;
 use std::{convert::TryFrom, fmt, str::FromStr};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -5,42 +6,25 @@ pub struct ChunkType {
     pub body: [u8; 4]
 }
 
-#[derive(Debug)]
-pub enum ChunkTypeError {
-    InvalidByteRange(u8),
-    InvalidString(String)
-}
-
-impl std::error::Error for ChunkTypeError {}
-
-impl fmt::Display for ChunkTypeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ChunkTypeError::InvalidByteRange(byte) => write!(f, "The byte {} is not within ASCII bounds", byte),
-            ChunkTypeError::InvalidString(feedback) => write!(f, "Cannot parse chunk type from str because of: {}", feedback)
-        }
-    }
-}
-
 impl ChunkType {
     pub fn bytes(&self) -> [u8; 4] {
-        self.body.clone()
+        self.body
     }
 
     pub fn is_critical(&self) -> bool {
-        if (self.body[0] >> 5) & 1 == 0 {true} else {false}
+        (self.body[0] >> 5) & 1 == 0
     }
     
     pub fn is_public(&self) -> bool {
-        if (self.body[1] >> 5) & 1 == 0 {true} else {false}
+        (self.body[1] >> 5) & 1 == 0
     }
     
     pub fn is_reserved_bit_valid(&self) -> bool {
-        if (self.body[2] >> 5) & 1 == 0 {true} else {false}
+        (self.body[2] >> 5) & 1 == 0
     }
     
     pub fn is_safe_to_copy(&self) -> bool {
-        if (self.body[3] >> 5) & 1 == 1 {true} else {false}
+        (self.body[3] >> 5) & 1 == 1
     } 
 
     pub fn is_valid(&self) -> bool {
@@ -55,12 +39,12 @@ impl ChunkType {
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = ChunkTypeError;
+    type Error = Error;
     
     fn try_from(bytes: [u8; 4]) -> Result<Self, Self::Error> {
         bytes.iter().try_for_each(|byte| {
             if !byte.is_ascii_alphabetic() {
-                Err(ChunkTypeError::InvalidByteRange(*byte))
+                Err(Error::InvalidChunkType(bytes))
             } else {
                 Ok(())
             }
@@ -73,17 +57,17 @@ impl TryFrom<[u8; 4]> for ChunkType {
 }
 
 impl FromStr for ChunkType {
-    type Err = ChunkTypeError;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let char_bytes = s.as_bytes();
         if char_bytes.len() != 4 {
-            return Err(ChunkTypeError::InvalidString(String::from("Invalid length")));
+            return Err(Error::InvalidChunkTypeStr(s.to_string()));
         }
 
         char_bytes.iter().try_for_each(|byte| {
             if !byte.is_ascii_alphabetic() {
-                Err(ChunkTypeError::InvalidString(String::from("Invalid Character")))
+                Err(Error::InvalidChunkTypeStr(s.to_string()))
             } else {
                 Ok(())
             }
@@ -187,6 +171,12 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_invalid_chunk_type_bytes() {
+        let chunk = ChunkType::try_from([82, 0, 83, 116]);
+        assert!(matches!(chunk, Err(Error::InvalidChunkType([82, 0, 83, 116]))));
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
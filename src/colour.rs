@@ -0,0 +1,562 @@
+use crate::{chunk::{u16_at, u32_at, Chunk}, chunk_type::ChunkType, limits::{self, Limits}, pixels::Header, png::Png, text};
+use flate2::{write::ZlibEncoder, Compression};
+use std::{fmt, io::Write, str::FromStr};
+
+// the chunks describing how the samples map to colours, all of them have to come before IDAT and all but
+// mDCV and cLLi before PLTE too
+pub const COLOUR_CHUNK_TYPES: [&str; 8] = ["gAMA", "cHRM", "sRGB", "iCCP", "sBIT", "cICP", "mDCV", "cLLi"];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ColourError {
+    Malformed { chunk: String, length: usize },
+    ZeroGamma,
+    InvalidIntent(u8),
+    InvalidProfileName,
+    UnsupportedCompression(u8),
+    CorruptProfile,
+    SignificantBits { bits: Vec<u8>, colour_type: u8, bit_depth: u8 },
+    Duplicate { chunk: String, index: usize },
+    Misplaced { chunk: String, index: usize, after: &'static str },
+    Conflict,
+    InvalidChromaticities(String),
+    UnsupportedMatrix(u8),
+    InvalidRangeFlag(u8),
+    InvalidLuminance { min: u32, max: u32 },
+}
+
+impl std::error::Error for ColourError {}
+
+impl fmt::Display for ColourError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColourError::Malformed { chunk, length } => write!(f, "{} chunk cannot hold {} bytes", chunk, length),
+            ColourError::ZeroGamma => write!(f, "gAMA chunk holds a gamma of 0"),
+            ColourError::InvalidIntent(intent) => write!(f, "Unknown sRGB rendering intent {}", intent),
+            ColourError::InvalidProfileName => write!(f, "ICC profile name must be 1 to 79 Latin-1 characters without leading, trailing or double spaces"),
+            ColourError::UnsupportedCompression(method) => write!(f, "Unknown ICC profile compression method {}", method),
+            ColourError::CorruptProfile => write!(f, "ICC profile is not a valid zlib stream"),
+            ColourError::SignificantBits { bits, colour_type, bit_depth } => {
+                write!(f, "Significant bits {:?} do not fit colour type {} at bit depth {}", bits, colour_type, bit_depth)
+            },
+            ColourError::Duplicate { chunk, index } => write!(f, "Chunk #{} is a second {} chunk", index, chunk),
+            ColourError::Misplaced { chunk, index, after } => write!(f, "{} chunk #{} comes after {}", chunk, index, after),
+            ColourError::Conflict => write!(f, "Image has both an sRGB and an iCCP chunk"),
+            ColourError::UnsupportedMatrix(matrix) => write!(f, "cICP matrix coefficients {} are not allowed, PNG samples are RGB (0)", matrix),
+            ColourError::InvalidRangeFlag(flag) => write!(f, "cICP full range flag is {} instead of 0 or 1", flag),
+            ColourError::InvalidLuminance { min, max } => {
+                write!(f, "mDCV minimum luminance {:.4} is above the maximum {:.4} cd/m²", luminance(*min), luminance(*max))
+            },
+            ColourError::InvalidChromaticities(s) => write!(f, "Chromaticities \"{}\" are not eight comma separated numbers from 0 to 1", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RenderingIntent {
+    Perceptual,
+    Relative,
+    Saturation,
+    Absolute,
+}
+
+impl fmt::Display for RenderingIntent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderingIntent::Perceptual => write!(f, "perceptual"),
+            RenderingIntent::Relative => write!(f, "relative colorimetric"),
+            RenderingIntent::Saturation => write!(f, "saturation"),
+            RenderingIntent::Absolute => write!(f, "absolute colorimetric"),
+        }
+    }
+}
+
+// a chromaticity or gamma as stored, times 100000
+fn fixed(value: u32) -> f64 {
+    value as f64 / 100_000.0
+}
+
+// mDCV and cLLi store luminance in 0.0001 cd/m² and mDCV chromaticities in steps of 0.00002
+fn luminance(value: u32) -> f64 {
+    value as f64 / 10_000.0
+}
+
+fn chromaticity(value: u16) -> f64 {
+    value as f64 * 0.00002
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+// white point, red, green and blue as x,y pairs, like "0.3127,0.329,0.64,0.33,0.3,0.6,0.15,0.06"
+impl FromStr for Chromaticities {
+    type Err = ColourError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f64>().ok().filter(|value| (0.0..=1.0).contains(value)))
+            .map(|value| value.map(|value| (value * 100_000.0).round() as u32))
+            .collect::<Option<Vec<u32>>>()
+            .filter(|values| values.len() == 8)
+            .ok_or_else(|| ColourError::InvalidChromaticities(s.to_string()))?;
+
+        let point = |i: usize| (values[i], values[i + 1]);
+        Ok(Chromaticities { white: point(0), red: point(2), green: point(4), blue: point(6) })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccProfile {
+    pub name: String,
+    // the profile uncompressed
+    pub profile: Vec<u8>,
+}
+
+impl IccProfile {
+    pub fn new(name: &str, profile: Vec<u8>) -> Result<IccProfile, ColourError> {
+        let latin1: Option<Vec<u8>> = name.chars().map(|c| u8::try_from(c).ok()).collect();
+        if !latin1.is_some_and(|name| text::is_keyword(&name)) {
+            return Err(ColourError::InvalidProfileName);
+        }
+        Ok(IccProfile { name: name.to_string(), profile })
+    }
+
+    // the data colour space from the profile header, like "RGB " or "GRAY"
+    pub fn colour_space(&self) -> Option<String> {
+        let space = std::str::from_utf8(self.profile.get(16..20)?).ok()?.trim_end();
+        (!space.is_empty() && space.chars().all(|c| c.is_ascii_alphanumeric())).then(|| space.to_string())
+    }
+}
+
+// cICP, the code points of ITU-T H.273 as video formats use them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodePoints {
+    pub primaries: u8,
+    pub transfer: u8,
+    // always 0 in a PNG file, its samples are RGB
+    pub matrix: u8,
+    pub full_range: bool,
+}
+
+fn primaries_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        1 => "BT.709",
+        4 => "BT.470 M",
+        5 => "BT.601 625",
+        6 => "BT.601 525",
+        9 => "BT.2020",
+        10 => "XYZ",
+        11 => "DCI-P3",
+        12 => "Display P3",
+        _ => return None,
+    })
+}
+
+fn transfer_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        1 | 6 | 14 | 15 => "BT.709",
+        4 => "gamma 2.2",
+        5 => "gamma 2.8",
+        8 => "linear",
+        13 => "sRGB",
+        16 => "PQ",
+        18 => "HLG",
+        _ => return None,
+    })
+}
+
+impl fmt::Display for CodePoints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match primaries_name(self.primaries) {
+            Some(name) => write!(f, "{} primaries", name)?,
+            None => write!(f, "primaries {}", self.primaries)?,
+        }
+        match transfer_name(self.transfer) {
+            Some(name) => write!(f, ", {} transfer", name)?,
+            None => write!(f, ", transfer {}", self.transfer)?,
+        }
+        write!(f, ", matrix {}, {} range", self.matrix, if self.full_range { "full" } else { "narrow" })
+    }
+}
+
+// mDCV, the colour volume of the display the image was mastered on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasteringDisplay {
+    // red, green and blue
+    pub primaries: [(u16, u16); 3],
+    pub white: (u16, u16),
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+// cLLi, the brightest pixel and the brightest frame average of the content, in 0.0001 cd/m²
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLightLevel {
+    pub max_content: u32,
+    pub max_frame_average: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Colour {
+    // gamma times 100000, 45455 for the usual 1/2.2
+    Gamma(u32),
+    Chromaticities(Chromaticities),
+    Srgb(RenderingIntent),
+    Icc(IccProfile),
+    // one value per channel of the colour type, the bits of each sample that carry information
+    SignificantBits(Vec<u8>),
+    CodePoints(CodePoints),
+    MasteringDisplay(MasteringDisplay),
+    ContentLightLevel(ContentLightLevel),
+}
+
+impl Colour {
+    // None for chunks of other types
+    pub fn parse(chunk: &Chunk, limits: &Limits) -> Option<Result<Colour, ColourError>> {
+        let data = chunk.data();
+        let malformed = || ColourError::Malformed { chunk: chunk.chunk_type().to_string(), length: data.len() };
+
+        Some(match &chunk.chunk_type().bytes() {
+            b"gAMA" if data.len() != 4 => Err(malformed()),
+            b"gAMA" => match u32_at(data, 0) {
+                0 => Err(ColourError::ZeroGamma),
+                gamma => Ok(Colour::Gamma(gamma)),
+            },
+            b"cHRM" if data.len() != 32 => Err(malformed()),
+            b"cHRM" => {
+                let point = |offset: usize| (u32_at(data, offset), u32_at(data, offset + 4));
+                Ok(Colour::Chromaticities(Chromaticities { white: point(0), red: point(8), green: point(16), blue: point(24) }))
+            },
+            b"sRGB" if data.len() != 1 => Err(malformed()),
+            b"sRGB" => match data[0] {
+                0 => Ok(Colour::Srgb(RenderingIntent::Perceptual)),
+                1 => Ok(Colour::Srgb(RenderingIntent::Relative)),
+                2 => Ok(Colour::Srgb(RenderingIntent::Saturation)),
+                3 => Ok(Colour::Srgb(RenderingIntent::Absolute)),
+                intent => Err(ColourError::InvalidIntent(intent)),
+            },
+            b"iCCP" => parse_profile(data, limits),
+            b"sBIT" if data.is_empty() || data.len() > 4 => Err(malformed()),
+            b"sBIT" => Ok(Colour::SignificantBits(data.to_vec())),
+            b"cICP" if data.len() != 4 => Err(malformed()),
+            b"cICP" => match (data[2], data[3]) {
+                (0, flag @ (0 | 1)) => Ok(Colour::CodePoints(CodePoints { primaries: data[0], transfer: data[1], matrix: 0, full_range: flag == 1 })),
+                (0, flag) => Err(ColourError::InvalidRangeFlag(flag)),
+                (matrix, _) => Err(ColourError::UnsupportedMatrix(matrix)),
+            },
+            b"mDCV" if data.len() != 24 => Err(malformed()),
+            b"mDCV" => {
+                let point = |offset: usize| (u16_at(data, offset), u16_at(data, offset + 2));
+                let (max_luminance, min_luminance) = (u32_at(data, 16), u32_at(data, 20));
+                if min_luminance > max_luminance {
+                    return Some(Err(ColourError::InvalidLuminance { min: min_luminance, max: max_luminance }));
+                }
+                Ok(Colour::MasteringDisplay(MasteringDisplay { primaries: [point(0), point(4), point(8)], white: point(12), max_luminance, min_luminance }))
+            },
+            b"cLLi" if data.len() != 8 => Err(malformed()),
+            b"cLLi" => Ok(Colour::ContentLightLevel(ContentLightLevel { max_content: u32_at(data, 0), max_frame_average: u32_at(data, 4) })),
+            _ => return None,
+        })
+    }
+
+    pub fn chunk_type(&self) -> &'static str {
+        match self {
+            Colour::Gamma(_) => "gAMA",
+            Colour::Chromaticities(_) => "cHRM",
+            Colour::Srgb(_) => "sRGB",
+            Colour::Icc(_) => "iCCP",
+            Colour::SignificantBits(_) => "sBIT",
+            Colour::CodePoints(_) => "cICP",
+            Colour::MasteringDisplay(_) => "mDCV",
+            Colour::ContentLightLevel(_) => "cLLi",
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = match self {
+            Colour::Gamma(gamma) => gamma.to_be_bytes().to_vec(),
+            Colour::Chromaticities(Chromaticities { white, red, green, blue }) => [white, red, green, blue]
+                .into_iter()
+                .flat_map(|&(x, y)| [x.to_be_bytes(), y.to_be_bytes()].concat())
+                .collect(),
+            Colour::Srgb(intent) => vec![*intent as u8],
+            Colour::Icc(IccProfile { name, profile }) => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(profile).unwrap();
+                let mut data: Vec<u8> = name.chars().map(|c| c as u8).collect();
+                data.extend([0, 0]);
+                data.extend(encoder.finish().unwrap());
+                data
+            },
+            Colour::SignificantBits(bits) => bits.clone(),
+            Colour::CodePoints(points) => vec![points.primaries, points.transfer, points.matrix, points.full_range as u8],
+            Colour::MasteringDisplay(MasteringDisplay { primaries, white, max_luminance, min_luminance }) => primaries
+                .iter()
+                .chain([white])
+                .flat_map(|&(x, y)| [x.to_be_bytes(), y.to_be_bytes()].concat())
+                .chain(max_luminance.to_be_bytes())
+                .chain(min_luminance.to_be_bytes())
+                .collect(),
+            Colour::ContentLightLevel(level) => [level.max_content.to_be_bytes(), level.max_frame_average.to_be_bytes()].concat(),
+        };
+        Chunk::new(ChunkType::from_str(self.chunk_type()).unwrap(), data)
+    }
+}
+
+fn parse_profile(data: &[u8], limits: &Limits) -> Result<Colour, ColourError> {
+    let nul = data.iter().position(|&byte| byte == 0).ok_or(ColourError::InvalidProfileName)?;
+    let (name, rest) = (&data[..nul], &data[nul + 1..]);
+    if !text::is_keyword(name) {
+        return Err(ColourError::InvalidProfileName);
+    }
+    let (&method, compressed) = rest.split_first().ok_or(ColourError::Malformed { chunk: "iCCP".to_string(), length: data.len() })?;
+    if method != 0 {
+        return Err(ColourError::UnsupportedCompression(method));
+    }
+
+    let profile = limits::decompress(compressed, limits).map_err(|_| ColourError::CorruptProfile)?;
+    Ok(Colour::Icc(IccProfile { name: text::latin1(name), profile }))
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Colour::Gamma(gamma) => write!(f, "gamma {:.5} (display exponent {:.2})", fixed(*gamma), 100_000.0 / *gamma as f64),
+            Colour::Chromaticities(Chromaticities { white, red, green, blue }) => {
+                let points: Vec<String> = [("white", white), ("red", red), ("green", green), ("blue", blue)]
+                    .iter()
+                    .map(|(name, (x, y))| format!("{} {:.5},{:.5}", name, fixed(*x), fixed(*y)))
+                    .collect();
+                write!(f, "{}", points.join(", "))
+            },
+            Colour::Srgb(intent) => write!(f, "{} rendering intent", intent),
+            Colour::Icc(profile) => {
+                write!(f, "ICC profile \"{}\", {} bytes", profile.name, profile.profile.len())?;
+                match profile.colour_space() {
+                    Some(space) => write!(f, ", {} colour space", space),
+                    None => Ok(()),
+                }
+            },
+            Colour::SignificantBits(bits) => {
+                let bits: Vec<String> = bits.iter().map(u8::to_string).collect();
+                write!(f, "significant bits {}", bits.join(","))
+            },
+            Colour::CodePoints(points) => write!(f, "{}", points),
+            Colour::MasteringDisplay(MasteringDisplay { primaries: [red, green, blue], white, max_luminance, min_luminance }) => {
+                for (name, (x, y)) in [("red", red), ("green", green), ("blue", blue), ("white", white)] {
+                    write!(f, "{} {:.5},{:.5}, ", name, chromaticity(*x), chromaticity(*y))?;
+                }
+                write!(f, "luminance {:.4} to {:.4} cd/m²", luminance(*min_luminance), luminance(*max_luminance))
+            },
+            Colour::ContentLightLevel(level) => {
+                write!(f, "max content light level {:.4} cd/m², max frame average {:.4} cd/m²", luminance(level.max_content), luminance(level.max_frame_average))
+            },
+        }
+    }
+}
+
+fn check_significant_bits(bits: &[u8], header: &Header) -> Result<(), ColourError> {
+    let (channels, depth) = match header.colour_type {
+        3 => (3, 8),
+        _ => (header.channels(), header.bit_depth),
+    };
+    if bits.len() != channels || bits.iter().any(|&bit| bit == 0 || bit > depth) {
+        return Err(ColourError::SignificantBits { bits: bits.to_vec(), colour_type: header.colour_type, bit_depth: header.bit_depth });
+    }
+    Ok(())
+}
+
+// every colour chunk of the file with its position, checked against the spec: one of each type at
+// most, never both sRGB and iCCP, all of them before IDAT and all but mDCV and cLLi before PLTE
+pub fn colours(png: &Png, limits: &Limits) -> crate::Result<Vec<(usize, Colour)>> {
+    let header = png.chunk_at(0).and_then(|chunk| Header::try_from(chunk).ok());
+    let mut found: Vec<(usize, Colour)> = Vec::new();
+    let (mut palette, mut image_data) = (false, false);
+
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        match &chunk.chunk_type().bytes() {
+            b"PLTE" => palette = true,
+            b"IDAT" => image_data = true,
+            _ => {},
+        }
+        let Some(colour) = Colour::parse(chunk, limits) else {
+            continue;
+        };
+        let colour = colour?;
+        let chunk = colour.chunk_type().to_string();
+
+        let may_follow_palette = matches!(colour, Colour::MasteringDisplay(_) | Colour::ContentLightLevel(_));
+        if image_data || (palette && !may_follow_palette) {
+            let after = if palette && !may_follow_palette { "PLTE" } else { "IDAT" };
+            return Err(ColourError::Misplaced { chunk, index, after }.into());
+        }
+        if found.iter().any(|(_, other)| other.chunk_type() == chunk) {
+            return Err(ColourError::Duplicate { chunk, index }.into());
+        }
+        if let (Colour::SignificantBits(bits), Some(header)) = (&colour, &header) {
+            check_significant_bits(bits, header)?;
+        }
+        found.push((index, colour));
+    }
+
+    let has = |code: &str| found.iter().any(|(_, colour)| colour.chunk_type() == code);
+    if has("sRGB") && has("iCCP") {
+        return Err(ColourError::Conflict.into());
+    }
+    Ok(found)
+}
+
+// removes every colour chunk, the removed ones are returned
+pub fn clear(png: &mut Png) -> Vec<Chunk> {
+    COLOUR_CHUNK_TYPES.iter().flat_map(|code| png.remove_all(code)).collect()
+}
+
+// `colour` takes the place of the chunk of its type, or goes in front of PLTE and IDAT; sRGB and iCCP
+// replace each other as a file may only carry one of them
+pub fn set(png: &mut Png, colour: &Colour) {
+    let rival = match colour {
+        Colour::Srgb(_) => Some("iCCP"),
+        Colour::Icc(_) => Some("sRGB"),
+        _ => None,
+    };
+    if let Some(rival) = rival {
+        png.remove_all(rival);
+    }
+
+    let chunk = colour.to_chunk();
+    if let Some((index, _)) = png.chunks_by_type(colour.chunk_type()).first() {
+        png.replace_at(*index, chunk);
+        return;
+    }
+    let position = png.chunks()
+        .iter()
+        .position(|chunk| matches!(&chunk.chunk_type().bytes(), b"PLTE" | b"IDAT"))
+        .unwrap_or(png.chunks().len().min(1));
+    png.insert_at(position, chunk);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, testing::{chunk, header, module_error, still}};
+
+    fn colour_error(png: &Png) -> ColourError {
+        module_error!(colours(png, &Limits::default()), Error::Colour)
+    }
+
+    fn round_trip(colour: Colour) {
+        assert_eq!(Colour::parse(&colour.to_chunk(), &Limits::default()), Some(Ok(colour)));
+    }
+
+    #[test]
+    fn test_round_trips() {
+        round_trip(Colour::Gamma(45455));
+        round_trip(Colour::Chromaticities(Chromaticities { white: (31270, 32900), red: (64000, 33000), green: (30000, 60000), blue: (15000, 6000) }));
+        round_trip(Colour::Srgb(RenderingIntent::Saturation));
+        round_trip(Colour::Icc(IccProfile { name: "Display P3".to_string(), profile: vec![7; 300] }));
+        round_trip(Colour::SignificantBits(vec![5, 6, 5]));
+        round_trip(Colour::Chromaticities("0.3127,0.329,0.64,0.33,0.3,0.6,0.15,0.06".parse().unwrap()));
+        assert!(matches!("0.3,0.3".parse::<Chromaticities>(), Err(ColourError::InvalidChromaticities(_))));
+        assert_eq!(Colour::parse(&chunk("tEXt", b"Title\0Dice"), &Limits::default()), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Colour::Gamma(45455).to_string(), "gamma 0.45455 (display exponent 2.20)");
+        assert_eq!(Colour::Srgb(RenderingIntent::Relative).to_string(), "relative colorimetric rendering intent");
+
+        let mut profile = vec![0; 20];
+        profile[16..20].copy_from_slice(b"RGB ");
+        assert_eq!(Colour::Icc(IccProfile { name: "sRGB".to_string(), profile }).to_string(), "ICC profile \"sRGB\", 20 bytes, RGB colour space");
+    }
+
+    #[test]
+    fn test_malformed_chunks() {
+        let parse = |code: &str, data: &[u8]| Colour::parse(&chunk(code, data), &Limits::default()).unwrap();
+
+        assert_eq!(parse("gAMA", &[0; 3]), Err(ColourError::Malformed { chunk: "gAMA".to_string(), length: 3 }));
+        assert_eq!(parse("gAMA", &[0; 4]), Err(ColourError::ZeroGamma));
+        assert_eq!(parse("sRGB", &[4]), Err(ColourError::InvalidIntent(4)));
+        assert_eq!(parse("iCCP", b" lead\0\0x"), Err(ColourError::InvalidProfileName));
+        assert_eq!(parse("iCCP", b"name\0\x01x"), Err(ColourError::UnsupportedCompression(1)));
+        assert_eq!(parse("iCCP", b"name\0\0not zlib"), Err(ColourError::CorruptProfile));
+        assert_eq!(IccProfile::new("double  space", Vec::new()), Err(ColourError::InvalidProfileName));
+        assert_eq!(IccProfile::new("\u{263a}", Vec::new()), Err(ColourError::InvalidProfileName));
+    }
+
+    #[test]
+    fn test_file_rules() {
+        let srgb = || Colour::Srgb(RenderingIntent::Perceptual).to_chunk();
+        let icc = || Colour::Icc(IccProfile { name: "P3".to_string(), profile: vec![1; 10] }).to_chunk();
+        let gamma = || Colour::Gamma(45455).to_chunk();
+
+        let found = colours(&still(header(2, 8), vec![gamma(), srgb()]), &Limits::default()).unwrap();
+        assert_eq!(found.iter().map(|(index, _)| *index).collect::<Vec<_>>(), [1, 2]);
+
+        assert_eq!(colour_error(&still(header(2, 8), vec![srgb(), icc()])), ColourError::Conflict);
+        assert_eq!(colour_error(&still(header(2, 8), vec![gamma(), gamma()])), ColourError::Duplicate { chunk: "gAMA".to_string(), index: 2 });
+        assert_eq!(colour_error(&still(header(2, 8), vec![chunk("PLTE", &[0; 3]), gamma()])), ColourError::Misplaced { chunk: "gAMA".to_string(), index: 2, after: "PLTE" });
+
+        let mut late = still(header(2, 8), vec![]);
+        late.append_chunk(srgb());
+        assert_eq!(colour_error(&late), ColourError::Misplaced { chunk: "sRGB".to_string(), index: 2, after: "IDAT" });
+    }
+
+    #[test]
+    fn test_hdr_chunks() {
+        let pq = CodePoints { primaries: 9, transfer: 16, matrix: 0, full_range: true };
+        round_trip(Colour::CodePoints(pq));
+        round_trip(Colour::MasteringDisplay(MasteringDisplay { primaries: [(35400, 14600), (8500, 39850), (6550, 2300)], white: (15635, 16450), max_luminance: 10_000_000, min_luminance: 50 }));
+        round_trip(Colour::ContentLightLevel(ContentLightLevel { max_content: 10_000_000, max_frame_average: 4_000_000 }));
+
+        assert_eq!(pq.to_string(), "BT.2020 primaries, PQ transfer, matrix 0, full range");
+        assert_eq!(Colour::ContentLightLevel(ContentLightLevel { max_content: 10_000_000, max_frame_average: 4_000_000 }).to_string(), "max content light level 1000.0000 cd/m², max frame average 400.0000 cd/m²");
+
+        let parse = |code: &str, data: &[u8]| Colour::parse(&chunk(code, data), &Limits::default()).unwrap();
+        assert_eq!(parse("cICP", &[9, 16, 1, 1]), Err(ColourError::UnsupportedMatrix(1)));
+        assert_eq!(parse("cICP", &[9, 16, 0, 2]), Err(ColourError::InvalidRangeFlag(2)));
+        let mut data = vec![0; 24];
+        data[23] = 1;
+        assert_eq!(parse("mDCV", &data), Err(ColourError::InvalidLuminance { min: 1, max: 0 }));
+    }
+
+    #[test]
+    fn test_hdr_ordering() {
+        let cicp = || Colour::CodePoints(CodePoints { primaries: 12, transfer: 13, matrix: 0, full_range: true }).to_chunk();
+        let clli = || Colour::ContentLightLevel(ContentLightLevel { max_content: 1, max_frame_average: 1 }).to_chunk();
+
+        // mDCV and cLLi may come after the palette, cICP may not
+        assert!(colours(&still(header(2, 8), vec![chunk("PLTE", &[0; 3]), clli()]), &Limits::default()).is_ok());
+        assert_eq!(colour_error(&still(header(2, 8), vec![chunk("PLTE", &[0; 3]), cicp()])), ColourError::Misplaced { chunk: "cICP".to_string(), index: 2, after: "PLTE" });
+
+        let mut late = still(header(2, 8), vec![cicp()]);
+        late.append_chunk(clli());
+        assert_eq!(colour_error(&late), ColourError::Misplaced { chunk: "cLLi".to_string(), index: 3, after: "IDAT" });
+    }
+
+    #[test]
+    fn test_significant_bits_against_header() {
+        assert!(colours(&still(header(2, 8), vec![chunk("sBIT", &[5, 6, 5])]), &Limits::default()).is_ok());
+        assert!(matches!(colour_error(&still(header(2, 8), vec![chunk("sBIT", &[5, 6])])), ColourError::SignificantBits { colour_type: 2, .. }));
+        assert!(matches!(colour_error(&still(header(2, 8), vec![chunk("sBIT", &[5, 9, 5])])), ColourError::SignificantBits { bit_depth: 8, .. }));
+    }
+
+    #[test]
+    fn test_set_and_clear() {
+        let mut png = still(header(2, 8), vec![Colour::Gamma(50000).to_chunk(), Colour::Srgb(RenderingIntent::Perceptual).to_chunk()]);
+
+        set(&mut png, &Colour::Gamma(45455));
+        set(&mut png, &Colour::Icc(IccProfile { name: "P3".to_string(), profile: vec![1; 10] }));
+        let found = colours(&png, &Limits::default()).unwrap();
+        assert_eq!(found.iter().map(|(_, colour)| colour.chunk_type()).collect::<Vec<_>>(), ["gAMA", "iCCP"]);
+        assert_eq!(found[0].1, Colour::Gamma(45455));
+
+        assert_eq!(clear(&mut png).len(), 2);
+        assert_eq!(png.chunks().len(), 3);
+    }
+}
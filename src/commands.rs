@@ -1,52 +1,686 @@
-use std::{fs, str::FromStr};
-use std::path::PathBuf;
-use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use std::{collections::HashSet, fs, io::{self, Read, Write}, str::FromStr};
+use std::path::{Path, PathBuf};
+use regex::bytes::Regex;
+use crate::{apng::{self, Animation, FrameControl}, atomic, batch::BatchError, colour::{self, Colour}, compare::{self, Comparison}, diff::{self, Change}, selector::Selector, chunk::Chunk, chunk_type::ChunkType, error::Error, fec::{self, FecParams}, frames, limits::Limits, lsb, palette::{self, PaletteChunk}, patch::{self, Patch}, payload::{self, Segment}, pixels::{Header, Image}, png::Png, policy::{PolicyError, PolicyFile, Violation}, seal::{self, SEAL_CHUNK_TYPE}, strip::{self, Policy}, text, unpack, watermark, shamir::{self, ShamirError, SHARE_CHUNK_TYPE}, volume::{self, VolumeError, VOLUME_CHUNK_TYPE}};
 
-pub fn encode(fpath: &PathBuf, chunkt_code: &String, message: String) -> crate::Result<()> {
-    let png = fs::read(fpath)?;
+// `-` in place of a path reads the image from stdin or writes it to stdout
+pub const STDIO: &str = "-";
 
-    let mut png = Png::try_from(png.as_slice())?;
-    let chunk_type = ChunkType::from_str(chunkt_code).unwrap();
-    let chunk = Chunk::new(chunk_type, message.into_bytes());
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO
+}
 
-    png.append_chunk(chunk);
-    fs::write(fpath, png.as_bytes())?;
+// where a modified image goes, back over its input unless an output is given
+#[derive(Debug, Clone, Default)]
+pub struct Save {
+    pub output: Option<PathBuf>,
+    pub backup: bool,
+}
 
-    Ok(())
+impl Save {
+    pub fn destination<'a>(&'a self, fpath: &'a PathBuf) -> &'a PathBuf {
+        self.output.as_ref().unwrap_or(fpath)
+    }
+
+    // status messages have to stay off stdout while the image itself is written there
+    pub fn writes_to_stdout(&self, fpath: &PathBuf) -> bool {
+        is_stdio(self.destination(fpath))
+    }
 }
 
-pub fn decode(fpath: &PathBuf, chunkt_code: &String) -> crate::Result<String> {
-    let png = fs::read(fpath)?;
-    let png = Png::try_from(png.as_slice())?;
+pub fn encode(fpath: &PathBuf, chunkt_code: &str, message: String, split_size: Option<usize>, fec_params: Option<FecParams>, limits: &Limits, save: &Save) -> crate::Result<usize> {
+    let mut png = load(fpath, limits)?;
+    let chunk_type = ChunkType::from_str(chunkt_code)?;
+
+    // messages too large for a single chunk get split even when no size was asked for
+    let split_size = split_size.or_else(|| {
+        (message.len() > payload::MAX_CHUNK_LENGTH).then_some(payload::MAX_CHUNK_LENGTH - Segment::HEADER_LENGTH)
+    });
+
+    let mut chunks = match split_size {
+        Some(size) => payload::split(&chunk_type, message.as_bytes(), size)?,
+        None => vec![Chunk::new(chunk_type.clone(), message.into_bytes())],
+    };
+
+    // error correction wraps each chunk on its own so a damaged chunk never spoils the others
+    if let Some(params) = fec_params {
+        chunks = chunks
+            .into_iter()
+            .map(|chunk| Ok(Chunk::new(chunk_type.clone(), fec::encode(chunk.data(), params)?)))
+            .collect::<crate::Result<Vec<Chunk>>>()?;
+    }
+    let count = chunks.len();
+
+    for chunk in chunks {
+        png.append_chunk(chunk);
+    }
+    write(fpath, &png, save)?;
+
+    Ok(count)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    First,
+    Index(usize),
+    All,
+    Matching(Selector),
+}
+
+pub struct Message {
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub text: String,
+    // symbol errors fixed by error correction, None when the chunk was not error coded
+    pub corrected: Option<usize>,
+}
+
+// a CRC mismatch is only forgiven when every damaged chunk is an error coded one we are decoding
+fn load_for_decode(fpath: &PathBuf, chunkt_code: Option<&str>, limits: &Limits) -> crate::Result<Png> {
+    let bytes = read(fpath, limits)?;
+
+    match Png::try_from_with_limits(bytes.as_slice(), limits) {
+        Ok(png) => Ok(png),
+        Err(err) if matches!(err.root(), Error::CrcMismatch { .. }) => {
+            let png = Png::try_from_unchecked(bytes.as_slice(), limits)?;
+            let recoverable = png.chunks()
+                .iter()
+                .filter(|chunk| !chunk.is_crc_valid())
+                .all(|chunk| is_of_type(chunk, chunkt_code) && fec::is_fec(chunk.data()));
+
+            if recoverable { Ok(png) } else { Err(err) }
+        },
+        Err(err) => Err(err),
+    }
+}
 
-    if let Some(chunk) = png.chunk_by_type(chunkt_code) {
-        let data = chunk.data_as_string()?;
-        Ok(data)
+// undoes error correction, the result may still be a segment of a larger payload
+fn unwrap_data(chunk: &Chunk) -> crate::Result<(Vec<u8>, Option<usize>)> {
+    if fec::is_fec(chunk.data()) {
+        let (data, count) = fec::decode(chunk.data())?;
+        Ok((data, Some(count)))
     } else {
-        Ok("Chunk doesn't exist".to_string())
+        Ok((chunk.data().to_vec(), None))
     }
 }
 
-pub fn remove(fpath: &PathBuf, chunkt_code: &String) -> crate::Result<Chunk> {
-    let png = fs::read(&fpath)?;
-    let mut png = Png::try_from(png.as_slice())?;
+fn chunk_message(index: usize, chunk: &Chunk) -> crate::Result<Message> {
+    let (mut data, corrected) = unwrap_data(chunk)?;
+    if Segment::is_segment(&data) {
+        data = Segment::try_from(data.as_slice())?.data().to_vec();
+    }
+
+    Ok(Message { index, chunk_type: chunk.chunk_type().clone(), text: String::from_utf8(data)?, corrected })
+}
 
-    if let None = png.chunk_by_type(chunkt_code) {
-        return Err("Chunk doesn't exist".into());
+fn is_of_type(chunk: &Chunk, chunkt_code: Option<&str>) -> bool {
+    chunkt_code.is_none_or(|code| chunk.chunk_type().to_string() == code)
+}
+
+// positions of the chunks a selection refers to, in file order, never empty
+fn selected(png: &Png, chunkt_code: Option<&str>, selection: &Selection) -> crate::Result<Vec<usize>> {
+    let indices: Vec<usize> = match selection {
+        Selection::First => png.chunks().iter().position(|chunk| is_of_type(chunk, chunkt_code)).into_iter().collect(),
+        Selection::Index(index) => match png.chunk_at(*index) {
+            Some(chunk) if is_of_type(chunk, chunkt_code) => vec![*index],
+            Some(chunk) => return Err(Error::WrongChunkType {
+                index: *index,
+                expected: chunkt_code.unwrap_or_default().to_string(),
+                found: chunk.chunk_type().clone(),
+            }),
+            None => return Err(Error::IndexOutOfRange { index: *index, count: png.chunks().len() }),
+        },
+        Selection::All => png.chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| is_of_type(chunk, chunkt_code))
+            .map(|(index, _)| index)
+            .collect(),
+        Selection::Matching(selector) => selector
+            .select(png)
+            .into_iter()
+            .filter(|(_, chunk)| is_of_type(chunk, chunkt_code))
+            .map(|(index, _)| index)
+            .collect(),
+    };
+
+    if indices.is_empty() {
+        return Err(Error::ChunkNotFound(chunkt_code.unwrap_or("matching the selection").to_string()));
     }
+    Ok(indices)
+}
+
+pub fn decode(fpath: &PathBuf, chunkt_code: Option<&str>, selection: &Selection, limits: &Limits) -> crate::Result<Vec<Message>> {
+    let png = load_for_decode(fpath, chunkt_code, limits)?;
+    let indices = selected(&png, chunkt_code, selection)?;
+
+    let (Selection::First, Some(code)) = (selection, chunkt_code) else {
+        return indices.into_iter().map(|index| chunk_message(index, &png.chunks()[index])).collect();
+    };
 
-    let removed_chunk = png.remove_first_chunk(chunkt_code).unwrap();
-    fs::write(fpath, png.as_bytes())?;
-    Ok(removed_chunk)
+    let mut corrected: Option<usize> = None;
+    let mut datas: Vec<Vec<u8>> = Vec::new();
+    for (_, chunk) in png.chunks_by_type(code) {
+        let (data, count) = unwrap_data(chunk)?;
+        if let Some(count) = count {
+            *corrected.get_or_insert(0) += count;
+        }
+        datas.push(data);
+    }
+
+    // segments of a split payload are put back together, otherwise the first chunk wins
+    let data = if datas.iter().any(|data| Segment::is_segment(data)) {
+        let segments = datas
+            .iter()
+            .filter(|data| Segment::is_segment(data))
+            .map(|data| Segment::try_from(data.as_slice()))
+            .collect::<Result<Vec<Segment>, _>>()?;
+        payload::join(segments)?
+    } else {
+        datas.swap_remove(0)
+    };
+
+    let chunk_type = png.chunks()[indices[0]].chunk_type().clone();
+    Ok(vec![Message { index: indices[0], chunk_type, text: String::from_utf8(data)?, corrected }])
+}
+
+// critical chunks are only removed when `force` is set, a selection like "*" would otherwise leave no image behind
+pub fn remove(fpath: &PathBuf, chunkt_code: Option<&str>, selection: &Selection, force: bool, limits: &Limits, save: &Save) -> crate::Result<Vec<Chunk>> {
+    let mut png = load(fpath, limits)?;
+    let indices = selected(&png, chunkt_code, selection)?;
+    if let Some(&index) = indices.iter().find(|&&index| !force && png.chunks()[index].chunk_type().is_critical()) {
+        return Err(Error::CriticalChunk { index, chunk_type: png.chunks()[index].chunk_type().clone() });
+    }
+
+    // back to front so the remaining positions stay valid
+    let mut removed: Vec<Chunk> = indices.into_iter().rev().filter_map(|index| png.remove_at(index)).collect();
+    removed.reverse();
+    // frames that lost their control or data go too, so an animation stays playable
+    removed.extend(apng::repair(&mut png).into_iter().map(|(_, chunk)| chunk));
+
+    write(fpath, &png, save)?;
+    Ok(removed)
 }
 
-pub fn print(fpath: &PathBuf) -> crate::Result<()> {
-    let png = fs::read(&fpath)?;
-    let png = Png::try_from(png.as_slice())?;
+pub fn replace(fpath: &PathBuf, chunkt_code: &str, message: String, selection: &Selection, limits: &Limits, save: &Save) -> crate::Result<Vec<usize>> {
+    let mut png = load(fpath, limits)?;
+    let chunk_type = ChunkType::from_str(chunkt_code)?;
+    let indices = selected(&png, Some(chunkt_code), selection)?;
 
-    for chunk in png.chunks() {
-        println!("{}", chunk);
+    for &index in &indices {
+        png.replace_at(index, Chunk::new(chunk_type.clone(), message.clone().into_bytes()));
     }
+    write(fpath, &png, save)?;
+
+    Ok(indices)
+}
+
+pub struct Stripped {
+    pub removed: Vec<(usize, Chunk)>,
+    pub size_before: usize,
+    pub size_after: usize,
+    // what stripping cannot fix, like a missing required chunk or an oversized critical chunk
+    pub violations: Vec<Violation>,
+}
+
+pub fn strip(fpath: &PathBuf, policy: &Policy, limits: &Limits, save: &Save) -> crate::Result<Stripped> {
+    let mut png = load(fpath, limits)?;
+    let size_before = png.as_bytes().len();
+
+    let removed = strip::strip(&mut png, policy, limits);
+    write(fpath, &png, save)?;
+
+    let violations = policy.rules.as_ref().map(|rules| rules.check(&png, limits)).unwrap_or_default();
+    Ok(Stripped { removed, size_before, size_after: png.as_bytes().len(), violations })
+}
+
+pub fn diff(old_path: &PathBuf, new_path: &PathBuf, limits: &Limits) -> crate::Result<Vec<Change>> {
+    let old = load(old_path, limits)?;
+    let new = load(new_path, limits)?;
+    Ok(diff::diff(&old, &new, limits))
+}
+
+// the difference image is only written when the pixels differ
+pub fn compare(first_path: &PathBuf, second_path: &PathBuf, output: Option<&PathBuf>, limits: &Limits, backup: bool) -> crate::Result<Comparison> {
+    let first = Image::decode(&load(first_path, limits)?, limits)?;
+    let second = Image::decode(&load(second_path, limits)?, limits)?;
+    let comparison = compare::compare(&first, &second);
+
+    if let Some(path) = output.filter(|_| comparison.differing() > 0) {
+        let save = Save { output: Some(path.clone()), backup };
+        write(path, &compare::difference(&first, &second, limits)?.to_png(), &save)?;
+    }
+    Ok(comparison)
+}
+
+// every copy is made before any is written, so an image that cannot carry a watermark leaves nothing behind
+pub fn watermark(fpath: &PathBuf, recipients: &[String], key: &str, out_dir: &Path, limits: &Limits, backup: bool) -> crate::Result<Vec<(String, PathBuf)>> {
+    let png = load(fpath, limits)?;
+    let stem = fpath.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "image".to_string());
+    let names = watermark::copy_names(&stem, recipients)?;
+    let copies = recipients
+        .iter()
+        .map(|recipient| watermark::embed(&png, key, recipient, limits))
+        .collect::<crate::Result<Vec<Png>>>()?;
+
+    fs::create_dir_all(out_dir)?;
+    recipients
+        .iter()
+        .zip(names.iter().zip(copies))
+        .map(|(recipient, (name, copy))| {
+            let path = out_dir.join(name);
+            atomic::write(&path, &copy.as_bytes(), backup)?;
+            Ok((recipient.clone(), path))
+        })
+        .collect()
+}
+
+pub fn identify(fpath: &PathBuf, recipients: &[String], key: &str, limits: &Limits) -> crate::Result<(String, u32, f64)> {
+    let png = load(fpath, limits)?;
+    let (recipient, distance, agreement) = watermark::identify(&png, key, recipients, limits)?;
+    Ok((recipient.to_string(), distance, agreement))
+}
+
+// stores the message in the pixels rather than a chunk, returning the bytes used and the bytes the pixels hold
+pub fn hide(fpath: &PathBuf, message: &str, fec_params: Option<FecParams>, limits: &Limits, save: &Save) -> crate::Result<(usize, usize)> {
+    let png = load(fpath, limits)?;
+    let data = match fec_params {
+        Some(params) => fec::encode(message.as_bytes(), params)?,
+        None => message.as_bytes().to_vec(),
+    };
+    let (hidden, used) = lsb::embed(&png, &data, limits)?;
+    write(fpath, &hidden, save)?;
+    Ok((used, lsb::capacity(&hidden, limits)?))
+}
+
+// the message hide stored, with the number of bytes error correction had to fix when it was protected
+pub fn reveal(fpath: &PathBuf, limits: &Limits) -> crate::Result<(String, Option<usize>)> {
+    let png = load(fpath, limits)?;
+    let data = lsb::read(&png, limits)?;
+    let (data, corrected) = match fec::is_fec(&data) {
+        true => fec::decode(&data).map(|(data, corrected)| (data, Some(corrected)))?,
+        false => (data, None),
+    };
+    Ok((String::from_utf8(data)?, corrected))
+}
+
+// the patch goes where `save` sends it, "-" being stdout
+pub fn patch_create(base_path: &PathBuf, new_path: &PathBuf, limits: &Limits, save: &Save) -> crate::Result<Patch> {
+    let base = load(base_path, limits)?;
+    let new = load(new_path, limits)?;
+    let patch = patch::create(&base, &new);
+
+    let destination = save.destination(base_path);
+    if is_stdio(destination) {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&patch.as_bytes())?;
+        stdout.flush()?;
+    } else {
+        atomic::write(destination, &patch.as_bytes(), save.backup)?;
+    }
+    Ok(patch)
+}
+
+// returns the number of chunks inserted, deleted and replaced
+pub fn patch_apply(fpath: &PathBuf, patch_path: &PathBuf, limits: &Limits, save: &Save) -> crate::Result<(usize, usize, usize)> {
+    let png = load(fpath, limits)?;
+    limits.check_total_size(fs::metadata(patch_path)?.len())?;
+    let patch = Patch::try_from_with_limits(&fs::read(patch_path)?, limits)?;
+
+    let patched = patch.apply(&png)?;
+    limits.check_chunk_count(patched.chunks().len())?;
+    write(fpath, &patched, save)?;
+    Ok(patch.counts())
+}
+
+// writes the data of every matching chunk to `NNN-TYPE.bin` inside `out_dir`
+pub fn extract(fpath: &PathBuf, selector: &Selector, out_dir: &PathBuf, limits: &Limits) -> crate::Result<Vec<PathBuf>> {
+    let png = load(fpath, limits)?;
+    fs::create_dir_all(out_dir)?;
+
+    selector
+        .select(&png)
+        .into_iter()
+        .map(|(index, chunk)| {
+            let path = out_dir.join(unpack::file_name(index, chunk));
+            fs::write(&path, chunk.data())?;
+            Ok(path)
+        })
+        .collect()
+}
+
+// every frame as shown goes to `frame-NNN.png` inside `dir`, returned with its frame control
+pub fn frames_extract(fpath: &PathBuf, dir: &Path, limits: &Limits) -> crate::Result<Vec<(PathBuf, FrameControl)>> {
+    let png = load(fpath, limits)?;
+    let animation = Animation::parse(&png)?.ok_or_else(|| Error::ChunkNotFound("acTL".to_string()))?;
+    let images = frames::render(&png, &animation, limits)?;
+
+    fs::create_dir_all(dir)?;
+    images
+        .iter()
+        .zip(&animation.frames)
+        .enumerate()
+        .map(|(number, (image, frame))| {
+            let path = dir.join(format!("frame-{:03}.png", number));
+            fs::write(&path, image.to_png().as_bytes())?;
+            Ok((path, frame.control))
+        })
+        .collect()
+}
+
+// the animation goes where `save` sends it
+pub fn frames_build(fpaths: &[PathBuf], delays: &[u16], plays: u32, limits: &Limits, save: &Save) -> crate::Result<Png> {
+    let images = fpaths
+        .iter()
+        .map(|fpath| Image::decode(&load(fpath, limits)?, limits))
+        .collect::<crate::Result<Vec<Image>>>()?;
+
+    let png = frames::build(&images, delays, plays)?;
+    write(&fpaths[0], &png, save)?;
+    Ok(png)
+}
+
+// returns the number of chunks written
+pub fn unpack(fpath: &PathBuf, dir: &Path, limits: &Limits) -> crate::Result<usize> {
+    let png = load(fpath, limits)?;
+    Ok(unpack::unpack(&png, dir)?.chunks.len())
+}
 
+// the image goes where `save` sends it, the chunks edited since unpacking are reported back
+pub fn pack(dir: &PathBuf, limits: &Limits, save: &Save) -> crate::Result<Vec<unpack::Edited>> {
+    let (png, edited) = unpack::pack(dir, limits)?;
+    write(dir, &png, save)?;
+    Ok(edited)
+}
+
+// chunks pngme understands show their decoded contents in place of the raw data
+fn describe(chunk: &Chunk, header: Option<&Header>, limits: &Limits) -> String {
+    if let Some(Ok(colour)) = Colour::parse(chunk, limits) {
+        return chunk.display_with(&colour.to_string());
+    }
+    match header.and_then(|header| PaletteChunk::parse(chunk, header)) {
+        Some(Ok(parsed)) => chunk.display_with(&parsed.to_string()),
+        _ => chunk.to_string(),
+    }
+}
+
+pub fn print(fpath: &PathBuf, selector: Option<&Selector>, limits: &Limits) -> crate::Result<Vec<String>> {
+    let png = load(fpath, limits)?;
+    // tRNS and bKGD can only be read knowing the colour type
+    let header = png.chunk_at(0).and_then(|chunk| Header::try_from(chunk).ok());
+
+    Ok(png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| selector.is_none_or(|selector| selector.matches(&png, *index)))
+        .map(|(index, chunk)| format!("#{}\n{}", index, describe(chunk, header.as_ref(), limits)))
+        .collect())
+}
+
+// `changes` replace the chunks of their type after `clear` removed every colour chunk, the image is only
+// written when something changed and the result follows the spec; the colour chunks of the image are returned
+pub fn colour(fpath: &PathBuf, clear: bool, changes: &[Colour], extract_icc: Option<&PathBuf>, limits: &Limits, save: &Save) -> crate::Result<Vec<(usize, Colour)>> {
+    let mut png = load(fpath, limits)?;
+
+    if let Some(path) = extract_icc {
+        let found = colour::colours(&png, limits)?;
+        let profile = found
+            .iter()
+            .find_map(|(_, colour)| match colour {
+                Colour::Icc(profile) => Some(profile),
+                _ => None,
+            })
+            .ok_or_else(|| Error::ChunkNotFound("iCCP".to_string()))?;
+        fs::write(path, &profile.profile)?;
+    }
+
+    if clear || !changes.is_empty() {
+        if clear {
+            colour::clear(&mut png);
+        }
+        for change in changes {
+            colour::set(&mut png, change);
+        }
+        colour::colours(&png, limits)?;
+        write(fpath, &png, save)?;
+    }
+    colour::colours(&png, limits)
+}
+
+// a full parse already checks the signature, every chunk CRC and the limits, the chunk count is reported back
+pub fn validate(fpath: &PathBuf, rules: Option<&PolicyFile>, limits: &Limits) -> crate::Result<usize> {
+    let png = load(fpath, limits)?;
+    Animation::parse(&png)?;
+    colour::colours(&png, limits)?;
+    palette::palette_chunks(&png)?;
+
+    let violations = rules.map(|rules| rules.check(&png, limits)).unwrap_or_default();
+    if !violations.is_empty() {
+        return Err(PolicyError::Violated(violations).into());
+    }
+    Ok(png.chunks().len())
+}
+
+// an existing seal is replaced
+pub fn seal(fpath: &PathBuf, limits: &Limits, save: &Save) -> crate::Result<()> {
+    let mut png = load(fpath, limits)?;
+    seal::seal(&mut png, limits)?;
+    write(fpath, &png, save)
+}
+
+pub fn check(fpath: &PathBuf, limits: &Limits) -> crate::Result<()> {
+    let png = load(fpath, limits)?;
+    let seal = seal::find(&png).ok_or_else(|| Error::ChunkNotFound(SEAL_CHUNK_TYPE.to_string()))??;
+    seal.check(&png, limits)
+}
+
+pub struct GrepMatch {
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    // set for text chunks, whose inflated text is searched instead of the raw data
+    pub keyword: Option<String>,
+    pub snippet: String,
+}
+
+const SNIPPET_CONTEXT: usize = 24;
+
+// the match with a little of what surrounds it, control characters and invalid UTF-8 escaped
+fn snippet(haystack: &[u8], start: usize, end: usize) -> String {
+    let from = start.saturating_sub(SNIPPET_CONTEXT);
+    let to = end.saturating_add(SNIPPET_CONTEXT).min(haystack.len());
+
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push_str("...");
+    }
+    for c in String::from_utf8_lossy(&haystack[from..to]).chars() {
+        if c.is_control() {
+            snippet.extend(c.escape_default());
+        } else {
+            snippet.push(c);
+        }
+    }
+    if to < haystack.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+// searches the data of ancillary chunks, hidden messages are searched after undoing error correction
+// and segmentation so a message split across chunks still matches within each part
+pub fn grep(fpath: &PathBuf, pattern: &Regex, private_only: bool, limits: &Limits) -> crate::Result<Vec<GrepMatch>> {
+    let png = load(fpath, limits)?;
+    let mut matches = Vec::new();
+
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        if chunk_type.is_critical() || (private_only && chunk_type.is_public()) {
+            continue;
+        }
+
+        let (keyword, haystack) = match text::decode(chunk, limits) {
+            Some(text) => (Some(text.keyword), text.text.into_bytes()),
+            None => {
+                let data = unwrap_data(chunk).map_or_else(|_| chunk.data().to_vec(), |(data, _)| data);
+                match Segment::try_from(data.as_slice()) {
+                    Ok(segment) => (None, segment.data().to_vec()),
+                    Err(_) => (None, data),
+                }
+            },
+        };
+
+        for found in pattern.find_iter(&haystack) {
+            matches.push(GrepMatch {
+                index,
+                chunk_type: chunk_type.clone(),
+                keyword: keyword.clone(),
+                snippet: snippet(&haystack, found.start(), found.end()),
+            });
+        }
+    }
+
+    Ok(matches)
+}
+
+// the size is checked against the file metadata so an oversized file is never read into memory,
+// stdin has no size up front so reading stops one byte past the limit instead
+fn read(fpath: &PathBuf, limits: &Limits) -> crate::Result<Vec<u8>> {
+    if is_stdio(fpath) {
+        let mut bytes = Vec::new();
+        io::stdin().lock().take((limits.max_total_size as u64).saturating_add(1)).read_to_end(&mut bytes)?;
+        limits.check_total_size(bytes.len() as u64)?;
+        return Ok(bytes);
+    }
+
+    limits.check_total_size(fs::metadata(fpath)?.len())?;
+    Ok(fs::read(fpath)?)
+}
+
+fn write(fpath: &PathBuf, png: &Png, save: &Save) -> crate::Result<()> {
+    let destination = save.destination(fpath);
+    if is_stdio(destination) {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&png.as_bytes())?;
+        stdout.flush()?;
+    } else {
+        atomic::write(destination, &png.as_bytes(), save.backup)?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+fn load(fpath: &PathBuf, limits: &Limits) -> crate::Result<Png> {
+    Png::try_from_with_limits(read(fpath, limits)?.as_slice(), limits)
+}
+
+// replaces the single container chunk of `chunk_type` in each carrier, carriers are all parsed
+// before any of them is written so a bad file leaves the whole set untouched. A carrier given twice
+// would only keep the part written last, so it is refused whatever path spells it
+fn distribute(carriers: &[PathBuf], chunk_type: &str, chunks: Vec<Chunk>, limits: &Limits, backup: bool) -> crate::Result<()> {
+    let mut seen = HashSet::new();
+    for path in carriers {
+        if !seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())) {
+            return Err(BatchError::RepeatedFile(path.clone()).into());
+        }
+    }
+
+    let mut pngs = carriers.iter().map(|path| load(path, limits)).collect::<crate::Result<Vec<Png>>>()?;
+
+    for ((path, png), chunk) in carriers.iter().zip(pngs.iter_mut()).zip(chunks) {
+        png.remove_all(chunk_type);
+        png.append_chunk(chunk);
+        atomic::write(path, &png.as_bytes(), backup)?;
+    }
+
+    Ok(())
+}
+
+pub fn split(payload_path: &PathBuf, carriers: &[PathBuf], limits: &Limits, backup: bool) -> crate::Result<u64> {
+    let payload = fs::read(payload_path)?;
+    let volumes = volume::split(&payload, carriers.len())?;
+    let id = volumes[0].id();
+
+    distribute(carriers, VOLUME_CHUNK_TYPE, volumes.iter().map(|volume| volume.to_chunk()).collect(), limits, backup)?;
+    Ok(id)
+}
+
+pub fn join(fpaths: &[PathBuf], limits: &Limits) -> crate::Result<Vec<u8>> {
+    let volumes = fpaths
+        .iter()
+        .enumerate()
+        .map(|(index, path)| Ok(volume::find(&load(path, limits)?).ok_or(VolumeError::MissingVolume(index))??))
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    Ok(volume::join(volumes)?)
+}
+
+pub fn share(secret_path: &PathBuf, carriers: &[PathBuf], threshold: usize, limits: &Limits, backup: bool) -> crate::Result<u64> {
+    let secret = fs::read(secret_path)?;
+    let shares = shamir::split(&secret, threshold, carriers.len())?;
+    let set_id = shares[0].set_id();
+
+    distribute(carriers, SHARE_CHUNK_TYPE, shares.iter().map(|share| share.to_chunk()).collect(), limits, backup)?;
+    Ok(set_id)
+}
+
+pub fn combine(fpaths: &[PathBuf], limits: &Limits) -> crate::Result<Vec<u8>> {
+    let shares = fpaths
+        .iter()
+        .enumerate()
+        .map(|(index, path)| Ok(shamir::find(&load(path, limits)?).ok_or(ShamirError::MissingShare(index))??))
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    Ok(shamir::combine(&shares)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // a copy of the dice image in a fresh directory, with a payload file next to it
+    fn carrier() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let image = dir.path().join("a.png");
+        fs::copy("data/dice.png", &image).unwrap();
+        let payload = dir.path().join("payload.bin");
+        fs::write(&payload, b"twice is once too many").unwrap();
+        (dir, image, payload)
+    }
+
+    fn is_repeated(result: crate::Result<u64>) -> bool {
+        matches!(result, Err(Error::Batch(BatchError::RepeatedFile(_))))
+    }
+
+    #[test]
+    fn test_repeated_carriers_are_refused() {
+        let (dir, image, payload) = carrier();
+        let original = fs::read(&image).unwrap();
+        let limits = Limits::default();
+
+        assert!(is_repeated(split(&payload, &[image.clone(), image.clone()], &limits, false)));
+        // the same file spelled differently
+        let respelled = dir.path().join(".").join("a.png");
+        assert!(is_repeated(share(&payload, &[image.clone(), respelled], 2, &limits, false)));
+        assert_eq!(fs::read(&image).unwrap(), original);
+    }
+
+    #[test]
+    fn test_remove_keeps_critical_chunks_unless_forced() {
+        let (_dir, image, _) = carrier();
+        let original = fs::read(&image).unwrap();
+        let (limits, save) = (Limits::default(), Save::default());
+        let everything = Selection::Matching(Selector::from_str("*").unwrap());
+
+        let refused = remove(&image, None, &everything, false, &limits, &save);
+        assert!(matches!(refused, Err(Error::CriticalChunk { index: 0, .. })));
+        assert_eq!(fs::read(&image).unwrap(), original);
+
+        let ancillary = Selection::Matching(Selector::from_str("* & ancillary").unwrap());
+        let removed = remove(&image, None, &ancillary, false, &limits, &save).unwrap();
+        assert_eq!(removed.iter().map(|chunk| chunk.chunk_type().to_string()).collect::<Vec<_>>(), ["gGit"]);
+        assert_eq!(load(&image, &limits).unwrap().chunks().len(), 3);
+
+        assert_eq!(remove(&image, None, &everything, true, &limits, &save).unwrap().len(), 3);
+        assert!(load(&image, &limits).unwrap().chunks().is_empty());
+    }
+}
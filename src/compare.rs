@@ -0,0 +1,179 @@
+use crate::{limits::Limits, pixels::Image};
+use std::fmt;
+
+const MARK: [u16; 4] = [0xFFFF, 0, 0, 0xFFFF];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Comparison {
+    Identical { width: u32, height: u32 },
+    SizeMismatch { first: (u32, u32), second: (u32, u32) },
+    Different {
+        differing: usize,
+        total: usize,
+        // first and last differing column and row
+        bounds: ((u32, u32), (u32, u32)),
+        // largest and average difference of a channel, in 16 bit units
+        max_error: u16,
+        mean_error: f64,
+    },
+}
+
+impl Comparison {
+    // pixels that are not the same, every pixel of the larger image when the sizes do not match
+    pub fn differing(&self) -> usize {
+        match self {
+            Comparison::Identical { .. } => 0,
+            Comparison::SizeMismatch { first: (w1, h1), second: (w2, h2) } => (*w1 as usize * *h1 as usize).max(*w2 as usize * *h2 as usize),
+            Comparison::Different { differing, .. } => *differing,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Comparison::Identical { width, height } => write!(f, "Images are pixel-identical ({}x{})", width, height),
+            Comparison::SizeMismatch { first, second } => write!(f, "Images differ in size: {}x{} and {}x{}", first.0, first.1, second.0, second.1),
+            Comparison::Different { differing, total, bounds: ((left, top), (right, bottom)), max_error, mean_error } => {
+                writeln!(f, "Images differ in {} of {} pixels ({:.2}%)", differing, total, *differing as f64 * 100.0 / *total as f64)?;
+                writeln!(f, "Differences within {},{} -> {},{} ({}x{})", left, top, right, bottom, right - left + 1, bottom - top + 1)?;
+                write!(
+                    f, "Max error {} ({:.2}%), mean error {:.2} ({:.4}%)",
+                    max_error, *max_error as f64 * 100.0 / 65535.0, mean_error, mean_error * 100.0 / 65535.0,
+                )
+            },
+        }
+    }
+}
+
+// fully transparent pixels look the same whatever colour they carry, so they only count as different
+// when one of them is not transparent
+fn channel_errors(a: &[u16; 4], b: &[u16; 4]) -> [u16; 4] {
+    if a[3] == 0 && b[3] == 0 {
+        return [0; 4];
+    }
+    [0, 1, 2, 3].map(|channel| a[channel].abs_diff(b[channel]))
+}
+
+pub fn compare(first: &Image, second: &Image) -> Comparison {
+    if (first.width, first.height) != (second.width, second.height) {
+        return Comparison::SizeMismatch { first: (first.width, first.height), second: (second.width, second.height) };
+    }
+
+    let mut differing = 0;
+    let mut bounds = ((u32::MAX, u32::MAX), (0, 0));
+    let (mut max_error, mut error_sum) = (0u16, 0u64);
+
+    for (index, (a, b)) in first.pixels.iter().zip(&second.pixels).enumerate() {
+        let errors = channel_errors(a, b);
+        if errors == [0; 4] {
+            continue;
+        }
+
+        let (x, y) = ((index % first.width as usize) as u32, (index / first.width as usize) as u32);
+        differing += 1;
+        bounds = ((bounds.0.0.min(x), bounds.0.1.min(y)), (bounds.1.0.max(x), bounds.1.1.max(y)));
+        max_error = max_error.max(*errors.iter().max().unwrap());
+        error_sum += errors.iter().map(|&error| error as u64).sum::<u64>();
+    }
+
+    if differing == 0 {
+        return Comparison::Identical { width: first.width, height: first.height };
+    }
+    Comparison::Different {
+        differing,
+        total: first.pixels.len(),
+        bounds,
+        max_error,
+        mean_error: error_sum as f64 / (first.pixels.len() * 4) as f64,
+    }
+}
+
+// the first image faded to light grey with every differing pixel in red, over the area of both images; that
+// area can be far larger than either image, a 1xN and an Nx1 image need NxN pixels
+pub fn difference(first: &Image, second: &Image, limits: &Limits) -> crate::Result<Image> {
+    let (width, height) = (first.width.max(second.width), first.height.max(second.height));
+    limits.check_decoded_size(width as u64 * height as u64 * 8)?;
+    let pixel = |image: &Image, x: u32, y: u32| (x < image.width && y < image.height).then(|| image.pixels[(y * image.width + x) as usize]);
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(match (pixel(first, x, y), pixel(second, x, y)) {
+                (Some(a), Some(b)) if channel_errors(&a, &b) == [0; 4] => {
+                    // luma over a white background, squeezed into the top quarter of the range
+                    let luma = (a[0] as u64 * 2126 + a[1] as u64 * 7152 + a[2] as u64 * 722) / 10000;
+                    let over_white = (luma * a[3] as u64 + 0xFFFF * (0xFFFF - a[3] as u64)) / 0xFFFF;
+                    let grey = 0xC000 + (over_white / 4) as u16;
+                    [grey, grey, grey, 0xFFFF]
+                },
+                _ => MARK,
+            });
+        }
+    }
+
+    Ok(Image { width, height, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn image(width: u32, height: u32, pixels: Vec<[u16; 4]>) -> Image {
+        Image { width, height, pixels }
+    }
+
+    #[test]
+    fn test_identical() {
+        let a = image(2, 1, vec![[1, 2, 3, 0xFFFF], [0, 0, 0, 0xFFFF]]);
+        assert_eq!(compare(&a, &a), Comparison::Identical { width: 2, height: 1 });
+        assert_eq!(compare(&a, &a).differing(), 0);
+    }
+
+    #[test]
+    fn test_transparent_colours_do_not_count() {
+        let a = image(1, 1, vec![[0xFFFF, 0, 0, 0]]);
+        let b = image(1, 1, vec![[0, 0, 0xFFFF, 0]]);
+        assert!(matches!(compare(&a, &b), Comparison::Identical { .. }));
+    }
+
+    #[test]
+    fn test_different() {
+        let a = image(3, 3, vec![[0, 0, 0, 0xFFFF]; 9]);
+        let mut b = a.clone();
+        b.pixels[4] = [0xFFFF, 0, 0, 0xFFFF];
+        b.pixels[5] = [0, 100, 0, 0xFFFF];
+
+        let comparison = compare(&a, &b);
+        let Comparison::Different { differing: 2, total: 9, bounds: ((1, 1), (2, 1)), max_error: 0xFFFF, mean_error } = comparison else {
+            panic!("unexpected comparison {:?}", comparison);
+        };
+        assert!((mean_error - (0xFFFF + 100) as f64 / 36.0).abs() < 1e-9);
+        assert!(comparison.to_string().starts_with("Images differ in 2 of 9 pixels (22.22%)\nDifferences within 1,1 -> 2,1 (2x1)"));
+
+        let highlighted = difference(&a, &b, &Limits::default()).unwrap();
+        assert_eq!(highlighted.pixels[4], MARK);
+        assert_eq!(highlighted.pixels[0], [0xC000, 0xC000, 0xC000, 0xFFFF]);
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let a = image(2, 1, vec![[0; 4]; 2]);
+        let b = image(1, 3, vec![[0; 4]; 3]);
+
+        assert_eq!(compare(&a, &b).differing(), 3);
+        let highlighted = difference(&a, &b, &Limits::default()).unwrap();
+        assert_eq!((highlighted.width, highlighted.height), (2, 3));
+        assert_eq!(highlighted.pixels[1], MARK);
+    }
+
+    #[test]
+    fn test_difference_within_limits() {
+        let tall = image(1, 1000, vec![[0; 4]; 1000]);
+        let wide = image(1000, 1, vec![[0; 4]; 1000]);
+        let limits = Limits { max_decompressed_size: 100_000, ..Limits::default() };
+
+        assert!(matches!(difference(&tall, &wide, &limits), Err(Error::LimitExceeded { limit: "decoded size", value: 8_000_000, .. })));
+    }
+}
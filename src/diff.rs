@@ -0,0 +1,258 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, limits::Limits, png::Png, text};
+use std::{collections::HashMap, fmt};
+
+// line diffs are skipped past this many line comparisons, the sizes and CRCs still tell what changed
+const MAX_TEXT_DIFF_CELLS: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Removed { index: usize, chunk_type: ChunkType, length: u32 },
+    Added { index: usize, chunk_type: ChunkType, length: u32 },
+    Moved { from: usize, to: usize, chunk_type: ChunkType },
+    Modified {
+        from: usize,
+        to: usize,
+        chunk_type: ChunkType,
+        lengths: (u32, u32),
+        crcs: (u32, u32),
+        // only for chunks holding text on both sides
+        lines: Option<Vec<Line>>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Removed { index, chunk_type, length } => write!(f, "- #{} {} removed ({} bytes)", index, chunk_type, length),
+            Change::Added { index, chunk_type, length } => write!(f, "+ #{} {} added ({} bytes)", index, chunk_type, length),
+            Change::Moved { from, to, chunk_type } => write!(f, "~ #{} -> #{} {} moved", from, to, chunk_type),
+            Change::Modified { from, to, chunk_type, lengths: (before, after), crcs: (old_crc, new_crc), lines } => {
+                write!(
+                    f, "* #{} -> #{} {} modified: {} -> {} bytes ({:+}), CRC {:#010x} -> {:#010x}",
+                    from, to, chunk_type, before, after, *after as i64 - *before as i64, old_crc, new_crc,
+                )?;
+                for line in lines.iter().flatten() {
+                    match line {
+                        Line::Same(_) => {},
+                        Line::Removed(line) => write!(f, "\n    - {}", line)?,
+                        Line::Added(line) => write!(f, "\n    + {}", line)?,
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// chunks are paired by type, text chunks by keyword as well, the n-th of a kind in one file with the n-th in the other
+fn keys(png: &Png, limits: &Limits) -> Vec<(String, Option<String>, usize)> {
+    let mut seen: HashMap<(String, Option<String>), usize> = HashMap::new();
+
+    png.chunks()
+        .iter()
+        .map(|chunk| {
+            let kind = (chunk.chunk_type().to_string(), text::decode(chunk, limits).map(|text| text.keyword));
+            let occurrence = seen.entry(kind.clone()).or_default();
+            *occurrence += 1;
+            (kind.0, kind.1, *occurrence)
+        })
+        .collect()
+}
+
+// positions in `values` of a longest strictly increasing subsequence
+pub fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // tails[k] is the position ending the best subsequence of length k + 1 found so far
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; values.len()];
+
+    for (position, &value) in values.iter().enumerate() {
+        let length = tails.partition_point(|&tail| values[tail] < value);
+        previous[position] = length.checked_sub(1).map(|before| tails[before]);
+        if length == tails.len() {
+            tails.push(position);
+        } else {
+            tails[length] = position;
+        }
+    }
+
+    let mut kept = Vec::with_capacity(tails.len());
+    let mut position = tails.last().copied();
+    while let Some(current) = position {
+        kept.push(current);
+        position = previous[current];
+    }
+    kept.reverse();
+    kept
+}
+
+fn text_of(chunk: &Chunk, limits: &Limits) -> Option<String> {
+    if let Some(text) = text::decode(chunk, limits) {
+        return Some(text.text);
+    }
+    let text = String::from_utf8(chunk.data().to_vec()).ok()?;
+    text.chars().all(|c| !c.is_control() || c == '\n' || c == '\r' || c == '\t').then_some(text)
+}
+
+pub fn diff_lines(old: &str, new: &str) -> Option<Vec<Line>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    if (old.len() + 1).saturating_mul(new.len() + 1) > MAX_TEXT_DIFF_CELLS {
+        return None;
+    }
+
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(Line::Same(old[i].to_string()));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(Line::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    Some(lines)
+}
+
+// changes turning `old` into `new`: removals, then moves and modifications in the order of `old`, then additions
+pub fn diff(old: &Png, new: &Png, limits: &Limits) -> Vec<Change> {
+    let old_keys = keys(old, limits);
+    let mut new_positions: HashMap<&(String, Option<String>, usize), usize> = HashMap::new();
+    let new_keys = keys(new, limits);
+    for (index, key) in new_keys.iter().enumerate() {
+        new_positions.insert(key, index);
+    }
+
+    let mut changes = Vec::new();
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for (index, key) in old_keys.iter().enumerate() {
+        match new_positions.remove(key) {
+            Some(to) => pairs.push((index, to)),
+            None => {
+                let chunk = &old.chunks()[index];
+                changes.push(Change::Removed { index, chunk_type: chunk.chunk_type().clone(), length: chunk.length() });
+            },
+        }
+    }
+
+    // the largest set of pairs already in the same relative order stays put, the rest moved
+    let targets: Vec<usize> = pairs.iter().map(|&(_, to)| to).collect();
+    let mut in_place = vec![false; pairs.len()];
+    for position in longest_increasing(&targets) {
+        in_place[position] = true;
+    }
+
+    for (&(from, to), in_place) in pairs.iter().zip(in_place) {
+        let (before, after) = (&old.chunks()[from], &new.chunks()[to]);
+        if !in_place {
+            changes.push(Change::Moved { from, to, chunk_type: before.chunk_type().clone() });
+        }
+        if before.data() != after.data() {
+            let lines = text_of(before, limits)
+                .zip(text_of(after, limits))
+                .and_then(|(old_text, new_text)| diff_lines(&old_text, &new_text));
+            changes.push(Change::Modified {
+                from,
+                to,
+                chunk_type: before.chunk_type().clone(),
+                lengths: (before.length(), after.length()),
+                crcs: (before.crc(), after.crc()),
+                lines,
+            });
+        }
+    }
+
+    let mut added: Vec<usize> = new_positions.into_values().collect();
+    added.sort_unstable();
+    for index in added {
+        let chunk = &new.chunks()[index];
+        changes.push(Change::Added { index, chunk_type: chunk.chunk_type().clone(), length: chunk.length() });
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::chunk;
+    use std::str::FromStr;
+
+    fn png(chunks: Vec<Chunk>) -> Png {
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_identical() {
+        let a = png(vec![chunk("IHDR", &[0; 13]), chunk("IDAT", &[1, 2]), chunk("IEND", &[])]);
+        assert!(diff(&a, &a, &Limits::default()).is_empty());
+    }
+
+    #[test]
+    fn test_added_removed_moved() {
+        let a = png(vec![chunk("IHDR", &[0; 13]), chunk("gAMA", &[0; 4]), chunk("tEXt", b"Title\0Dice"), chunk("IDAT", &[1]), chunk("IEND", &[])]);
+        let b = png(vec![chunk("IHDR", &[0; 13]), chunk("tEXt", b"Title\0Dice"), chunk("IDAT", &[1]), chunk("gAMA", &[0; 4]), chunk("ruSt", b"hi"), chunk("IEND", &[])]);
+
+        let changes = diff(&a, &b, &Limits::default());
+        assert_eq!(changes, [
+            Change::Moved { from: 1, to: 3, chunk_type: ChunkType::from_str("gAMA").unwrap() },
+            Change::Added { index: 4, chunk_type: ChunkType::from_str("ruSt").unwrap(), length: 2 },
+        ]);
+
+        let changes = diff(&b, &a, &Limits::default());
+        assert!(matches!(changes[0], Change::Removed { index: 4, .. }));
+    }
+
+    #[test]
+    fn test_text_keys_and_lines() {
+        let a = png(vec![chunk("tEXt", b"Title\0Dice"), chunk("tEXt", b"Comment\0one\ntwo\nthree")]);
+        let b = png(vec![chunk("tEXt", b"Comment\0one\n2\nthree"), chunk("tEXt", b"Title\0Dice")]);
+
+        let changes = diff(&a, &b, &Limits::default());
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(changes[0], Change::Moved { from: 0, to: 1, .. }));
+        let Change::Modified { from: 1, to: 0, lengths: (21, 19), lines: Some(lines), .. } = &changes[1] else {
+            panic!("unexpected change {:?}", changes[1]);
+        };
+        assert_eq!(lines, &[
+            Line::Same("one".to_string()),
+            Line::Removed("two".to_string()),
+            Line::Added("2".to_string()),
+            Line::Same("three".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_binary_modified() {
+        let a = png(vec![chunk("IDAT", &[0, 1, 2])]);
+        let b = png(vec![chunk("IDAT", &[0, 1, 2, 3])]);
+
+        let changes = diff(&a, &b, &Limits::default());
+        assert!(matches!(&changes[..], [Change::Modified { lines: None, lengths: (3, 4), .. }]));
+        assert!(changes[0].to_string().starts_with("* #0 -> #0 IDAT modified: 3 -> 4 bytes (+1), CRC 0x"));
+    }
+
+    #[test]
+    fn test_longest_increasing() {
+        assert_eq!(longest_increasing(&[3, 0, 1, 4, 2]), [1, 2, 4]);
+        assert!(longest_increasing(&[]).is_empty());
+    }
+}
@@ -0,0 +1,378 @@
+use crate::{apng::ApngError, batch::BatchError, chunk_type::ChunkType, colour::ColourError, fec::FecError, lsb::LsbError, palette::PaletteError, patch::PatchError, payload::PayloadError, pixels::PixelError, policy::PolicyError, seal::SealError, selector::SelectorError, shamir::ShamirError, unpack::ManifestError, volume::VolumeError, watermark::WatermarkError};
+use std::{error::Error as StdError, fmt, io, string::FromUtf8Error};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidSignature([u8; 8]),
+    Truncated { needed: usize, available: usize },
+    TrailingBytes(usize),
+    InvalidChunkType([u8; 4]),
+    InvalidChunkTypeStr(String),
+    CrcMismatch { stored: u32, computed: u32 },
+    LimitExceeded { limit: &'static str, value: u64, max: u64 },
+    ChunkNotFound(String),
+    IndexOutOfRange { index: usize, count: usize },
+    WrongChunkType { index: usize, expected: String, found: ChunkType },
+    CriticalChunk { index: usize, chunk_type: ChunkType },
+    InvalidUtf8(FromUtf8Error),
+    Payload(PayloadError),
+    Volume(VolumeError),
+    Shamir(ShamirError),
+    Fec(FecError),
+    Selector(SelectorError),
+    Batch(BatchError),
+    Pattern(regex::Error),
+    Policy(PolicyError),
+    Differ(usize),
+    Patch(PatchError),
+    Manifest(ManifestError),
+    Pixels(PixelError),
+    Seal(SealError),
+    Watermark(WatermarkError),
+    Apng(ApngError),
+    Colour(ColourError),
+    Palette(PaletteError),
+    Lsb(LsbError),
+    // where in the file a chunk level error happened, the cause is kept as the source
+    AtChunk { index: usize, offset: usize, chunk_type: Option<ChunkType>, source: Box<Error> },
+}
+
+impl Error {
+    pub fn at_chunk(self, index: usize, offset: usize, chunk_type: Option<ChunkType>) -> Error {
+        Error::AtChunk { index, offset, chunk_type, source: Box::new(self) }
+    }
+
+    // the error underneath any location context
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::AtChunk { source, .. } => source.root(),
+            err => err,
+        }
+    }
+
+    // stable identifiers, scripts may rely on them so existing ones must never change
+    pub fn code(&self) -> &'static str {
+        match self.root() {
+            Error::Io(_) => "io",
+            Error::InvalidSignature(_) => "invalid-signature",
+            Error::Truncated { .. } => "truncated",
+            Error::TrailingBytes(_) => "trailing-bytes",
+            Error::InvalidChunkType(_) | Error::InvalidChunkTypeStr(_) => "invalid-chunk-type",
+            Error::CrcMismatch { .. } => "crc-mismatch",
+            Error::LimitExceeded { .. } => "limit-exceeded",
+            Error::ChunkNotFound(_) => "chunk-not-found",
+            Error::IndexOutOfRange { .. } | Error::WrongChunkType { .. } => "bad-index",
+            Error::CriticalChunk { .. } => "critical-chunk",
+            Error::InvalidUtf8(_) => "invalid-utf8",
+            Error::Payload(_) => "payload",
+            Error::Volume(_) => "volume",
+            Error::Shamir(_) => "shamir",
+            Error::Fec(_) => "fec",
+            Error::Selector(_) => "selector",
+            Error::Batch(_) => "batch",
+            Error::Pattern(_) => "pattern",
+            Error::Policy(_) => "policy",
+            Error::Differ(_) => "differ",
+            Error::Patch(_) => "patch",
+            Error::Manifest(_) => "manifest",
+            Error::Pixels(_) => "pixels",
+            Error::Seal(SealError::MetadataChanged { .. }) => "metadata-changed",
+            Error::Seal(SealError::PixelsChanged) => "pixels-changed",
+            Error::Seal(_) => "seal",
+            Error::Watermark(_) => "watermark",
+            Error::Apng(_) => "apng",
+            Error::Colour(_) => "colour",
+            Error::Palette(_) => "palette",
+            Error::Lsb(_) => "lsb",
+            Error::AtChunk { .. } => unreachable!("root() never returns a context"),
+        }
+    }
+
+    // process exit status for each code. 2 is clap's status for argument errors and panics exit with 101; 1 is
+    // only the fallback for a code missing from this table, which the tests make sure never happens
+    pub fn exit_code(&self) -> u8 {
+        match self.code() {
+            "io" => 3,
+            "invalid-signature" => 10,
+            "truncated" => 11,
+            "trailing-bytes" => 12,
+            "invalid-chunk-type" => 13,
+            "crc-mismatch" => 14,
+            "limit-exceeded" => 15,
+            "chunk-not-found" => 20,
+            "bad-index" => 21,
+            "invalid-utf8" => 22,
+            "critical-chunk" => 23,
+            "payload" => 30,
+            "volume" => 31,
+            "shamir" => 32,
+            "fec" => 33,
+            "selector" => 34,
+            "batch" => 35,
+            "pattern" => 36,
+            "policy" => 37,
+            "differ" => 38,
+            "patch" => 39,
+            "manifest" => 40,
+            "pixels" => 41,
+            "seal" => 42,
+            "metadata-changed" => 43,
+            "pixels-changed" => 44,
+            "watermark" => 45,
+            "apng" => 46,
+            "colour" => 47,
+            "palette" => 48,
+            "lsb" => 49,
+            _ => 1,
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::InvalidUtf8(err) => Some(err),
+            Error::Payload(err) => Some(err),
+            Error::Volume(err) => Some(err),
+            Error::Shamir(err) => Some(err),
+            Error::Fec(err) => Some(err),
+            Error::Selector(err) => Some(err),
+            Error::Batch(err) => Some(err),
+            Error::Pattern(err) => Some(err),
+            Error::Policy(err) => Some(err),
+            Error::Patch(err) => Some(err),
+            Error::Manifest(err) => Some(err),
+            Error::Pixels(err) => Some(err),
+            Error::Seal(err) => Some(err),
+            Error::Watermark(err) => Some(err),
+            Error::Apng(err) => Some(err),
+            Error::Colour(err) => Some(err),
+            Error::Palette(err) => Some(err),
+            Error::Lsb(err) => Some(err),
+            Error::AtChunk { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(_) => write!(f, "I/O operation failed"),
+            Error::InvalidSignature(found) => write!(f, "File does not start with the PNG signature (found {:02X?})", found),
+            Error::Truncated { needed, available } => write!(f, "Data ends early ({} bytes needed, {} available)", needed, available),
+            Error::TrailingBytes(count) => write!(f, "{} stray bytes after the last chunk", count),
+            Error::InvalidChunkType(bytes) => write!(f, "Chunk type {:02X?} contains non-alphabetic bytes", bytes),
+            Error::InvalidChunkTypeStr(code) => write!(f, "\"{}\" is not a chunk type code (4 ASCII letters)", code),
+            Error::CrcMismatch { stored, computed } => write!(f, "Chunk contains {:#x} as CRC value when it should contain {:#x}", stored, computed),
+            Error::LimitExceeded { limit, value, max } => write!(f, "{} of {} exceeds the configured limit of {}", limit, value, max),
+            Error::ChunkNotFound(code) => write!(f, "Chunk {} doesn't exist", code),
+            Error::IndexOutOfRange { index, count } => write!(f, "Chunk #{} doesn't exist ({} chunks in file)", index, count),
+            Error::WrongChunkType { index, expected, found } => write!(f, "Chunk #{} is of type {}, not {}", index, found, expected),
+            Error::CriticalChunk { index, chunk_type } => write!(f, "Chunk #{} ({}) is critical, removing it breaks the image (pass --force to remove it anyway)", index, chunk_type),
+            Error::InvalidUtf8(_) => write!(f, "Chunk data is not valid UTF-8"),
+            Error::Payload(_) => write!(f, "Segmented payload could not be reassembled"),
+            Error::Volume(_) => write!(f, "Multi-volume payload could not be processed"),
+            Error::Shamir(_) => write!(f, "Secret sharing failed"),
+            Error::Fec(_) => write!(f, "Error correction failed"),
+            Error::Selector(_) => write!(f, "Invalid chunk selector"),
+            Error::Batch(_) => write!(f, "Processing several files failed"),
+            Error::Pattern(_) => write!(f, "Invalid search pattern"),
+            Error::Policy(_) => write!(f, "Policy check failed"),
+            Error::Differ(count) => write!(f, "Files differ ({} changes)", count),
+            Error::Patch(_) => write!(f, "Patch could not be applied"),
+            Error::Manifest(_) => write!(f, "Unpacked chunk directory could not be packed"),
+            Error::Pixels(_) => write!(f, "Image data could not be decoded"),
+            Error::Seal(_) => write!(f, "Image does not match its seal"),
+            Error::Watermark(_) => write!(f, "Watermarking failed"),
+            Error::Apng(_) => write!(f, "Animation is invalid"),
+            Error::Colour(_) => write!(f, "Colour information is invalid"),
+            Error::Palette(_) => write!(f, "Palette information is invalid"),
+            Error::Lsb(_) => write!(f, "Message in the pixels could not be stored or read"),
+            Error::AtChunk { index, offset, chunk_type: Some(chunk_type), .. } => write!(f, "Chunk #{} ({}) at byte offset {} is invalid", index, chunk_type, offset),
+            Error::AtChunk { index, offset, chunk_type: None, .. } => write!(f, "Chunk #{} at byte offset {} is invalid", index, offset),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Self {
+        Error::InvalidUtf8(err)
+    }
+}
+
+impl From<PayloadError> for Error {
+    fn from(err: PayloadError) -> Self {
+        Error::Payload(err)
+    }
+}
+
+impl From<VolumeError> for Error {
+    fn from(err: VolumeError) -> Self {
+        Error::Volume(err)
+    }
+}
+
+impl From<ShamirError> for Error {
+    fn from(err: ShamirError) -> Self {
+        Error::Shamir(err)
+    }
+}
+
+impl From<FecError> for Error {
+    fn from(err: FecError) -> Self {
+        Error::Fec(err)
+    }
+}
+
+impl From<SelectorError> for Error {
+    fn from(err: SelectorError) -> Self {
+        Error::Selector(err)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Self {
+        Error::Pattern(err)
+    }
+}
+
+impl From<PolicyError> for Error {
+    fn from(err: PolicyError) -> Self {
+        Error::Policy(err)
+    }
+}
+
+impl From<PatchError> for Error {
+    fn from(err: PatchError) -> Self {
+        Error::Patch(err)
+    }
+}
+
+impl From<ManifestError> for Error {
+    fn from(err: ManifestError) -> Self {
+        Error::Manifest(err)
+    }
+}
+
+impl From<PixelError> for Error {
+    fn from(err: PixelError) -> Self {
+        Error::Pixels(err)
+    }
+}
+
+impl From<SealError> for Error {
+    fn from(err: SealError) -> Self {
+        Error::Seal(err)
+    }
+}
+
+impl From<WatermarkError> for Error {
+    fn from(err: WatermarkError) -> Self {
+        Error::Watermark(err)
+    }
+}
+
+impl From<ApngError> for Error {
+    fn from(err: ApngError) -> Self {
+        Error::Apng(err)
+    }
+}
+
+impl From<ColourError> for Error {
+    fn from(err: ColourError) -> Self {
+        Error::Colour(err)
+    }
+}
+
+impl From<PaletteError> for Error {
+    fn from(err: PaletteError) -> Self {
+        Error::Palette(err)
+    }
+}
+
+impl From<LsbError> for Error {
+    fn from(err: LsbError) -> Self {
+        Error::Lsb(err)
+    }
+}
+
+impl From<BatchError> for Error {
+    fn from(err: BatchError) -> Self {
+        Error::Batch(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_context_chain() {
+        let err = Error::CrcMismatch { stored: 1, computed: 2 }
+            .at_chunk(3, 57, Some(ChunkType::from_str("ruSt").unwrap()));
+
+        assert_eq!(err.to_string(), "Chunk #3 (ruSt) at byte offset 57 is invalid");
+        assert_eq!(err.source().unwrap().to_string(), "Chunk contains 0x1 as CRC value when it should contain 0x2");
+        assert!(matches!(err.root(), Error::CrcMismatch { .. }));
+        assert_eq!(err.code(), "crc-mismatch");
+    }
+
+    #[test]
+    fn test_source_chain_reaches_module_errors() {
+        let err = Error::from(FecError::Uncorrectable(4));
+        assert_eq!(err.source().unwrap().to_string(), FecError::Uncorrectable(4).to_string());
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            Error::Io(io::Error::other("disk")),
+            Error::InvalidSignature([0; 8]),
+            Error::Truncated { needed: 12, available: 3 },
+            Error::TrailingBytes(2),
+            Error::InvalidChunkType([0; 4]),
+            Error::CrcMismatch { stored: 0, computed: 1 },
+            Error::LimitExceeded { limit: "chunk count", value: 3, max: 2 },
+            Error::ChunkNotFound("ruSt".to_string()),
+            Error::IndexOutOfRange { index: 9, count: 3 },
+            Error::CriticalChunk { index: 0, chunk_type: ChunkType::from_str("IHDR").unwrap() },
+            Error::InvalidUtf8(String::from_utf8(vec![0xFF]).unwrap_err()),
+            Error::Payload(PayloadError::SegmentSize(0)),
+            Error::Volume(VolumeError::NoCarriers),
+            Error::Shamir(ShamirError::NotEnoughShares(1, 2)),
+            Error::Fec(FecError::InvalidParity(0)),
+            Error::Selector(SelectorError::Empty),
+            Error::Batch(BatchError::SharedOutput(2)),
+            Error::Pattern(regex::Error::Syntax("(".to_string())),
+            Error::Policy(PolicyError::UnsupportedVersion(2)),
+            Error::Differ(1),
+            Error::Patch(PatchError::WrongBase),
+            Error::Manifest(ManifestError::UnsupportedVersion(2)),
+            Error::Pixels(PixelError::NoImageData),
+            Error::Seal(SealError::Malformed(3)),
+            Error::Seal(SealError::MetadataChanged { critical: false, ancillary: true }),
+            Error::Seal(SealError::PixelsChanged),
+            Error::Watermark(WatermarkError::TooSmall),
+            Error::Apng(ApngError::NoFrames),
+            Error::Colour(ColourError::Conflict),
+            Error::Palette(PaletteError::MissingPalette),
+            Error::Lsb(LsbError::NoMessage),
+        ];
+
+        let mut codes: Vec<u8> = errors.iter().map(Error::exit_code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|&code| code > 2));
+    }
+}
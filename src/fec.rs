@@ -0,0 +1,401 @@
+use crate::gf256;
+use std::fmt;
+
+// Reed-Solomon framing for payloads: a magic tag, a separately protected header and the
+// (optionally interleaved) codewords. The frame is plain bytes so any storage can carry it, chunks and
+// pixel least significant bits both do.
+
+pub const MAGIC: [u8; 4] = [0, b'F', b'E', b'C'];
+
+// parity count + interleave depth + payload length
+const HEADER_DATA_LENGTH: usize = 1 + 2 + 4;
+const HEADER_PARITY: usize = 8;
+const FRAME_HEADER_LENGTH: usize = MAGIC.len() + HEADER_DATA_LENGTH + HEADER_PARITY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FecParams {
+    parity: u8,
+    interleave: u16,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FecError {
+    InvalidParity(u8),
+    InvalidInterleave(u16),
+    Malformed(usize),
+    Uncorrectable(usize),
+    TooLarge(usize),
+}
+
+impl std::error::Error for FecError {}
+
+impl fmt::Display for FecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FecError::InvalidParity(parity) => write!(f, "Parity symbol count must be between 2 and 254 (got {})", parity),
+            FecError::InvalidInterleave(depth) => write!(f, "Interleave depth must be at least 1 (got {})", depth),
+            FecError::Malformed(size) => write!(f, "Error correction frame of {} bytes is truncated or has a damaged header", size),
+            FecError::Uncorrectable(block) => write!(f, "Codeword #{} has more errors than its parity symbols can correct", block),
+            FecError::TooLarge(size) => write!(f, "Payload of {} bytes is too large for an error correction frame", size),
+        }
+    }
+}
+
+impl FecParams {
+    pub fn new(parity: u8, interleave: u16) -> Result<FecParams, FecError> {
+        if !(2..=254).contains(&parity) {
+            return Err(FecError::InvalidParity(parity));
+        }
+        if interleave == 0 {
+            return Err(FecError::InvalidInterleave(interleave));
+        }
+
+        Ok(FecParams { parity, interleave })
+    }
+
+    pub fn parity(&self) -> u8 {
+        self.parity
+    }
+
+    pub fn interleave(&self) -> u16 {
+        self.interleave
+    }
+
+    // (codeword count, data bytes per codeword) for a payload of `length` bytes
+    fn layout(&self, length: usize) -> (usize, usize) {
+        let max_data = 255 - self.parity as usize;
+        let blocks = length.div_ceil(max_data).max(1);
+        (blocks, length.div_ceil(blocks))
+    }
+}
+
+// polynomials below are stored highest degree first
+
+fn poly_scale(poly: &[u8], x: u8) -> Vec<u8> {
+    poly.iter().map(|&coefficient| gf256::mul(coefficient, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let length = p.len().max(q.len());
+    let mut result = vec![0; length];
+    for (i, &coefficient) in p.iter().enumerate() {
+        result[i + length - p.len()] = coefficient;
+    }
+    for (i, &coefficient) in q.iter().enumerate() {
+        result[i + length - q.len()] ^= coefficient;
+    }
+    result
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; p.len() + q.len() - 1];
+    for (i, &a) in p.iter().enumerate() {
+        for (j, &b) in q.iter().enumerate() {
+            result[i + j] ^= gf256::mul(a, b);
+        }
+    }
+    result
+}
+
+fn poly_eval(poly: &[u8], x: u8) -> u8 {
+    poly.iter().fold(0, |acc, &coefficient| gf256::mul(acc, x) ^ coefficient)
+}
+
+fn generator_poly(parity: usize) -> Vec<u8> {
+    (0..parity).fold(vec![1], |generator, i| poly_mul(&generator, &[1, gf256::pow(2, i)]))
+}
+
+fn encode_block(data: &[u8], parity: usize) -> Vec<u8> {
+    let generator = generator_poly(parity);
+    let mut codeword = data.to_vec();
+    codeword.resize(data.len() + parity, 0);
+
+    // synthetic division by the generator, the remainder becomes the parity symbols
+    for i in 0..data.len() {
+        let coefficient = codeword[i];
+        if coefficient != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                codeword[i + j] ^= gf256::mul(g, coefficient);
+            }
+        }
+    }
+    codeword[..data.len()].copy_from_slice(data);
+
+    codeword
+}
+
+fn calc_syndromes(codeword: &[u8], parity: usize) -> Vec<u8> {
+    (0..parity).map(|i| poly_eval(codeword, gf256::pow(2, i))).collect()
+}
+
+// Berlekamp-Massey
+fn error_locator(syndromes: &[u8]) -> Option<Vec<u8>> {
+    let mut locator = vec![1];
+    let mut old_locator = vec![1];
+
+    for (i, &syndrome) in syndromes.iter().enumerate() {
+        let mut delta = syndrome;
+        for j in 1..locator.len() {
+            delta ^= gf256::mul(locator[locator.len() - 1 - j], syndromes[i - j]);
+        }
+
+        old_locator.push(0);
+        if delta != 0 {
+            if old_locator.len() > locator.len() {
+                let new_locator = poly_scale(&old_locator, delta);
+                old_locator = poly_scale(&locator, gf256::inv(delta));
+                locator = new_locator;
+            }
+            locator = poly_add(&locator, &poly_scale(&old_locator, delta));
+        }
+    }
+
+    let leading = locator.iter().take_while(|&&coefficient| coefficient == 0).count();
+    locator.drain(..leading);
+
+    ((locator.len() - 1) * 2 <= syndromes.len()).then_some(locator)
+}
+
+// Chien search, returns positions counted from the start of the codeword
+fn error_positions(locator: &[u8], length: usize) -> Option<Vec<usize>> {
+    let reversed: Vec<u8> = locator.iter().rev().copied().collect();
+    let positions: Vec<usize> = (0..length)
+        .filter(|&i| poly_eval(&reversed, gf256::pow(2, i)) == 0)
+        .map(|i| length - 1 - i)
+        .collect();
+
+    (positions.len() == locator.len() - 1).then_some(positions)
+}
+
+// Forney algorithm
+fn correct_errors(codeword: &mut [u8], syndromes: &[u8], positions: &[usize]) -> Option<()> {
+    let powers: Vec<usize> = positions.iter().map(|&position| codeword.len() - 1 - position).collect();
+
+    let locator = powers.iter().fold(vec![1], |locator, &power| {
+        poly_mul(&locator, &[gf256::pow(2, power), 1])
+    });
+
+    let reversed_syndromes: Vec<u8> = syndromes.iter().rev().copied().collect();
+    let product = poly_mul(&reversed_syndromes, &locator);
+    let evaluator: Vec<u8> = product[product.len().saturating_sub(powers.len())..].to_vec();
+
+    let locations: Vec<u8> = powers.iter().map(|&power| gf256::pow(2, power)).collect();
+
+    for (i, &location) in locations.iter().enumerate() {
+        let location_inv = gf256::inv(location);
+
+        let derivative = locations
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |acc, (_, &other)| gf256::mul(acc, 1 ^ gf256::mul(location_inv, other)));
+        if derivative == 0 {
+            return None;
+        }
+
+        let y = poly_eval(&evaluator, location_inv);
+        codeword[positions[i]] ^= gf256::div(y, derivative);
+    }
+
+    Some(())
+}
+
+// returns the data part of the codeword and how many symbols had to be corrected
+fn decode_block(codeword: &[u8], parity: usize) -> Option<(Vec<u8>, usize)> {
+    let data_length = codeword.len() - parity;
+    let syndromes = calc_syndromes(codeword, parity);
+    if syndromes.iter().all(|&syndrome| syndrome == 0) {
+        return Some((codeword[..data_length].to_vec(), 0));
+    }
+
+    let locator = error_locator(&syndromes)?;
+    let positions = error_positions(&locator, codeword.len())?;
+
+    let mut corrected = codeword.to_vec();
+    correct_errors(&mut corrected, &syndromes, &positions)?;
+
+    if calc_syndromes(&corrected, parity).iter().any(|&syndrome| syndrome != 0) {
+        return None;
+    }
+
+    corrected.truncate(data_length);
+    Some((corrected, positions.len()))
+}
+
+// codewords are cut into groups of `depth` and each group is written column by column,
+// so a burst of damaged bytes is spread over several codewords
+fn interleave(codewords: &[Vec<u8>], depth: usize) -> Vec<u8> {
+    codewords
+        .chunks(depth)
+        .flat_map(|group| (0..group[0].len()).flat_map(move |i| group.iter().map(move |codeword| codeword[i])))
+        .collect()
+}
+
+fn deinterleave(bytes: &[u8], count: usize, length: usize, depth: usize) -> Vec<Vec<u8>> {
+    let mut codewords = vec![Vec::with_capacity(length); count];
+
+    for (group_index, group) in bytes.chunks(depth * length).enumerate() {
+        let width = group.len() / length;
+        for (i, &byte) in group.iter().enumerate() {
+            codewords[group_index * depth + i % width].push(byte);
+        }
+    }
+
+    codewords
+}
+
+pub fn is_fec(bytes: &[u8]) -> bool {
+    bytes.len() >= FRAME_HEADER_LENGTH && bytes[..4] == MAGIC
+}
+
+// the length of the whole frame starting with `bytes`, read from its header once that is repaired; storage
+// without chunk boundaries needs it to know where the frame ends
+pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+    if !is_fec(bytes) {
+        return None;
+    }
+    let (header, _) = decode_block(&bytes[MAGIC.len()..FRAME_HEADER_LENGTH], HEADER_PARITY)?;
+    let params = FecParams::new(header[0], u16::from_be_bytes([header[1], header[2]])).ok()?;
+    let (blocks, block_length) = params.layout(u32::from_be_bytes(header[3..7].try_into().unwrap()) as usize);
+    Some(FRAME_HEADER_LENGTH + blocks * (block_length + params.parity as usize))
+}
+
+pub fn encode(data: &[u8], params: FecParams) -> Result<Vec<u8>, FecError> {
+    let length = u32::try_from(data.len()).map_err(|_| FecError::TooLarge(data.len()))?;
+    let parity = params.parity as usize;
+    let (blocks, block_length) = params.layout(data.len());
+
+    let header: Vec<u8> = [params.parity]
+        .into_iter()
+        .chain(params.interleave.to_be_bytes())
+        .chain(length.to_be_bytes())
+        .collect();
+
+    // the last block is zero padded so every codeword has the same length
+    let codewords: Vec<Vec<u8>> = (0..blocks)
+        .map(|block| {
+            let start = (block * block_length).min(data.len());
+            let end = (start + block_length).min(data.len());
+            let mut block_data = data[start..end].to_vec();
+            block_data.resize(block_length, 0);
+            encode_block(&block_data, parity)
+        })
+        .collect();
+
+    Ok(MAGIC
+        .into_iter()
+        .chain(encode_block(&header, HEADER_PARITY))
+        .chain(interleave(&codewords, params.interleave as usize))
+        .collect())
+}
+
+// returns the payload and the number of symbol errors that were corrected
+pub fn decode(bytes: &[u8]) -> Result<(Vec<u8>, usize), FecError> {
+    if !is_fec(bytes) {
+        return Err(FecError::Malformed(bytes.len()));
+    }
+
+    let (header, mut corrected) = decode_block(&bytes[MAGIC.len()..FRAME_HEADER_LENGTH], HEADER_PARITY)
+        .ok_or(FecError::Malformed(bytes.len()))?;
+
+    let params = FecParams::new(header[0], u16::from_be_bytes([header[1], header[2]]))
+        .map_err(|_| FecError::Malformed(bytes.len()))?;
+    let length = u32::from_be_bytes(header[3..7].try_into().unwrap()) as usize;
+
+    let (blocks, block_length) = params.layout(length);
+    let codeword_length = block_length + params.parity as usize;
+    let body = &bytes[FRAME_HEADER_LENGTH..];
+    if body.len() != blocks * codeword_length {
+        return Err(FecError::Malformed(bytes.len()));
+    }
+
+    let mut data = Vec::with_capacity(blocks * block_length);
+    for (block, codeword) in deinterleave(body, blocks, codeword_length, params.interleave as usize).iter().enumerate() {
+        let (block_data, count) = decode_block(codeword, params.parity as usize)
+            .ok_or(FecError::Uncorrectable(block))?;
+        data.extend(block_data);
+        corrected += count;
+    }
+    data.truncate(length);
+
+    Ok((data, corrected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"Reed-Solomon codes correct symbol errors in transit";
+
+    fn long_payload() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 + i / 3) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip_without_errors() {
+        let params = FecParams::new(16, 1).unwrap();
+        let encoded = encode(PAYLOAD, params).unwrap();
+
+        assert!(is_fec(&encoded));
+        assert_eq!(decode(&encoded).unwrap(), (PAYLOAD.to_vec(), 0));
+    }
+
+    #[test]
+    fn test_corrects_errors() {
+        let params = FecParams::new(16, 1).unwrap();
+        let mut encoded = encode(PAYLOAD, params).unwrap();
+        for i in [FRAME_HEADER_LENGTH, FRAME_HEADER_LENGTH + 5, FRAME_HEADER_LENGTH + 30, encoded.len() - 1] {
+            encoded[i] ^= 0x5A;
+        }
+
+        assert_eq!(decode(&encoded).unwrap(), (PAYLOAD.to_vec(), 4));
+    }
+
+    #[test]
+    fn test_corrects_damaged_header() {
+        let params = FecParams::new(8, 1).unwrap();
+        let mut encoded = encode(PAYLOAD, params).unwrap();
+        encoded[MAGIC.len()] ^= 0xFF;
+        encoded[MAGIC.len() + 3] ^= 0x01;
+
+        assert_eq!(decode(&encoded).unwrap(), (PAYLOAD.to_vec(), 2));
+    }
+
+    #[test]
+    fn test_too_many_errors() {
+        let params = FecParams::new(4, 1).unwrap();
+        let mut encoded = encode(PAYLOAD, params).unwrap();
+        for byte in encoded[FRAME_HEADER_LENGTH..FRAME_HEADER_LENGTH + 6].iter_mut() {
+            *byte ^= 0xFF;
+        }
+
+        assert_eq!(decode(&encoded), Err(FecError::Uncorrectable(0)));
+    }
+
+    #[test]
+    fn test_interleave_spreads_bursts() {
+        let payload = long_payload();
+        let burst = FRAME_HEADER_LENGTH + 100..FRAME_HEADER_LENGTH + 120;
+
+        let mut plain = encode(&payload, FecParams::new(10, 1).unwrap()).unwrap();
+        plain[burst.clone()].iter_mut().for_each(|byte| *byte ^= 0xFF);
+        assert!(decode(&plain).is_err());
+
+        let mut interleaved = encode(&payload, FecParams::new(10, 4).unwrap()).unwrap();
+        interleaved[burst].iter_mut().for_each(|byte| *byte ^= 0xFF);
+        assert_eq!(decode(&interleaved).unwrap(), (payload, 20));
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        let encoded = encode(b"", FecParams::new(2, 3).unwrap()).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        assert_eq!(FecParams::new(0, 1), Err(FecError::InvalidParity(0)));
+        assert_eq!(FecParams::new(255, 1), Err(FecError::InvalidParity(255)));
+        assert_eq!(FecParams::new(8, 0), Err(FecError::InvalidInterleave(0)));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{apng::{self, AnimationControl, Animation, ApngError, BlendOp, DisposeOp, FrameControl}, chunk::Chunk, chunk_type::ChunkType, limits::Limits, pixels::{self, Colours, Header, Image}, png::Png};
+use std::str::FromStr;
+
+// `top` composited over `bottom`, both 16 bit RGBA
+fn over(top: [u16; 4], bottom: [u16; 4]) -> [u16; 4] {
+    let (alpha, below) = (top[3] as u64, bottom[3] as u64);
+    match alpha {
+        0xFFFF => return top,
+        0 => return bottom,
+        _ => {},
+    }
+
+    let below = below * (0xFFFF - alpha) / 0xFFFF;
+    let total = alpha + below;
+    let mix = |channel: usize| ((top[channel] as u64 * alpha + bottom[channel] as u64 * below) / total) as u16;
+    [mix(0), mix(1), mix(2), total as u16]
+}
+
+// every frame as a viewer shows it, drawn over what the frames before it left on the canvas
+pub fn render(png: &Png, animation: &Animation, limits: &Limits) -> crate::Result<Vec<Image>> {
+    let header = Header::try_from(&png.chunks()[0])?;
+    let colours = Colours::of(png);
+    let (width, height) = (animation.width as usize, animation.height as usize);
+    limits.check_decoded_size((width as u64 * height as u64 * 8).saturating_mul(animation.frames.len() as u64 + 1))?;
+
+    let mut canvas = Image { width: animation.width, height: animation.height, pixels: vec![[0; 4]; width * height] };
+    let mut rendered = Vec::with_capacity(animation.frames.len());
+
+    for (number, frame) in animation.frames.iter().enumerate() {
+        let control = frame.control;
+        let frame_header = Header { width: control.width, height: control.height, ..header };
+        let passes = pixels::inflate(&frame_header, &animation.frame_data(png, number), limits)?;
+        let image = Image::from_passes(&frame_header, &passes, &colours, limits)?;
+
+        // there is nothing to go back to before the first frame, so it is cleared instead
+        let dispose = match control.dispose {
+            DisposeOp::Previous if number == 0 => DisposeOp::Background,
+            dispose => dispose,
+        };
+        let previous = (dispose == DisposeOp::Previous).then(|| canvas.pixels.clone());
+
+        let area = |y: usize| (control.y as usize + y) * width + control.x as usize;
+        for (y, row) in image.pixels.chunks_exact(control.width as usize).enumerate() {
+            let target = &mut canvas.pixels[area(y)..area(y) + control.width as usize];
+            for (pixel, &value) in target.iter_mut().zip(row) {
+                *pixel = match control.blend {
+                    BlendOp::Source => value,
+                    BlendOp::Over => over(value, *pixel),
+                };
+            }
+        }
+        rendered.push(canvas.clone());
+
+        match dispose {
+            DisposeOp::None => {},
+            DisposeOp::Background => {
+                for y in 0..control.height as usize {
+                    canvas.pixels[area(y)..area(y) + control.width as usize].fill([0; 4]);
+                }
+            },
+            DisposeOp::Previous => canvas.pixels = previous.unwrap(),
+        }
+    }
+    Ok(rendered)
+}
+
+// the smallest area holding every pixel that differs between two images of the same size
+fn changed(before: &Image, after: &Image) -> (u32, u32, u32, u32) {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (index, _) in before.pixels.iter().zip(&after.pixels).enumerate().filter(|(_, (a, b))| a != b) {
+        let (x, y) = ((index % after.width as usize) as u32, (index / after.width as usize) as u32);
+        bounds = Some(match bounds {
+            Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+            None => (x, y, x, y),
+        });
+    }
+
+    // a frame identical to the one before still needs a pixel to carry its delay
+    let (left, top, right, bottom) = bounds.unwrap_or((0, 0, 0, 0));
+    (left, top, right - left + 1, bottom - top + 1)
+}
+
+fn crop(image: &Image, (x, y, width, height): (u32, u32, u32, u32)) -> Image {
+    let pixels = (y..y + height)
+        .flat_map(|row| {
+            let start = (row * image.width + x) as usize;
+            image.pixels[start..start + width as usize].iter().copied()
+        })
+        .collect();
+    Image { width, height, pixels }
+}
+
+// an animation showing `images` in turn, the first one doubling as the still image; the frames after it
+// only carry the area that changed, drawn over what is left of the one before. Delays are in
+// milliseconds, one for every frame or one for each, `plays` is 0 to loop forever
+pub fn build(images: &[Image], delays: &[u16], plays: u32) -> Result<Png, ApngError> {
+    let first = images.first().ok_or(ApngError::NoFrames)?;
+    if delays.len() != 1 && delays.len() != images.len() {
+        return Err(ApngError::DelayCount { delays: delays.len(), frames: images.len() });
+    }
+    if let Some((frame, image)) = images.iter().enumerate().find(|(_, image)| (image.width, image.height) != (first.width, first.height)) {
+        return Err(ApngError::FrameSize { frame, size: (image.width, image.height), expected: (first.width, first.height) });
+    }
+
+    let bit_depth = images.iter().map(Image::bit_depth).max().unwrap();
+    let header = Header { width: first.width, height: first.height, bit_depth, colour_type: 6, interlaced: false };
+    let mut chunks = vec![header.to_chunk(), AnimationControl { frames: images.len() as u32, plays }.to_chunk()];
+    let mut sequence = 0;
+
+    for (number, image) in images.iter().enumerate() {
+        let area = match number {
+            0 => (0, 0, image.width, image.height),
+            _ => changed(&images[number - 1], image),
+        };
+        let (_, data) = crop(image, area).compress(bit_depth);
+        let delay = delays.get(number).unwrap_or(&delays[0]);
+
+        let (x, y, width, height) = area;
+        chunks.push(FrameControl { sequence, width, height, x, y, delay: (*delay, 1000), dispose: DisposeOp::None, blend: BlendOp::Source }.to_chunk());
+        sequence += 1;
+        if number == 0 {
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), data));
+        } else {
+            chunks.push(apng::frame_data_chunk(sequence, &data));
+            sequence += 1;
+        }
+    }
+
+    chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+    Ok(Png::from_chunks(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u16; 4] = [0xFFFF, 0, 0, 0xFFFF];
+    const BLUE: [u16; 4] = [0, 0, 0xFFFF, 0xFFFF];
+    const GLASS: [u16; 4] = [0, 0xFFFF, 0, 0x8000];
+
+    fn filled(width: u32, height: u32, pixel: [u16; 4]) -> Image {
+        Image { width, height, pixels: vec![pixel; (width * height) as usize] }
+    }
+
+    fn rendered(png: &Png) -> Vec<Image> {
+        let animation = Animation::parse(png).unwrap().unwrap();
+        render(png, &animation, &Limits::default()).unwrap()
+    }
+
+    #[test]
+    fn test_build_round_trip() {
+        let mut second = filled(4, 3, RED);
+        second.pixels[6] = BLUE;
+        let mut third = second.clone();
+        third.pixels[11] = [0; 4];
+        let images = [filled(4, 3, RED), second, third.clone(), third];
+
+        let png = build(&images, &[100, 250, 40, 40], 2).unwrap();
+        let animation = Animation::parse(&png).unwrap().unwrap();
+        assert_eq!(animation.control, AnimationControl { frames: 4, plays: 2 });
+        assert!(animation.shows_default_image());
+        assert_eq!(animation.frames[1].control.delay, (250, 1000));
+        // only the changed pixel is stored
+        let FrameControl { x, y, width, height, .. } = animation.frames[1].control;
+        assert_eq!((x, y, width, height), (2, 1, 1, 1));
+
+        assert_eq!(rendered(&png), images);
+        assert_eq!(Image::decode(&png, &Limits::default()).unwrap(), images[0]);
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let images = [filled(2, 2, RED), filled(2, 2, BLUE), filled(2, 3, RED)];
+        assert_eq!(build(&images, &[10, 20], 0).unwrap_err(), ApngError::DelayCount { delays: 2, frames: 3 });
+        assert_eq!(build(&images, &[10], 0).unwrap_err(), ApngError::FrameSize { frame: 2, size: (2, 3), expected: (2, 2) });
+        assert_eq!(build(&[], &[10], 0).unwrap_err(), ApngError::NoFrames);
+    }
+
+    // a blue 3x3 canvas, then three frames on the middle row with each dispose operation
+    fn disposing(blend: BlendOp, pixel: [u16; 4]) -> Png {
+        let (header, still) = filled(3, 3, BLUE).compress(16);
+        let (_, strip) = filled(3, 1, pixel).compress(16);
+        let (_, dot) = filled(1, 1, pixel).compress(16);
+        let control = |sequence, width, height, y, dispose| FrameControl { sequence, width, height, x: 0, y, delay: (1, 10), dispose, blend }.to_chunk();
+
+        Png::from_chunks(vec![
+            header.to_chunk(),
+            AnimationControl { frames: 4, plays: 0 }.to_chunk(),
+            FrameControl { sequence: 0, width: 3, height: 3, x: 0, y: 0, delay: (1, 10), dispose: DisposeOp::None, blend: BlendOp::Source }.to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), still),
+            control(1, 3, 1, 1, DisposeOp::Previous),
+            apng::frame_data_chunk(2, &strip),
+            control(3, 1, 1, 0, DisposeOp::Background),
+            apng::frame_data_chunk(4, &dot),
+            control(5, 1, 1, 2, DisposeOp::None),
+            apng::frame_data_chunk(6, &dot),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_dispose_and_blend() {
+        let frames = rendered(&disposing(BlendOp::Source, RED));
+        assert_eq!(frames[1].pixels[3..6], [RED; 3]);
+        // the strip was put back to blue before the dot was drawn
+        assert_eq!(frames[2].pixels[..6], [RED, BLUE, BLUE, BLUE, BLUE, BLUE]);
+        // the dot's area was cleared before the last frame
+        assert_eq!(frames[3].pixels[0], [0; 4]);
+        assert_eq!(frames[3].pixels[6], RED);
+
+        let frames = rendered(&disposing(BlendOp::Over, GLASS));
+        assert_eq!(frames[1].pixels[4], over(GLASS, BLUE));
+        assert_eq!(frames[3].pixels[0], [0; 4]);
+        assert_eq!(over(GLASS, [0; 4]), GLASS);
+        assert_eq!(over(GLASS, BLUE)[3], 0xFFFF);
+    }
+}
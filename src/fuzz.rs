@@ -0,0 +1,103 @@
+// Mutation fuzzing of the parsers, run with `cargo test fuzz`. The long run is ignored by default:
+// PNGME_FUZZ_ITERATIONS=1000000 cargo test --release fuzz -- --ignored
+use crate::{chunk::Chunk, limits::Limits, png::Png};
+use std::env;
+
+const SEED_FILE: &[u8] = include_bytes!("../data/dice.png");
+
+// the sample's image data would make each iteration slow, so every chunk is cut down to a short prefix
+fn seed_input() -> Vec<u8> {
+    let png = Png::try_from(SEED_FILE).unwrap();
+    let chunks = png.chunks()
+        .iter()
+        .map(|chunk| Chunk::new(chunk.chunk_type().clone(), chunk.data()[..chunk.data().len().min(512)].to_vec()))
+        .collect();
+    Png::from_chunks(chunks).as_bytes()
+}
+
+// xorshift64, reproducible from the seed printed on failure
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound.max(1) as u64) as usize
+    }
+}
+
+fn mutate(rng: &mut Rng, input: &[u8]) -> Vec<u8> {
+    let mut bytes = input.to_vec();
+
+    for _ in 0..=rng.below(8) {
+        let position = rng.below(bytes.len());
+        match rng.below(6) {
+            0 if !bytes.is_empty() => bytes[position] ^= 1 << rng.below(8),
+            1 if !bytes.is_empty() => bytes[position] = rng.next() as u8,
+            2 => bytes.truncate(position),
+            3 => bytes.insert(position, rng.next() as u8),
+            // length fields are the interesting target, so whole big-endian words get overwritten too
+            4 if bytes.len() >= 4 => {
+                let position = rng.below(bytes.len() - 3);
+                bytes[position..position + 4].copy_from_slice(&(rng.next() as u32).to_be_bytes());
+            },
+            _ => {
+                let extra: Vec<u8> = (0..rng.below(16)).map(|_| rng.next() as u8).collect();
+                bytes.extend(extra);
+            },
+        }
+    }
+
+    bytes
+}
+
+fn check(input: &[u8]) {
+    let limits = Limits { max_total_size: 1 << 20, max_chunk_count: 4096, ..Limits::default() };
+
+    if let Ok(png) = Png::try_from_with_limits(input, &limits) {
+        assert_eq!(png.as_bytes(), input);
+    }
+    if let Ok(png) = Png::try_from_unchecked(input, &limits) {
+        assert_eq!(png.as_bytes(), input);
+    }
+    if let Some(chunk_bytes) = input.get(8..) {
+        if let Ok(chunk) = Chunk::try_from(chunk_bytes) {
+            assert_eq!(chunk.as_bytes(), chunk_bytes[..chunk.as_bytes().len()]);
+        }
+        let _ = Chunk::try_from_unchecked(chunk_bytes);
+    }
+}
+
+fn run(seed: u64, iterations: usize) {
+    let mut rng = Rng(seed);
+    let seed_input = seed_input();
+
+    for iteration in 0..iterations {
+        let input = if rng.below(8) == 0 {
+            (0..rng.below(64)).map(|_| rng.next() as u8).collect()
+        } else {
+            mutate(&mut rng, &seed_input)
+        };
+
+        let result = std::panic::catch_unwind(|| check(&input));
+        assert!(result.is_ok(), "parser panicked on iteration {} (seed {}), input: {:02X?}", iteration, seed, input);
+    }
+}
+
+#[test]
+fn fuzz_parsers() {
+    run(0x5EED_D1CE, 2_000);
+}
+
+#[test]
+#[ignore]
+fn fuzz_parsers_long() {
+    let iterations = env::var("PNGME_FUZZ_ITERATIONS").ok().and_then(|count| count.parse().ok()).unwrap_or(1_000_000);
+    let seed = env::var("PNGME_FUZZ_SEED").ok().and_then(|seed| seed.parse().ok()).unwrap_or(0x5EED_D1CE);
+    run(seed, iterations);
+}
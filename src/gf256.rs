@@ -0,0 +1,114 @@
+// GF(2^8) arithmetic over the x^8 + x^4 + x^3 + x^2 + 1 polynomial (0x11D) with 2 as generator
+
+pub const POLYNOMIAL: u16 = 0x11D;
+
+// doubled so products of two logarithms can index it without a modulo
+pub const EXP_TABLE: [u8; 512] = {
+    let mut table: [u8; 512] = [0; 512];
+
+    let mut value: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        table[i] = value as u8;
+        table[i + 255] = value as u8;
+        value <<= 1;
+        if value & 0x100 != 0 {
+            value ^= POLYNOMIAL;
+        }
+        i += 1;
+    }
+    table[510] = table[0];
+    table[511] = table[1];
+
+    table
+};
+
+pub const LOG_TABLE: [u8; 256] = {
+    let mut table: [u8; 256] = [0; 256];
+
+    let mut i = 0;
+    while i < 255 {
+        table[EXP_TABLE[i] as usize] = i as u8;
+        i += 1;
+    }
+
+    table
+};
+
+pub fn add(a: u8, b: u8) -> u8 {
+    a ^ b
+}
+
+pub fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    EXP_TABLE[LOG_TABLE[a as usize] as usize + LOG_TABLE[b as usize] as usize]
+}
+
+pub fn div(a: u8, b: u8) -> u8 {
+    assert!(b != 0, "division by zero in GF(256)");
+    if a == 0 {
+        return 0;
+    }
+    EXP_TABLE[LOG_TABLE[a as usize] as usize + 255 - LOG_TABLE[b as usize] as usize]
+}
+
+pub fn inv(a: u8) -> u8 {
+    div(1, a)
+}
+
+pub fn pow(a: u8, exponent: usize) -> u8 {
+    if exponent == 0 {
+        return 1;
+    }
+    if a == 0 {
+        return 0;
+    }
+    EXP_TABLE[(LOG_TABLE[a as usize] as usize * exponent) % 255]
+}
+
+// coefficients are ordered from the constant term upwards
+pub fn eval_poly(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |acc, &coefficient| add(mul(acc, x), coefficient))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul_identity_and_zero() {
+        for a in 0..=255u8 {
+            assert_eq!(mul(a, 1), a);
+            assert_eq!(mul(a, 0), 0);
+        }
+    }
+
+    #[test]
+    fn test_mul_div_inverse() {
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inv(a)), 1);
+            for b in [1u8, 2, 29, 128, 255] {
+                assert_eq!(div(mul(a, b), b), a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_products() {
+        assert_eq!(mul(2, 128), 0x1D);
+        assert_eq!(pow(2, 8), 0x1D);
+        assert_eq!(pow(2, 255), 1);
+    }
+
+    #[test]
+    fn test_eval_poly() {
+        // 3 + 2x + x^2 at x = 2 -> 3 ^ 4 ^ 4
+        assert_eq!(eval_poly(&[3, 2, 1], 2), 3);
+        assert_eq!(eval_poly(&[7, 5, 1], 0), 7);
+    }
+}
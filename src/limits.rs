@@ -0,0 +1,117 @@
+use crate::error::Error;
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+// Caps applied while parsing so untrusted files cannot make pngme allocate or inflate without bound.
+// Every check happens before the memory it guards is allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_chunk_length: u32,
+    pub max_total_size: usize,
+    pub max_chunk_count: usize,
+    pub max_decompressed_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            // the PNG spec caps a chunk's data length at 2^31 - 1 bytes
+            max_chunk_length: i32::MAX as u32,
+            max_total_size: 1 << 30,
+            max_chunk_count: 1 << 20,
+            max_decompressed_size: 256 << 20,
+        }
+    }
+}
+
+impl Limits {
+    pub const UNLIMITED: Limits = Limits {
+        max_chunk_length: u32::MAX,
+        max_total_size: usize::MAX,
+        max_chunk_count: usize::MAX,
+        max_decompressed_size: usize::MAX,
+    };
+
+    pub fn check_total_size(&self, size: u64) -> crate::Result<()> {
+        check("total size", size, self.max_total_size as u64)
+    }
+
+    pub fn check_chunk_length(&self, length: u32) -> crate::Result<()> {
+        check("chunk length", length as u64, self.max_chunk_length as u64)
+    }
+
+    pub fn check_chunk_count(&self, count: usize) -> crate::Result<()> {
+        check("chunk count", count as u64, self.max_chunk_count as u64)
+    }
+
+    // decoded pixels are held to the same cap as inflated data
+    pub fn check_decoded_size(&self, size: u64) -> crate::Result<()> {
+        check("decoded size", size, self.max_decompressed_size as u64)
+    }
+}
+
+fn check(limit: &'static str, value: u64, max: u64) -> crate::Result<()> {
+    if value > max {
+        return Err(Error::LimitExceeded { limit, value, max });
+    }
+    Ok(())
+}
+
+// inflates zlib data (zTXt, iTXt, iCCP, IDAT, ...) without ever holding more than the limit allows
+pub fn decompress(data: &[u8], limits: &Limits) -> crate::Result<Vec<u8>> {
+    let max = limits.max_decompressed_size as u64;
+    let mut decompressed = Vec::new();
+
+    ZlibDecoder::new(data)
+        .take(max.saturating_add(1))
+        .read_to_end(&mut decompressed)?;
+
+    check("decompressed size", decompressed.len() as u64, max)?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decompress_within_limit() {
+        let data = vec![7u8; 4096];
+        assert_eq!(decompress(&compress(&data), &Limits::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decompress_bomb() {
+        let bomb = compress(&vec![0u8; 1 << 20]);
+        let limits = Limits { max_decompressed_size: 1024, ..Limits::default() };
+
+        assert!(matches!(
+            decompress(&bomb, &limits),
+            Err(Error::LimitExceeded { limit: "decompressed size", value: 1025, max: 1024 })
+        ));
+    }
+
+    #[test]
+    fn test_decompress_garbage() {
+        assert!(matches!(decompress(b"not zlib at all", &Limits::default()), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_checks() {
+        let limits = Limits { max_chunk_length: 10, max_total_size: 100, max_chunk_count: 2, ..Limits::default() };
+
+        assert!(limits.check_chunk_length(10).is_ok());
+        assert!(limits.check_chunk_length(11).is_err());
+        assert!(limits.check_total_size(101).is_err());
+        assert!(limits.check_chunk_count(3).is_err());
+        assert!(Limits::UNLIMITED.check_total_size(u64::MAX).is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{fec, limits::Limits, pixels::{self, Header, Pass, PixelError}, png::Png};
+use std::fmt;
+
+// a plain message is stored behind this tag and its length, an error coded one as its frame, which
+// carries its own tag and length
+const MAGIC: [u8; 4] = [0, b'L', b'S', b'B'];
+const PLAIN_HEADER_LENGTH: usize = MAGIC.len() + 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LsbError {
+    Unsupported { bit_depth: u8, colour_type: u8 },
+    TooSmall { needed: usize, capacity: usize },
+    NoMessage,
+}
+
+impl std::error::Error for LsbError {}
+
+impl fmt::Display for LsbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LsbError::Unsupported { bit_depth, colour_type } => write!(f, "Images of colour type {} at bit depth {} cannot hold a message in their pixels", colour_type, bit_depth),
+            LsbError::TooSmall { needed, capacity } => write!(f, "Message needs {} bytes but the pixels only hold {}", needed, capacity),
+            LsbError::NoMessage => write!(f, "Pixels do not hold a message"),
+        }
+    }
+}
+
+// the byte holding the least significant bit of a colour sample, in a pass
+type Slot = (usize, usize);
+
+// the decoded image and one slot per colour sample, in file order; alpha samples are left alone so
+// no pixel changes how transparent it is
+fn carrier(png: &Png, limits: &Limits) -> crate::Result<(Header, Vec<Pass>, Vec<Slot>)> {
+    let header = Header::try_from(png.chunk_at(0).ok_or(PixelError::MissingHeader)?)?;
+    if header.bit_depth < 8 || header.colour_type == 3 {
+        return Err(LsbError::Unsupported { bit_depth: header.bit_depth, colour_type: header.colour_type }.into());
+    }
+    let passes = pixels::inflate(&header, &pixels::image_data(png)?, limits)?;
+
+    let bytes = header.bit_depth as usize / 8;
+    let channels = header.channels();
+    let colour_channels = if header.colour_type == 2 || header.colour_type == 6 { 3 } else { 1 };
+    let slots = passes
+        .iter()
+        .enumerate()
+        .flat_map(|(pass, Pass { width, height, .. })| {
+            (0..*width as usize * *height as usize)
+                .flat_map(move |pixel| (0..colour_channels).map(move |channel| (pass, (pixel * channels + channel) * bytes + bytes - 1)))
+        })
+        .collect();
+    Ok((header, passes, slots))
+}
+
+// the number of bytes the pixels of `png` can hold, headers included
+pub fn capacity(png: &Png, limits: &Limits) -> crate::Result<usize> {
+    Ok(carrier(png, limits)?.2.len() / 8)
+}
+
+// a copy of `png` carrying `data` in the least significant bits of its colour samples, with the number of
+// bytes stored; every other chunk is kept as it is and an error correction frame is stored as it is
+pub fn embed(png: &Png, data: &[u8], limits: &Limits) -> crate::Result<(Png, usize)> {
+    let (header, mut passes, slots) = carrier(png, limits)?;
+    let stored = match fec::is_fec(data) {
+        true => data.to_vec(),
+        false => MAGIC.into_iter().chain((data.len() as u32).to_be_bytes()).chain(data.iter().copied()).collect(),
+    };
+    if stored.len() * 8 > slots.len() {
+        return Err(LsbError::TooSmall { needed: stored.len(), capacity: slots.len() / 8 }.into());
+    }
+
+    for (index, &(pass, byte)) in slots.iter().take(stored.len() * 8).enumerate() {
+        let bit = (stored[index / 8] >> (7 - index % 8)) & 1;
+        let byte = &mut passes[pass].rows[byte];
+        *byte = (*byte & !1) | bit;
+    }
+    Ok((pixels::with_image_data(png, &header, &passes), stored.len()))
+}
+
+// the data `embed` stored, an error correction frame is returned whole for fec::decode to repair
+pub fn read(png: &Png, limits: &Limits) -> crate::Result<Vec<u8>> {
+    let (_, passes, slots) = carrier(png, limits)?;
+    let stored: Vec<u8> = slots
+        .chunks_exact(8)
+        .map(|bits| bits.iter().fold(0, |byte, &(pass, index)| (byte << 1) | (passes[pass].rows[index] & 1)))
+        .collect();
+
+    if let Some(length) = fec::frame_length(&stored) {
+        return stored.get(..length).map(<[u8]>::to_vec).ok_or(LsbError::NoMessage.into());
+    }
+    if stored.len() < PLAIN_HEADER_LENGTH || stored[..MAGIC.len()] != MAGIC {
+        return Err(LsbError::NoMessage.into());
+    }
+    let length = u32::from_be_bytes(stored[MAGIC.len()..PLAIN_HEADER_LENGTH].try_into().unwrap()) as usize;
+    stored.get(PLAIN_HEADER_LENGTH..PLAIN_HEADER_LENGTH + length).map(<[u8]>::to_vec).ok_or(LsbError::NoMessage.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, fec::FecParams, pixels::Image, testing::{header, module_error, still}};
+
+    fn photo() -> Png {
+        let pixels = (0..32 * 24).map(|i| [(i * 37 % 65536) as u16, (i * 91 % 65536) as u16, 0x4000, 0xFFFF]).collect();
+        Image { width: 32, height: 24, pixels }.to_png()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let limits = Limits::default();
+        let (png, used) = embed(&photo(), b"in plain sight", &limits).unwrap();
+        assert_eq!(used, PLAIN_HEADER_LENGTH + 14);
+        assert_eq!(read(&png, &limits).unwrap(), b"in plain sight");
+        assert_eq!(capacity(&png, &limits).unwrap(), 32 * 24 * 3 / 8);
+
+        // only least significant bits change
+        let (before, after) = (Image::decode(&photo(), &limits).unwrap(), Image::decode(&png, &limits).unwrap());
+        assert!(before.pixels.iter().zip(&after.pixels).all(|(a, b)| (0..4).all(|c| a[c].abs_diff(b[c]) <= 257)));
+        assert!(after.pixels.iter().all(|pixel| pixel[3] == 0xFFFF));
+    }
+
+    #[test]
+    fn test_error_coded_message_survives_damage() {
+        let limits = Limits::default();
+        let frame = fec::encode(b"protected by parity", FecParams::new(16, 2).unwrap()).unwrap();
+        let (png, used) = embed(&photo(), &frame, &limits).unwrap();
+        assert_eq!(used, frame.len());
+
+        // flip a run of bits past the frame header
+        let (header, mut passes, slots) = carrier(&png, &limits).unwrap();
+        for &(pass, byte) in &slots[200..240] {
+            passes[pass].rows[byte] ^= 1;
+        }
+        let damaged = read(&pixels::with_image_data(&png, &header, &passes), &limits).unwrap();
+        assert_ne!(damaged, frame);
+
+        let (data, corrected) = fec::decode(&damaged).unwrap();
+        assert_eq!(data, b"protected by parity");
+        assert!(corrected > 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let limits = Limits::default();
+        assert_eq!(module_error!(embed(&photo(), &[0; 300], &limits), Error::Lsb), LsbError::TooSmall { needed: 308, capacity: 288 });
+        assert_eq!(module_error!(read(&photo(), &limits), Error::Lsb), LsbError::NoMessage);
+
+        let indexed = still(header(3, 8), vec![]);
+        assert_eq!(module_error!(embed(&indexed, b"hi", &limits), Error::Lsb), LsbError::Unsupported { bit_depth: 8, colour_type: 3 });
+        let grey = still(header(0, 4), vec![]);
+        assert_eq!(module_error!(read(&grey, &limits), Error::Lsb), LsbError::Unsupported { bit_depth: 4, colour_type: 0 });
+    }
+}
@@ -1,55 +1,391 @@
 #![allow(dead_code)]
-use args::{Args, Commands};
+use args::{Args, Batch, Commands, FramesAction, PatchAction};
+use colour::{Colour, IccProfile};
+use limits::Limits;
+use policy::PolicyFile;
+use regex::bytes::RegexBuilder;
 use clap::Parser;
+use std::{error::Error as _, fs, io::Write, path::PathBuf, process::ExitCode};
 
+mod apng;
+mod colour;
 mod commands;
+mod compare;
+mod diff;
+mod error;
 mod chunk;
 mod chunk_type;
 mod args;
+mod atomic;
+mod batch;
+mod payload;
+mod fec;
+mod frames;
+#[cfg(test)]
+mod fuzz;
+mod gf256;
+mod limits;
+mod lsb;
+mod palette;
+mod patch;
+mod pixels;
 mod png;
+mod policy;
+mod seal;
+mod selector;
+mod shamir;
+mod strip;
+#[cfg(test)]
+mod testing;
+mod text;
+mod unpack;
+mod volume;
+mod watermark;
 
-pub type Error = Box<dyn std::error::Error>;
+pub use error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let args = Args::parse();
 
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error [{}]: {}", err.code(), err);
+            let mut source = err.source();
+            while let Some(cause) = source {
+                eprintln!("  caused by: {}", cause);
+                source = cause.source();
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+// progress lines go to stderr when stdout carries the image
+fn status(to_stderr: bool, line: String) {
+    if to_stderr {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+// where a command that rewrites its input sends the image
+fn save(cmd: &Commands, backup: bool) -> commands::Save {
+    match cmd {
+        Commands::Encode { output, .. }
+        | Commands::Remove { output, .. }
+        | Commands::Strip { output, .. }
+        | Commands::Seal { output, .. } => commands::Save { output: output.clone(), backup },
+        _ => commands::Save { output: None, backup },
+    }
+}
+
+// the policy file is read once up front rather than for every file of a batch
+fn rules(cmd: &Commands) -> Result<Option<PolicyFile>> {
+    match cmd {
+        Commands::Strip { policy: Some(path), .. } | Commands::Validate { policy: Some(path), .. } => Ok(Some(PolicyFile::load(path)?)),
+        _ => Ok(None),
+    }
+}
+
+// the work a batchable command does on one file, returning the lines to show for it
+fn run_file(cmd: &Commands, filepath: &PathBuf, rules: Option<&PolicyFile>, limits: &Limits, backup: bool) -> Result<Vec<String>> {
+    let save = save(cmd, backup);
+    let mut lines = Vec::new();
+
+    match cmd {
+        Commands::Encode { chunk_code, message, split_size, ecc, interleave, .. } => {
+            let fec_params = ecc.map(|parity| fec::FecParams::new(parity, *interleave)).transpose()?;
+            let count = commands::encode(filepath, chunk_code, message.clone(), *split_size, fec_params, limits, &save)?;
+            let destination = match save.writes_to_stdout(filepath) {
+                true => "stdout".to_string(),
+                false => save.output.as_ref().unwrap_or(filepath).display().to_string(),
+            };
+            lines.push(format!("Successfully encoded message with code {0} inside {1}", chunk_code, destination));
+            if count > 1 {
+                lines.push(format!("Message was split across {} chunks", count));
+            }
+        },
+        Commands::Decode { chunk_code, target, .. } => {
+            for message in commands::decode(filepath, chunk_code.as_deref(), &target.selection(), limits)? {
+                lines.push(format!("Message hidden within chunk \"{0}\" (#{1}) -> {2}", message.chunk_type, message.index, message.text));
+                if let Some(count) = message.corrected {
+                    lines.push(format!("Error correction fixed {} damaged bytes", count));
+                }
+            }
+        },
+        Commands::Remove { chunk_code, target, force, .. } => {
+            for chunk in commands::remove(filepath, chunk_code.as_deref(), &target.selection(), *force, limits, &save)? {
+                lines.push(format!("Removed chunk container (code: {})", str::from_utf8(&chunk.chunk_type().bytes()).unwrap()));
+            }
+        },
+        Commands::Print { select, .. } => {
+            lines = commands::print(filepath, select.as_ref(), limits)?;
+        },
+        Commands::Strip { preset, keep, drop, .. } => {
+            let policy = strip::Policy { preset: *preset, keep: keep.clone(), drop: drop.clone(), rules: rules.cloned() };
+            let stripped = commands::strip(filepath, &policy, limits, &save)?;
+            for (index, chunk) in &stripped.removed {
+                lines.push(format!("Removed chunk #{0} ({1}, {2} bytes)", index, chunk.chunk_type(), chunk.length()));
+            }
+            lines.push(format!(
+                "Stripped {0} chunks, saved {1} bytes ({2} -> {3} bytes)",
+                stripped.removed.len(), stripped.size_before - stripped.size_after, stripped.size_before, stripped.size_after,
+            ));
+            for violation in &stripped.violations {
+                lines.push(format!("Policy still violated: {}", violation));
+            }
+        },
+        Commands::Validate { .. } => {
+            let count = commands::validate(filepath, rules, limits)?;
+            lines.push(format!("Valid PNG with {} chunks", count));
+        },
+        Commands::Seal { .. } => {
+            commands::seal(filepath, limits, &save)?;
+            lines.push(format!("Sealed {}", save.destination(filepath).display()));
+        },
+        Commands::Check { .. } => {
+            commands::check(filepath, limits)?;
+            lines.push("Intact: pixels and chunks are as sealed".to_string());
+        },
+        _ => unreachable!("not a per-file command"),
+    }
+
+    Ok(lines)
+}
+
+// a single plain path runs exactly like before, globs, directories and several paths go through the batch runner
+fn run_batched<F>(paths: Vec<PathBuf>, batch: &Batch, save: &commands::Save, headers: bool, job: F) -> Result<()>
+where
+    F: Fn(&PathBuf) -> Result<Vec<String>> + Sync,
+{
+    let files = batch::resolve(&paths, batch.recursive)?;
+
+    if !batch::is_batch(&paths, &files) {
+        let to_stderr = save.writes_to_stdout(&files[0]);
+        for line in job(&files[0])? {
+            status(to_stderr, line);
+        }
+        return Ok(());
+    }
+
+    if save.output.is_some() {
+        return Err(batch::BatchError::SharedOutput(files.len()).into());
+    }
+    let outcomes = batch::run(&files, batch.jobs, headers, job)?;
+    batch::finish(&outcomes, batch.manifest.as_ref())
+}
+
+fn run(args: Args) -> Result<()> {
+    let limits = args.limits.limits();
+    let backup = args.backup;
+
     match args.cmd {
-        Commands::Encode { 
-            filepath, 
-            chunk_code, 
-            message,
-        } => {
-            match commands::encode(&filepath, &chunk_code, message) {
-                Ok(_) => {
-                    println!("Successfully encoded message with code {0} inside {1}", chunk_code, filepath.into_os_string().into_string().unwrap());
-                    Ok(())
-                },
-                Err(err) => {
-                    eprintln!("{}", err);
-                    Err(err)
-            }   }
-        },
-        Commands::Decode {filepath, chunk_code} => {
-            match commands::decode(&filepath, &chunk_code) {
-                Ok(message) => {
-                    println!("Message hidden within chunk \"{0}\" -> {1}", chunk_code, message);
-                    Ok(())
-                },
-                Err(err) => Err(err)
+        Commands::Encode { ref filepath, ref batch, .. }
+        | Commands::Decode { ref filepath, ref batch, .. }
+        | Commands::Remove { ref filepath, ref batch, .. }
+        | Commands::Strip { ref filepath, ref batch, .. }
+        | Commands::Seal { ref filepath, ref batch, .. } => {
+            let rules = rules(&args.cmd)?;
+            let paths = batch.paths(std::slice::from_ref(filepath));
+            run_batched(paths, batch, &save(&args.cmd, backup), true, |path| run_file(&args.cmd, path, rules.as_ref(), &limits, backup))
+        },
+        Commands::Print { ref filepaths, ref batch, .. }
+        | Commands::Validate { ref filepaths, ref batch, .. }
+        | Commands::Check { ref filepaths, ref batch } => {
+            let rules = rules(&args.cmd)?;
+            run_batched(batch.paths(filepaths), batch, &save(&args.cmd, backup), true, |path| run_file(&args.cmd, path, rules.as_ref(), &limits, backup))
+        },
+        Commands::Grep { ref pattern, ref filepaths, fixed_strings, ignore_case, private, ref batch } => {
+            let pattern = if fixed_strings { regex::escape(pattern) } else { pattern.clone() };
+            let pattern = RegexBuilder::new(&pattern).case_insensitive(ignore_case).build()?;
+
+            run_batched(batch.paths(filepaths), batch, &commands::Save::default(), false, |path| {
+                Ok(commands::grep(path, &pattern, private, &limits)?
+                    .into_iter()
+                    .map(|found| match found.keyword {
+                        Some(keyword) => format!("{}:#{}:{}[{}]: {}", path.display(), found.index, found.chunk_type, keyword, found.snippet),
+                        None => format!("{}:#{}:{}: {}", path.display(), found.index, found.chunk_type, found.snippet),
+                    })
+                    .collect())
+            })
+        },
+        Commands::Replace {filepath, chunk_code, message, target, output} => {
+            let save = commands::Save { output, backup };
+            let indices = commands::replace(&filepath, &chunk_code, message, &target.selection(), &limits, &save)?;
+            for index in indices {
+                status(save.writes_to_stdout(&filepath), format!("Replaced message in chunk container #{0} (code: {1})", index, chunk_code));
+            }
+            Ok(())
+        },
+        Commands::Extract { filepath, selector, output } => {
+            for path in commands::extract(&filepath, &selector, &output, &limits)? {
+                println!("Extracted {}", path.display());
+            }
+            Ok(())
+        },
+        Commands::Diff { old, new } => {
+            let changes = commands::diff(&old, &new, &limits)?;
+            for change in &changes {
+                println!("{}", change);
+            }
+            match changes.len() {
+                0 => Ok(()),
+                count => Err(Error::Differ(count)),
+            }
+        },
+        Commands::Compare { first, second, output } => {
+            let comparison = commands::compare(&first, &second, output.as_ref(), &limits, backup)?;
+            println!("{}", comparison);
+            if let Some(path) = output.filter(|_| comparison.differing() > 0) {
+                println!("Difference image written to {}", path.display());
+            }
+            match comparison.differing() {
+                0 => Ok(()),
+                count => Err(Error::Differ(count)),
+            }
+        },
+        Commands::Colour { filepath, extract_icc, clear, gamma, chromaticities, srgb, icc, icc_name, significant_bits, output } => {
+            let mut changes = Vec::new();
+            // stored times 100000
+            changes.extend(gamma.map(|gamma| Colour::Gamma((gamma * 100_000.0).round() as u32)));
+            changes.extend(chromaticities.map(Colour::Chromaticities));
+            changes.extend(srgb.map(Colour::Srgb));
+            if let Some(path) = icc {
+                limits.check_total_size(fs::metadata(&path)?.len())?;
+                changes.push(Colour::Icc(IccProfile::new(&icc_name, fs::read(&path)?)?));
+            }
+            changes.extend(significant_bits.map(Colour::SignificantBits));
+
+            let save = commands::Save { output, backup };
+            let found = commands::colour(&filepath, clear, &changes, extract_icc.as_ref(), &limits, &save)?;
+            let to_stderr = save.writes_to_stdout(&filepath);
+            for (index, colour) in &found {
+                status(to_stderr, format!("#{0} {1}: {2}", index, colour.chunk_type(), colour));
+            }
+            if found.is_empty() {
+                status(to_stderr, "No colour chunks".to_string());
+            }
+            if let Some(path) = extract_icc {
+                status(to_stderr, format!("ICC profile written to {}", path.display()));
+            }
+            if clear || !changes.is_empty() {
+                status(to_stderr, format!("Colour chunks written to {}", save.destination(&filepath).display()));
             }
+            Ok(())
         },
-        Commands::Remove {filepath, chunk_code} => {
-            match commands::remove(&filepath, &chunk_code) {
-                Ok(chunk) => {
-                    println!("Removed chunk container (code: {})", str::from_utf8(&chunk.chunk_type().bytes()).unwrap());
-                    Ok(())
+        Commands::Watermark { filepath, recipients, key, output } => {
+            for (recipient, path) in commands::watermark(&filepath, &recipients, &key, &output, &limits, backup)? {
+                println!("Watermarked copy for {0} written to {1}", recipient, path.display());
+            }
+            Ok(())
+        },
+        Commands::Identify { filepath, recipients, key } => {
+            let (recipient, distance, agreement) = commands::identify(&filepath, &recipients, &key, &limits)?;
+            println!("Copy of {0} ({1} of 64 fingerprint bits off, {2:.1}% of marks agree)", recipient, distance, agreement * 100.0);
+            Ok(())
+        },
+        Commands::Hide { filepath, message, ecc, interleave, output } => {
+            let fec_params = ecc.map(|parity| fec::FecParams::new(parity, interleave)).transpose()?;
+            let save = commands::Save { output, backup };
+            let (used, capacity) = commands::hide(&filepath, &message, fec_params, &limits, &save)?;
+            status(
+                save.writes_to_stdout(&filepath),
+                format!("Message hidden in the pixels of {0} ({1} of {2} bytes used)", save.destination(&filepath).display(), used, capacity),
+            );
+            Ok(())
+        },
+        Commands::Reveal { filepath } => {
+            let (text, corrected) = commands::reveal(&filepath, &limits)?;
+            println!("Message hidden in the pixels -> {}", text);
+            if let Some(count) = corrected {
+                println!("Error correction fixed {} damaged bytes", count);
+            }
+            Ok(())
+        },
+        Commands::Patch { action: PatchAction::Create { base, new, patch } } => {
+            let save = commands::Save { output: Some(patch), backup };
+            let created = commands::patch_create(&base, &new, &limits, &save)?;
+            let (inserted, deleted, replaced) = created.counts();
+            status(
+                save.writes_to_stdout(&base),
+                format!("Patch of {0} operations written ({1} inserted, {2} deleted, {3} replaced chunks)", created.operations().len(), inserted, deleted, replaced),
+            );
+            Ok(())
+        },
+        Commands::Patch { action: PatchAction::Apply { filepath, patch, output } } => {
+            let save = commands::Save { output, backup };
+            let (inserted, deleted, replaced) = commands::patch_apply(&filepath, &patch, &limits, &save)?;
+            status(save.writes_to_stdout(&filepath), format!("Applied patch: {0} inserted, {1} deleted, {2} replaced chunks", inserted, deleted, replaced));
+            Ok(())
+        },
+        Commands::Frames { action: FramesAction::Extract { filepath, dir } } => {
+            let frames = commands::frames_extract(&filepath, &dir, &limits)?;
+            for (number, (path, control)) in frames.iter().enumerate() {
+                println!(
+                    "Frame {0} ({1}x{2} at {3},{4}, shown {5:.3}s) written to {6}",
+                    number, control.width, control.height, control.x, control.y, control.delay_seconds(), path.display(),
+                );
+            }
+            Ok(())
+        },
+        Commands::Frames { action: FramesAction::Build { frames, output, delay, plays } } => {
+            let save = commands::Save { output: Some(output), backup };
+            let png = commands::frames_build(&frames, &delay, plays, &limits, &save)?;
+            status(save.writes_to_stdout(&frames[0]), format!("Built an animation of {0} frames ({1} bytes)", frames.len(), png.as_bytes().len()));
+            Ok(())
+        },
+        Commands::Unpack { filepath, dir } => {
+            let count = commands::unpack(&filepath, &dir, &limits)?;
+            println!("Unpacked {0} chunks into {1}", count, dir.display());
+            Ok(())
+        },
+        Commands::Pack { dir, output } => {
+            let save = commands::Save { output: Some(output), backup };
+            let edited = commands::pack(&dir, &limits, &save)?;
+            let to_stderr = save.writes_to_stdout(&dir);
+            for edit in &edited {
+                match edit.crcs.0 {
+                    Some(crc) => status(to_stderr, format!("Chunk #{0} ({1}) was edited, CRC {2:#010x} -> {3:#010x}", edit.index, edit.chunk_type, crc, edit.crcs.1)),
+                    None => status(to_stderr, format!("Chunk #{0} ({1}) was added, CRC {2:#010x}", edit.index, edit.chunk_type, edit.crcs.1)),
                 }
-                Err(err) => Err(err)
             }
+            status(to_stderr, format!("Packed {0} into {1}", dir.display(), save.destination(&dir).display()));
+            Ok(())
+        },
+        Commands::Split { payload, carriers } => {
+            let id = commands::split(&payload, &carriers, &limits, backup)?;
+            println!("Split {0} into {1} volumes (volume id: {2:016x})", payload.display(), carriers.len(), id);
+            Ok(())
         },
-        Commands::Print { filepath } => {
-            commands::print(&filepath)
+        Commands::Join { filepaths, output } => {
+            let payload = commands::join(&filepaths, &limits)?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, &payload)?;
+                    println!("Joined {0} volumes into {1}", filepaths.len(), path.display());
+                },
+                None => std::io::stdout().write_all(&payload)?,
+            }
+            Ok(())
+        },
+        Commands::Share { secret, carriers, threshold } => {
+            let set_id = commands::share(&secret, &carriers, threshold, &limits, backup)?;
+            println!("Split {0} into {1} shares, any {2} of them recover it (set id: {3:016x})", secret.display(), carriers.len(), threshold, set_id);
+            Ok(())
+        },
+        Commands::Combine { filepaths, output } => {
+            let secret = commands::combine(&filepaths, &limits)?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, &secret)?;
+                    println!("Combined {0} shares into {1}", filepaths.len(), path.display());
+                },
+                None => std::io::stdout().write_all(&secret)?,
+            }
+            Ok(())
         },
     }
 }
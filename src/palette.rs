@@ -0,0 +1,363 @@
+use crate::{chunk::{u16_at, Chunk}, chunk_type::ChunkType, pixels::{Header, PixelError}, png::Png, text};
+use std::{fmt, str::FromStr};
+
+// at most this many palette entries or values are listed when a chunk is shown
+const SHOWN: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaletteError {
+    Malformed { chunk: String, length: usize },
+    MissingPalette,
+    UnexpectedPalette(u8),
+    TooManyEntries { entries: usize, bit_depth: u8 },
+    NotAllowed { chunk: String, colour_type: u8 },
+    SampleRange { chunk: String, value: u16, bit_depth: u8 },
+    PastPalette { chunk: String, entries: usize, palette: usize },
+    HistogramLength { entries: usize, palette: usize },
+    InvalidName,
+    InvalidDepth(u8),
+    DuplicateName(String),
+    Duplicate { chunk: String, index: usize },
+    Misplaced { chunk: String, index: usize, rule: &'static str },
+}
+
+impl std::error::Error for PaletteError {}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::Malformed { chunk, length } => write!(f, "{} chunk cannot hold {} bytes", chunk, length),
+            PaletteError::MissingPalette => write!(f, "Indexed image has no PLTE chunk"),
+            PaletteError::UnexpectedPalette(colour_type) => write!(f, "Images of colour type {} cannot have a PLTE chunk", colour_type),
+            PaletteError::TooManyEntries { entries, bit_depth } => write!(f, "Palette of {} entries is too large for bit depth {}", entries, bit_depth),
+            PaletteError::NotAllowed { chunk, colour_type } => write!(f, "Images of colour type {} cannot have a {} chunk", colour_type, chunk),
+            PaletteError::SampleRange { chunk, value, bit_depth } => write!(f, "{} value {} does not fit bit depth {}", chunk, value, bit_depth),
+            PaletteError::PastPalette { chunk, entries, palette } => write!(f, "{} chunk refers to {} palette entries but the palette has {}", chunk, entries, palette),
+            PaletteError::HistogramLength { entries, palette } => write!(f, "hIST chunk holds {} entries for a palette of {}", entries, palette),
+            PaletteError::InvalidName => write!(f, "Suggested palette name must be 1 to 79 Latin-1 characters without leading, trailing or double spaces"),
+            PaletteError::InvalidDepth(depth) => write!(f, "Suggested palette sample depth {} is neither 8 nor 16", depth),
+            PaletteError::DuplicateName(name) => write!(f, "Two suggested palettes are named \"{}\"", name),
+            PaletteError::Duplicate { chunk, index } => write!(f, "Chunk #{} is a second {} chunk", index, chunk),
+            PaletteError::Misplaced { chunk, index, rule } => write!(f, "{} chunk #{} has to come {}", chunk, index, rule),
+        }
+    }
+}
+
+// tRNS, the grey level or colour that is fully transparent, or the alpha of the first palette entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transparency {
+    Grey(u16),
+    Rgb([u16; 3]),
+    Alphas(Vec<u8>),
+}
+
+// bKGD, in the format of the image's samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Grey(u16),
+    Rgb([u16; 3]),
+    Index(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedEntry {
+    pub rgba: [u16; 4],
+    pub frequency: u16,
+}
+
+// sPLT, a palette for viewers that can only show a few colours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    // 8 or 16 bits per sample
+    pub depth: u8,
+    pub entries: Vec<SuggestedEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteChunk {
+    Palette(Vec<[u8; 3]>),
+    Transparency(Transparency),
+    Background(Background),
+    // how often each palette entry is used, scaled to fit 16 bits
+    Histogram(Vec<u16>),
+    Suggested(SuggestedPalette),
+}
+
+// up to SHOWN items, with a count of the ones left out
+fn listed<T>(items: &[T], show: impl Fn(&T) -> String) -> String {
+    let mut shown: Vec<String> = items.iter().take(SHOWN).map(show).collect();
+    if items.len() > SHOWN {
+        shown.push(format!("and {} more", items.len() - SHOWN));
+    }
+    shown.join(", ")
+}
+
+impl PaletteChunk {
+    // None for chunks of other types, tRNS and bKGD are read according to the colour type in `header`
+    pub fn parse(chunk: &Chunk, header: &Header) -> Option<Result<PaletteChunk, PaletteError>> {
+        let data = chunk.data();
+        let code = chunk.chunk_type().to_string();
+        let malformed = || PaletteError::Malformed { chunk: code.clone(), length: data.len() };
+        let not_allowed = || PaletteError::NotAllowed { chunk: code.clone(), colour_type: header.colour_type };
+        // 16 bit values hold samples of the image's bit depth, palette indices aside
+        let sample = |offset: usize| {
+            let value = u16_at(data, offset);
+            match header.bit_depth < 16 && value >= 1 << header.bit_depth {
+                true => Err(PaletteError::SampleRange { chunk: code.clone(), value, bit_depth: header.bit_depth }),
+                false => Ok(value),
+            }
+        };
+        let rgb = || Ok::<_, PaletteError>([sample(0)?, sample(2)?, sample(4)?]);
+
+        Some(match (&chunk.chunk_type().bytes(), header.colour_type) {
+            (b"PLTE", 0 | 4) => Err(PaletteError::UnexpectedPalette(header.colour_type)),
+            (b"PLTE", _) if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 256 * 3 => Err(malformed()),
+            (b"PLTE", 3) if data.len() / 3 > 1 << header.bit_depth => Err(PaletteError::TooManyEntries { entries: data.len() / 3, bit_depth: header.bit_depth }),
+            (b"PLTE", _) => Ok(PaletteChunk::Palette(data.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect())),
+
+            (b"tRNS", 4 | 6) => Err(not_allowed()),
+            (b"tRNS", 0) if data.len() != 2 => Err(malformed()),
+            (b"tRNS", 0) => sample(0).map(|grey| PaletteChunk::Transparency(Transparency::Grey(grey))),
+            (b"tRNS", 2) if data.len() != 6 => Err(malformed()),
+            (b"tRNS", 2) => rgb().map(|rgb| PaletteChunk::Transparency(Transparency::Rgb(rgb))),
+            (b"tRNS", _) if data.is_empty() || data.len() > 256 => Err(malformed()),
+            (b"tRNS", _) => Ok(PaletteChunk::Transparency(Transparency::Alphas(data.to_vec()))),
+
+            (b"bKGD", 0 | 4) if data.len() != 2 => Err(malformed()),
+            (b"bKGD", 0 | 4) => sample(0).map(|grey| PaletteChunk::Background(Background::Grey(grey))),
+            (b"bKGD", 2 | 6) if data.len() != 6 => Err(malformed()),
+            (b"bKGD", 2 | 6) => rgb().map(|rgb| PaletteChunk::Background(Background::Rgb(rgb))),
+            (b"bKGD", _) if data.len() != 1 => Err(malformed()),
+            (b"bKGD", _) => Ok(PaletteChunk::Background(Background::Index(data[0]))),
+
+            (b"hIST", _) if data.is_empty() || !data.len().is_multiple_of(2) || data.len() > 256 * 2 => Err(malformed()),
+            (b"hIST", _) => Ok(PaletteChunk::Histogram((0..data.len()).step_by(2).map(|offset| u16_at(data, offset)).collect())),
+
+            (b"sPLT", _) => parse_suggested(data),
+            _ => return None,
+        })
+    }
+
+    pub fn chunk_type(&self) -> &'static str {
+        match self {
+            PaletteChunk::Palette(_) => "PLTE",
+            PaletteChunk::Transparency(_) => "tRNS",
+            PaletteChunk::Background(_) => "bKGD",
+            PaletteChunk::Histogram(_) => "hIST",
+            PaletteChunk::Suggested(_) => "sPLT",
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = match self {
+            PaletteChunk::Palette(entries) => entries.concat(),
+            PaletteChunk::Transparency(Transparency::Grey(grey)) | PaletteChunk::Background(Background::Grey(grey)) => grey.to_be_bytes().to_vec(),
+            PaletteChunk::Transparency(Transparency::Rgb(rgb)) | PaletteChunk::Background(Background::Rgb(rgb)) => {
+                rgb.iter().flat_map(|value| value.to_be_bytes()).collect()
+            },
+            PaletteChunk::Transparency(Transparency::Alphas(alphas)) => alphas.clone(),
+            PaletteChunk::Background(Background::Index(index)) => vec![*index],
+            PaletteChunk::Histogram(frequencies) => frequencies.iter().flat_map(|value| value.to_be_bytes()).collect(),
+            PaletteChunk::Suggested(SuggestedPalette { name, depth, entries }) => {
+                let mut data: Vec<u8> = name.chars().map(|c| c as u8).collect();
+                data.extend([0, *depth]);
+                for SuggestedEntry { rgba, frequency } in entries {
+                    match depth {
+                        8 => data.extend(rgba.map(|value| value as u8)),
+                        _ => data.extend(rgba.iter().flat_map(|value| value.to_be_bytes())),
+                    }
+                    data.extend(frequency.to_be_bytes());
+                }
+                data
+            },
+        };
+        Chunk::new(ChunkType::from_str(self.chunk_type()).unwrap(), data)
+    }
+}
+
+fn parse_suggested(data: &[u8]) -> Result<PaletteChunk, PaletteError> {
+    let nul = data.iter().position(|&byte| byte == 0).ok_or(PaletteError::InvalidName)?;
+    let (name, rest) = (&data[..nul], &data[nul + 1..]);
+    if !text::is_keyword(name) {
+        return Err(PaletteError::InvalidName);
+    }
+    let (&depth, entries) = rest.split_first().ok_or(PaletteError::Malformed { chunk: "sPLT".to_string(), length: data.len() })?;
+    let size = match depth {
+        8 => 6,
+        16 => 10,
+        _ => return Err(PaletteError::InvalidDepth(depth)),
+    };
+    if !entries.len().is_multiple_of(size) {
+        return Err(PaletteError::Malformed { chunk: "sPLT".to_string(), length: data.len() });
+    }
+
+    let entries = entries
+        .chunks_exact(size)
+        .map(|entry| match depth {
+            8 => SuggestedEntry { rgba: [0, 1, 2, 3].map(|i| entry[i] as u16), frequency: u16_at(entry, 4) },
+            _ => SuggestedEntry { rgba: [0, 1, 2, 3].map(|i| u16_at(entry, i * 2)), frequency: u16_at(entry, 8) },
+        })
+        .collect();
+    Ok(PaletteChunk::Suggested(SuggestedPalette { name: text::latin1(name), depth, entries }))
+}
+
+impl fmt::Display for PaletteChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteChunk::Palette(entries) => {
+                write!(f, "{} entries: {}", entries.len(), listed(entries, |[r, g, b]| format!("#{:02x}{:02x}{:02x}", r, g, b)))
+            },
+            PaletteChunk::Transparency(Transparency::Grey(grey)) => write!(f, "grey {} is transparent", grey),
+            PaletteChunk::Transparency(Transparency::Rgb([r, g, b])) => write!(f, "colour {},{},{} is transparent", r, g, b),
+            PaletteChunk::Transparency(Transparency::Alphas(alphas)) => {
+                write!(f, "alpha of the first {} palette entries: {}", alphas.len(), listed(alphas, u8::to_string))
+            },
+            PaletteChunk::Background(Background::Grey(grey)) => write!(f, "background grey {}", grey),
+            PaletteChunk::Background(Background::Rgb([r, g, b])) => write!(f, "background colour {},{},{}", r, g, b),
+            PaletteChunk::Background(Background::Index(index)) => write!(f, "background palette entry {}", index),
+            PaletteChunk::Histogram(frequencies) => write!(f, "frequencies of {} palette entries: {}", frequencies.len(), listed(frequencies, u16::to_string)),
+            PaletteChunk::Suggested(SuggestedPalette { name, depth, entries }) => {
+                write!(f, "suggested palette \"{}\", {} entries of {} bit samples", name, entries.len(), depth)
+            },
+        }
+    }
+}
+
+// every palette related chunk of the file with its position, checked against the IHDR colour type and bit
+// depth and against the size of the palette, and placed where the spec wants them: PLTE before IDAT,
+// tRNS, bKGD and hIST between PLTE and IDAT, sPLT before IDAT
+pub fn palette_chunks(png: &Png) -> crate::Result<Vec<(usize, PaletteChunk)>> {
+    let header = Header::try_from(png.chunk_at(0).ok_or(PixelError::MissingHeader)?)?;
+    let mut found: Vec<(usize, PaletteChunk)> = Vec::new();
+    let mut image_data = false;
+
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        if &chunk.chunk_type().bytes() == b"IDAT" {
+            image_data = true;
+        }
+        let Some(parsed) = PaletteChunk::parse(chunk, &header) else {
+            continue;
+        };
+        let parsed = parsed?;
+        let code = parsed.chunk_type().to_string();
+        let palette = found.iter().find_map(|(_, found)| match found {
+            PaletteChunk::Palette(entries) => Some(entries.len()),
+            _ => None,
+        });
+
+        if image_data {
+            return Err(PaletteError::Misplaced { chunk: code, index, rule: "before IDAT" }.into());
+        }
+        if palette.is_none() && matches!(parsed, PaletteChunk::Transparency(_) | PaletteChunk::Background(_)) && header.colour_type == 3 {
+            return Err(PaletteError::Misplaced { chunk: code, index, rule: "after PLTE" }.into());
+        }
+        match &parsed {
+            PaletteChunk::Suggested(suggested) => {
+                let same_name = |found: &PaletteChunk| matches!(found, PaletteChunk::Suggested(other) if other.name == suggested.name);
+                if found.iter().any(|(_, found)| same_name(found)) {
+                    return Err(PaletteError::DuplicateName(suggested.name.clone()).into());
+                }
+            },
+            _ if found.iter().any(|(_, found)| found.chunk_type() == code) => {
+                return Err(PaletteError::Duplicate { chunk: code, index }.into());
+            },
+            PaletteChunk::Palette(_) if found.iter().any(|(_, found)| matches!(found, PaletteChunk::Transparency(_) | PaletteChunk::Background(_))) => {
+                return Err(PaletteError::Misplaced { chunk: code, index, rule: "before tRNS and bKGD" }.into());
+            },
+            PaletteChunk::Transparency(Transparency::Alphas(alphas)) if alphas.len() > palette.unwrap_or(0) => {
+                return Err(PaletteError::PastPalette { chunk: code, entries: alphas.len(), palette: palette.unwrap_or(0) }.into());
+            },
+            PaletteChunk::Background(Background::Index(entry)) if *entry as usize >= palette.unwrap_or(0) => {
+                return Err(PaletteError::PastPalette { chunk: code, entries: *entry as usize + 1, palette: palette.unwrap_or(0) }.into());
+            },
+            PaletteChunk::Histogram(frequencies) => match palette {
+                None => return Err(PaletteError::Misplaced { chunk: code, index, rule: "after PLTE" }.into()),
+                Some(palette) if palette != frequencies.len() => {
+                    return Err(PaletteError::HistogramLength { entries: frequencies.len(), palette }.into());
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+        found.push((index, parsed));
+    }
+
+    let has_palette = found.iter().any(|(_, found)| matches!(found, PaletteChunk::Palette(_)));
+    if header.colour_type == 3 && !has_palette {
+        return Err(PaletteError::MissingPalette.into());
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, testing::{chunk, header, module_error, still}};
+
+    fn palette_error(png: &Png) -> PaletteError {
+        module_error!(palette_chunks(png), Error::Palette)
+    }
+
+    fn round_trip(parsed: PaletteChunk, header: &Header) {
+        assert_eq!(PaletteChunk::parse(&parsed.to_chunk(), header), Some(Ok(parsed)));
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let indexed = header(3, 8);
+        round_trip(PaletteChunk::Palette(vec![[255, 0, 0], [0, 0, 255]]), &indexed);
+        round_trip(PaletteChunk::Transparency(Transparency::Alphas(vec![0, 128])), &indexed);
+        round_trip(PaletteChunk::Background(Background::Index(1)), &indexed);
+        round_trip(PaletteChunk::Histogram(vec![10, 3]), &indexed);
+        round_trip(PaletteChunk::Transparency(Transparency::Grey(3)), &header(0, 2));
+        round_trip(PaletteChunk::Background(Background::Rgb([0, 1000, 65535])), &header(6, 16));
+
+        let entries = vec![SuggestedEntry { rgba: [255, 0, 0, 255], frequency: 7 }];
+        round_trip(PaletteChunk::Suggested(SuggestedPalette { name: "web".to_string(), depth: 8, entries }), &indexed);
+        let entries = vec![SuggestedEntry { rgba: [65535, 0, 300, 65535], frequency: 1 }];
+        round_trip(PaletteChunk::Suggested(SuggestedPalette { name: "fine".to_string(), depth: 16, entries }), &header(2, 8));
+
+        assert_eq!(PaletteChunk::parse(&chunk("tEXt", b"Title\0Dice"), &indexed), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let palette = PaletteChunk::Palette((0..10).map(|i| [i, 0, 255]).collect());
+        assert_eq!(palette.to_string(), "10 entries: #0000ff, #0100ff, #0200ff, #0300ff, #0400ff, #0500ff, #0600ff, #0700ff, and 2 more");
+        assert_eq!(PaletteChunk::Background(Background::Index(3)).to_string(), "background palette entry 3");
+    }
+
+    #[test]
+    fn test_chunks_against_header() {
+        let parse = |code: &str, data: &[u8], header: &Header| PaletteChunk::parse(&chunk(code, data), header).unwrap();
+
+        assert_eq!(parse("PLTE", &[0; 3], &header(0, 8)), Err(PaletteError::UnexpectedPalette(0)));
+        assert_eq!(parse("PLTE", &[0; 15], &header(3, 2)), Err(PaletteError::TooManyEntries { entries: 5, bit_depth: 2 }));
+        assert_eq!(parse("PLTE", &[0; 4], &header(3, 8)), Err(PaletteError::Malformed { chunk: "PLTE".to_string(), length: 4 }));
+        assert_eq!(parse("tRNS", &[0; 2], &header(6, 8)), Err(PaletteError::NotAllowed { chunk: "tRNS".to_string(), colour_type: 6 }));
+        assert_eq!(parse("tRNS", &[0, 4], &header(0, 2)), Err(PaletteError::SampleRange { chunk: "tRNS".to_string(), value: 4, bit_depth: 2 }));
+        assert_eq!(parse("bKGD", &[0; 2], &header(2, 8)), Err(PaletteError::Malformed { chunk: "bKGD".to_string(), length: 2 }));
+        assert_eq!(parse("sPLT", b"web\0\x04", &header(2, 8)), Err(PaletteError::InvalidDepth(4)));
+    }
+
+    #[test]
+    fn test_file_rules() {
+        let plte = || chunk("PLTE", &[0; 6]);
+        let found = palette_chunks(&still(header(3, 8), vec![plte(), chunk("tRNS", &[0]), chunk("bKGD", &[1]), chunk("hIST", &[0, 1, 0, 2])])).unwrap();
+        assert_eq!(found.iter().map(|(_, found)| found.chunk_type()).collect::<Vec<_>>(), ["PLTE", "tRNS", "bKGD", "hIST"]);
+
+        assert_eq!(palette_error(&still(header(3, 8), vec![])), PaletteError::MissingPalette);
+        assert_eq!(palette_error(&still(header(3, 8), vec![plte(), chunk("tRNS", &[0; 3])])), PaletteError::PastPalette { chunk: "tRNS".to_string(), entries: 3, palette: 2 });
+        assert_eq!(palette_error(&still(header(3, 8), vec![plte(), chunk("bKGD", &[2])])), PaletteError::PastPalette { chunk: "bKGD".to_string(), entries: 3, palette: 2 });
+        assert_eq!(palette_error(&still(header(3, 8), vec![plte(), chunk("hIST", &[0; 2])])), PaletteError::HistogramLength { entries: 1, palette: 2 });
+        assert_eq!(palette_error(&still(header(3, 8), vec![chunk("tRNS", &[0]), plte()])), PaletteError::Misplaced { chunk: "tRNS".to_string(), index: 1, rule: "after PLTE" });
+        assert_eq!(palette_error(&still(header(2, 8), vec![chunk("hIST", &[0; 2])])), PaletteError::Misplaced { chunk: "hIST".to_string(), index: 1, rule: "after PLTE" });
+        assert_eq!(palette_error(&still(header(2, 8), vec![chunk("bKGD", &[0; 6]), plte()])), PaletteError::Misplaced { chunk: "PLTE".to_string(), index: 2, rule: "before tRNS and bKGD" });
+        assert_eq!(palette_error(&still(header(2, 8), vec![chunk("sPLT", b"web\0\x08"), chunk("sPLT", b"web\0\x10")])), PaletteError::DuplicateName("web".to_string()));
+
+        let mut late = still(header(0, 8), vec![]);
+        late.append_chunk(chunk("bKGD", &[0, 1]));
+        assert_eq!(palette_error(&late), PaletteError::Misplaced { chunk: "bKGD".to_string(), index: 2, rule: "before IDAT" });
+    }
+}
@@ -0,0 +1,374 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, diff, limits::Limits, png::Png, volume::sha256};
+use std::{collections::{HashMap, VecDeque}, fmt};
+
+// A patch turns one exact base file into one exact result:
+//
+//   magic (8) | version (1) | SHA-256 of the base file (32) | SHA-256 of the result (32) | operation count (4)
+//
+// followed by the operations, which walk the base chunks in order:
+//
+//   0 keep    | count (4)                                       copies the next chunks of the base
+//   1 delete  | chunk hash (32)                                 skips the next base chunk
+//   2 replace | chunk hash (32) | type (4) | length (4) | data   skips the next base chunk, writes a new one
+//   3 insert  | type (4) | length (4) | data                    writes a new chunk
+//
+// chunk hashes are SHA-256 over the chunk type and data, CRCs are recomputed when the chunks are written
+
+pub const PATCH_MAGIC: [u8; 8] = *b"\x89PNGPAT\n";
+pub const PATCH_VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Operation {
+    Keep(u32),
+    Delete([u8; 32]),
+    Replace([u8; 32], Chunk),
+    Insert(Chunk),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Patch {
+    base_hash: [u8; 32],
+    result_hash: [u8; 32],
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    NotAPatch,
+    UnsupportedVersion(u8),
+    Truncated(usize),
+    UnknownOperation(u8, usize),
+    WrongBase,
+    ChunkMismatch(usize),
+    OutOfStep(usize),
+    ResultMismatch,
+}
+
+impl std::error::Error for PatchError {}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::NotAPatch => write!(f, "File does not start with the patch signature"),
+            PatchError::UnsupportedVersion(version) => write!(f, "Patch version {} is not supported (expected {})", version, PATCH_VERSION),
+            PatchError::Truncated(offset) => write!(f, "Patch ends early at byte {}", offset),
+            PatchError::UnknownOperation(tag, offset) => write!(f, "Unknown patch operation {} at byte {}", tag, offset),
+            PatchError::WrongBase => write!(f, "Patch was made for a different base file"),
+            PatchError::ChunkMismatch(index) => write!(f, "Chunk #{} of the base does not match the chunk the patch expects", index),
+            PatchError::OutOfStep(count) => write!(f, "Patch operations do not line up with the {} chunks of the base", count),
+            PatchError::ResultMismatch => write!(f, "Patched file does not match the SHA-256 hash stored in the patch"),
+        }
+    }
+}
+
+pub fn chunk_hash(chunk: &Chunk) -> [u8; 32] {
+    let mut bytes = chunk.chunk_type().bytes().to_vec();
+    bytes.extend_from_slice(chunk.data());
+    sha256(&bytes)
+}
+
+fn copy(chunk: &Chunk) -> Chunk {
+    Chunk::new(chunk.chunk_type().clone(), chunk.data().to_vec())
+}
+
+// consecutive kept chunks share a single operation
+fn keep(operations: &mut Vec<Operation>) {
+    match operations.last_mut() {
+        Some(Operation::Keep(count)) => *count += 1,
+        _ => operations.push(Operation::Keep(1)),
+    }
+}
+
+fn chunk_bytes(chunk: &Chunk) -> impl Iterator<Item = u8> + '_ {
+    chunk.chunk_type().bytes().into_iter().chain(chunk.length().to_be_bytes()).chain(chunk.data().iter().copied())
+}
+
+// a cursor over the patch bytes that reports where it ran out
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.offset.checked_add(count).filter(|&end| end <= self.bytes.len()).ok_or(PatchError::Truncated(self.bytes.len()))?;
+        let taken = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], PatchError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, PatchError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn chunk(&mut self, limits: &Limits) -> crate::Result<Chunk> {
+        let chunk_type = ChunkType::try_from(self.array::<4>()?)?;
+        let length = self.u32()?;
+        limits.check_chunk_length(length)?;
+        Ok(Chunk::new(chunk_type, self.take(length as usize)?.to_vec()))
+    }
+}
+
+impl Patch {
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = PATCH_MAGIC.to_vec();
+        bytes.push(PATCH_VERSION);
+        bytes.extend(self.base_hash);
+        bytes.extend(self.result_hash);
+        bytes.extend((self.operations.len() as u32).to_be_bytes());
+
+        for operation in &self.operations {
+            match operation {
+                Operation::Keep(count) => {
+                    bytes.push(0);
+                    bytes.extend(count.to_be_bytes());
+                },
+                Operation::Delete(hash) => {
+                    bytes.push(1);
+                    bytes.extend(hash);
+                },
+                Operation::Replace(hash, chunk) => {
+                    bytes.push(2);
+                    bytes.extend(hash);
+                    bytes.extend(chunk_bytes(chunk));
+                },
+                Operation::Insert(chunk) => {
+                    bytes.push(3);
+                    bytes.extend(chunk_bytes(chunk));
+                },
+            }
+        }
+        bytes
+    }
+
+    pub fn try_from_with_limits(bytes: &[u8], limits: &Limits) -> crate::Result<Patch> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.array::<8>().ok() != Some(PATCH_MAGIC) {
+            return Err(PatchError::NotAPatch.into());
+        }
+        let version = reader.array::<1>()?[0];
+        if version != PATCH_VERSION {
+            return Err(PatchError::UnsupportedVersion(version).into());
+        }
+
+        let base_hash = reader.array()?;
+        let result_hash = reader.array()?;
+        let count = reader.u32()?;
+
+        // the count is not trusted for the allocation, every operation takes at least 5 bytes
+        let mut operations = Vec::with_capacity((count as usize).min(bytes.len() / 5));
+        for _ in 0..count {
+            let offset = reader.offset;
+            let operation = match reader.array::<1>()?[0] {
+                0 => Operation::Keep(reader.u32()?),
+                1 => Operation::Delete(reader.array()?),
+                2 => Operation::Replace(reader.array()?, reader.chunk(limits)?),
+                3 => Operation::Insert(reader.chunk(limits)?),
+                tag => return Err(PatchError::UnknownOperation(tag, offset).into()),
+            };
+            operations.push(operation);
+        }
+        if reader.offset != bytes.len() {
+            return Err(crate::Error::TrailingBytes(bytes.len() - reader.offset));
+        }
+
+        Ok(Patch { base_hash, result_hash, operations })
+    }
+
+    // chunks inserted, deleted and replaced
+    pub fn counts(&self) -> (usize, usize, usize) {
+        self.operations.iter().fold((0, 0, 0), |(inserted, deleted, replaced), operation| match operation {
+            Operation::Keep(_) => (inserted, deleted, replaced),
+            Operation::Delete(_) => (inserted, deleted + 1, replaced),
+            Operation::Replace(..) => (inserted, deleted, replaced + 1),
+            Operation::Insert(_) => (inserted + 1, deleted, replaced),
+        })
+    }
+
+    // the base is checked as a whole before anything else, each chunk a deletion or replacement
+    // names is checked again on the way and the result has to come out exactly as it was made
+    pub fn apply(&self, base: &Png) -> Result<Png, PatchError> {
+        if sha256(&base.as_bytes()) != self.base_hash {
+            return Err(PatchError::WrongBase);
+        }
+
+        let count = base.chunks().len();
+        let mut next = 0;
+        let mut take = |expected: Option<&[u8; 32]>| -> Result<&Chunk, PatchError> {
+            let chunk = base.chunk_at(next).ok_or(PatchError::OutOfStep(count))?;
+            if expected.is_some_and(|hash| *hash != chunk_hash(chunk)) {
+                return Err(PatchError::ChunkMismatch(next));
+            }
+            next += 1;
+            Ok(chunk)
+        };
+
+        let mut chunks = Vec::new();
+        for operation in &self.operations {
+            match operation {
+                Operation::Keep(kept) => {
+                    for _ in 0..*kept {
+                        chunks.push(copy(take(None)?));
+                    }
+                },
+                Operation::Delete(hash) => {
+                    take(Some(hash))?;
+                },
+                Operation::Replace(hash, chunk) => {
+                    take(Some(hash))?;
+                    chunks.push(copy(chunk));
+                },
+                Operation::Insert(chunk) => chunks.push(copy(chunk)),
+            }
+        }
+        if next != count {
+            return Err(PatchError::OutOfStep(count));
+        }
+
+        let png = Png::from_chunks(chunks);
+        if sha256(&png.as_bytes()) != self.result_hash {
+            return Err(PatchError::ResultMismatch);
+        }
+        Ok(png)
+    }
+}
+
+// identical chunks are paired up, the n-th copy in one file with the n-th in the other, and the largest
+// set of pairs in the same order is kept; what lies between two kept chunks is deleted and inserted,
+// or replaced where a deleted and an inserted chunk line up and share a type
+pub fn create(base: &Png, new: &Png) -> Patch {
+    let mut positions: HashMap<[u8; 32], VecDeque<usize>> = HashMap::new();
+    for (index, chunk) in base.chunks().iter().enumerate() {
+        positions.entry(chunk_hash(chunk)).or_default().push_back(index);
+    }
+
+    let pairs: Vec<(usize, usize)> = new
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter_map(|(to, chunk)| Some((positions.get_mut(&chunk_hash(chunk))?.pop_front()?, to)))
+        .collect();
+    let sources: Vec<usize> = pairs.iter().map(|&(from, _)| from).collect();
+    let kept = diff::longest_increasing(&sources).into_iter().map(|position| pairs[position]);
+
+    let (base_count, new_count) = (base.chunks().len(), new.chunks().len());
+    let mut operations = Vec::new();
+    let (mut from, mut to) = (0, 0);
+
+    for (next_from, next_to) in kept.chain([(base_count, new_count)]) {
+        let deleted = &base.chunks()[from..next_from];
+        let inserted = &new.chunks()[to..next_to];
+
+        for offset in 0..deleted.len().max(inserted.len()) {
+            match (deleted.get(offset), inserted.get(offset)) {
+                (Some(old), Some(chunk)) if old.chunk_type() == chunk.chunk_type() => {
+                    operations.push(Operation::Replace(chunk_hash(old), copy(chunk)));
+                },
+                (old, chunk) => {
+                    if let Some(old) = old {
+                        operations.push(Operation::Delete(chunk_hash(old)));
+                    }
+                    if let Some(chunk) = chunk {
+                        operations.push(Operation::Insert(copy(chunk)));
+                    }
+                },
+            }
+        }
+
+        if next_from < base_count {
+            keep(&mut operations);
+        }
+        (from, to) = (next_from + 1, next_to + 1);
+    }
+
+    Patch { base_hash: sha256(&base.as_bytes()), result_hash: sha256(&new.as_bytes()), operations }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::chunk;
+
+    fn base() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Title\0Dice"),
+            chunk("gAMA", &[0; 4]),
+            chunk("IDAT", &[1; 64]),
+            chunk("IDAT", &[2; 64]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn edited() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Title\0Two dice"),
+            chunk("IDAT", &[1; 64]),
+            chunk("IDAT", &[2; 64]),
+            chunk("ruSt", b"hi"),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_create_and_apply() {
+        let patch = create(&base(), &edited());
+
+        assert_eq!(patch.counts(), (1, 1, 1));
+        assert!(matches!(patch.operations(), [
+            Operation::Keep(1),
+            Operation::Replace(..),
+            Operation::Delete(_),
+            Operation::Keep(2),
+            Operation::Insert(_),
+            Operation::Keep(1),
+        ]));
+        assert_eq!(patch.apply(&base()).unwrap().as_bytes(), edited().as_bytes());
+    }
+
+    #[test]
+    fn test_round_trip_bytes() {
+        let patch = create(&base(), &edited());
+        let bytes = patch.as_bytes();
+
+        assert_eq!(Patch::try_from_with_limits(&bytes, &Limits::default()).unwrap(), patch);
+        // the image data is kept, not carried, so the patch stays small
+        assert!(bytes.len() < edited().as_bytes().len());
+    }
+
+    #[test]
+    fn test_identical_files() {
+        let patch = create(&base(), &base());
+        assert_eq!(patch.operations(), [Operation::Keep(6)]);
+        assert_eq!(patch.apply(&base()).unwrap().as_bytes(), base().as_bytes());
+    }
+
+    #[test]
+    fn test_refuses_wrong_base() {
+        let patch = create(&base(), &edited());
+
+        assert_eq!(patch.apply(&edited()).unwrap_err(), PatchError::WrongBase);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let bytes = create(&base(), &edited()).as_bytes();
+        let limits = Limits::default();
+
+        assert!(matches!(Patch::try_from_with_limits(b"\x89PNG\r\n\x1a\n", &limits), Err(crate::Error::Patch(PatchError::NotAPatch))));
+        assert!(matches!(Patch::try_from_with_limits(&bytes[..bytes.len() - 1], &limits), Err(crate::Error::Patch(PatchError::Truncated(_)))));
+
+        let mut newer = bytes.clone();
+        newer[8] = 2;
+        assert!(matches!(Patch::try_from_with_limits(&newer, &limits), Err(crate::Error::Patch(PatchError::UnsupportedVersion(2)))));
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{chunk::{crc32, Chunk}, chunk_type::ChunkType, png::Png};
+use std::{collections::BTreeMap, fmt};
+
+// the PNG spec caps a chunk's data length at 2^31 - 1 bytes
+pub const MAX_CHUNK_LENGTH: usize = i32::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    sequence: u32,
+    count: u32,
+    total_length: u64,
+    checksum: u32,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PayloadError {
+    SegmentSize(usize),
+    TooManySegments(usize),
+    MalformedSegment(usize),
+    NoSegments(String),
+    InconsistentSegment(u32),
+    Incomplete { missing: Vec<u32>, duplicates: Vec<u32> },
+    LengthMismatch(u64, u64),
+    ChecksumMismatch(u32, u32),
+}
+
+impl std::error::Error for PayloadError {}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayloadError::SegmentSize(size) => write!(f, "Segment size must be between 1 and {} bytes (got {})", MAX_CHUNK_LENGTH - Segment::HEADER_LENGTH, size),
+            PayloadError::TooManySegments(count) => write!(f, "Cannot cut a payload into {} segments", count),
+            PayloadError::MalformedSegment(size) => write!(f, "Segment data too short to hold a segment header ({} < {})", size, Segment::HEADER_LENGTH),
+            PayloadError::NoSegments(code) => write!(f, "No segmented payload found in chunks of type {}", code),
+            PayloadError::InconsistentSegment(seq) => write!(f, "Segment {} disagrees with the others on count, length or checksum", seq),
+            PayloadError::Incomplete { missing, duplicates } => write!(f, "Payload is incomplete (missing segments: {:?}, duplicate segments: {:?})", missing, duplicates),
+            PayloadError::LengthMismatch(expected, actual) => write!(f, "Reassembled payload is {} bytes when it should be {} bytes", actual, expected),
+            PayloadError::ChecksumMismatch(expected, actual) => write!(f, "Reassembled payload has {:#x} as checksum when it should have {:#x}", actual, expected),
+        }
+    }
+}
+
+impl Segment {
+    // leading NUL keeps plain text messages from being mistaken for segments
+    pub const MAGIC: [u8; 4] = [0, b'S', b'E', b'G'];
+    // magic + sequence + count + total length + checksum
+    pub const HEADER_LENGTH: usize = 4 + 4 + 4 + 8 + 4;
+
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn total_length(&self) -> u64 {
+        self.total_length
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn is_segment(data: &[u8]) -> bool {
+        data.len() >= Segment::HEADER_LENGTH && data[..4] == Segment::MAGIC
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        Segment::MAGIC
+            .into_iter()
+            .chain(self.sequence.to_be_bytes())
+            .chain(self.count.to_be_bytes())
+            .chain(self.total_length.to_be_bytes())
+            .chain(self.checksum.to_be_bytes())
+            .chain(self.data.iter().copied())
+            .collect()
+    }
+
+    fn same_payload(&self, other: &Segment) -> bool {
+        self.count == other.count
+            && self.total_length == other.total_length
+            && self.checksum == other.checksum
+    }
+}
+
+impl TryFrom<&[u8]> for Segment {
+    type Error = PayloadError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if !Segment::is_segment(bytes) {
+            return Err(PayloadError::MalformedSegment(bytes.len()));
+        }
+
+        let u32_at = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+
+        Ok(Segment {
+            sequence: u32_at(4),
+            count: u32_at(8),
+            total_length: u64::from_be_bytes(bytes[12..20].try_into().unwrap()),
+            checksum: u32_at(20),
+            data: bytes[Segment::HEADER_LENGTH..].to_vec(),
+        })
+    }
+}
+
+pub fn segment(payload: &[u8], segment_size: usize) -> Result<Vec<Segment>, PayloadError> {
+    if segment_size == 0 || segment_size > MAX_CHUNK_LENGTH - Segment::HEADER_LENGTH {
+        return Err(PayloadError::SegmentSize(segment_size));
+    }
+
+    // an empty payload still needs one segment to carry its length and checksum
+    let count = payload.len().div_ceil(segment_size).max(1);
+    let count = u32::try_from(count).map_err(|_| PayloadError::TooManySegments(count))?;
+
+    Ok(cut(payload, count, segment_size))
+}
+
+pub fn segment_evenly(payload: &[u8], count: u32) -> Result<Vec<Segment>, PayloadError> {
+    if count == 0 {
+        return Err(PayloadError::TooManySegments(0));
+    }
+
+    let segment_size = payload.len().div_ceil(count as usize).max(1);
+    if segment_size > MAX_CHUNK_LENGTH - Segment::HEADER_LENGTH {
+        return Err(PayloadError::SegmentSize(segment_size));
+    }
+
+    // segments past the end of a short payload are left empty rather than dropped
+    Ok(cut(payload, count, segment_size))
+}
+
+fn cut(payload: &[u8], count: u32, segment_size: usize) -> Vec<Segment> {
+    let checksum = crc32(payload);
+
+    (0..count)
+        .map(|sequence| {
+            let start = (sequence as usize * segment_size).min(payload.len());
+            let end = (start + segment_size).min(payload.len());
+
+            Segment {
+                sequence,
+                count,
+                total_length: payload.len() as u64,
+                checksum,
+                data: payload[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+pub fn split(chunk_type: &ChunkType, payload: &[u8], segment_size: usize) -> Result<Vec<Chunk>, PayloadError> {
+    Ok(segment(payload, segment_size)?
+        .into_iter()
+        .map(|segment| Chunk::new(chunk_type.clone(), segment.as_bytes()))
+        .collect())
+}
+
+pub fn join(segments: Vec<Segment>) -> Result<Vec<u8>, PayloadError> {
+    let first = match segments.first() {
+        Some(segment) => segment.clone(),
+        None => return Err(PayloadError::Incomplete { missing: vec![0], duplicates: Vec::new() }),
+    };
+
+    let mut ordered: BTreeMap<u32, Segment> = BTreeMap::new();
+    let mut duplicates: Vec<u32> = Vec::new();
+
+    for segment in segments {
+        if !segment.same_payload(&first) || segment.sequence >= first.count {
+            return Err(PayloadError::InconsistentSegment(segment.sequence));
+        }
+
+        let sequence = segment.sequence;
+        if ordered.insert(sequence, segment).is_some() && !duplicates.contains(&sequence) {
+            duplicates.push(sequence);
+        }
+    }
+
+    let missing: Vec<u32> = (0..first.count).filter(|seq| !ordered.contains_key(seq)).collect();
+    if !missing.is_empty() || !duplicates.is_empty() {
+        duplicates.sort_unstable();
+        return Err(PayloadError::Incomplete { missing, duplicates });
+    }
+
+    let payload: Vec<u8> = ordered.into_values().flat_map(|segment| segment.data).collect();
+
+    if payload.len() as u64 != first.total_length {
+        return Err(PayloadError::LengthMismatch(first.total_length, payload.len() as u64));
+    }
+
+    let checksum = crc32(&payload);
+    if checksum != first.checksum {
+        return Err(PayloadError::ChecksumMismatch(first.checksum, checksum));
+    }
+
+    Ok(payload)
+}
+
+pub fn is_segmented(png: &Png, chunk_type: &str) -> bool {
+    png.chunks().iter().any(|chunk| {
+        chunk.chunk_type().to_string() == chunk_type && Segment::is_segment(chunk.data())
+    })
+}
+
+pub fn reassemble(png: &Png, chunk_type: &str) -> Result<Vec<u8>, PayloadError> {
+    let segments = png.chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type().to_string() == chunk_type)
+        .filter(|chunk| Segment::is_segment(chunk.data()))
+        .map(|chunk| Segment::try_from(chunk.data()))
+        .collect::<Result<Vec<Segment>, PayloadError>>()?;
+
+    if segments.is_empty() {
+        return Err(PayloadError::NoSegments(chunk_type.to_string()));
+    }
+
+    join(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const PAYLOAD: &[u8] = b"This payload is long enough to be cut into several segments";
+
+    fn testing_png(chunks: Vec<Chunk>) -> Png {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        for chunk in chunks {
+            png.append_chunk(chunk);
+        }
+        png
+    }
+
+    #[test]
+    fn test_segment_round_trip() {
+        let segments = segment(PAYLOAD, 8).unwrap();
+        assert_eq!(segments.len(), 8);
+
+        let bytes = segments[3].as_bytes();
+        assert!(Segment::is_segment(&bytes));
+        assert_eq!(Segment::try_from(bytes.as_slice()).unwrap(), segments[3]);
+    }
+
+    #[test]
+    fn test_invalid_segment_size() {
+        assert_eq!(segment(PAYLOAD, 0), Err(PayloadError::SegmentSize(0)));
+    }
+
+    #[test]
+    fn test_segment_evenly() {
+        let segments = segment_evenly(b"abc", 5).unwrap();
+        assert_eq!(segments.len(), 5);
+        assert!(segments[3].data().is_empty());
+        assert_eq!(join(segments).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        let segments = segment(b"", 8).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(join(segments).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut chunks = split(&chunk_type, PAYLOAD, 10).unwrap();
+        chunks.reverse();
+
+        let png = testing_png(chunks);
+        assert!(is_segmented(&png, "ruSt"));
+        assert_eq!(reassemble(&png, "ruSt").unwrap(), PAYLOAD);
+    }
+
+    #[test]
+    fn test_reassemble_ignores_plain_chunks() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut chunks = vec![Chunk::new(chunk_type.clone(), b"plain message".to_vec())];
+        chunks.extend(split(&chunk_type, PAYLOAD, 16).unwrap());
+
+        let png = testing_png(chunks);
+        assert_eq!(reassemble(&png, "ruSt").unwrap(), PAYLOAD);
+    }
+
+    #[test]
+    fn test_reassemble_missing_and_duplicate() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut chunks = split(&chunk_type, PAYLOAD, 10).unwrap();
+        chunks.remove(2);
+        chunks.push(Chunk::new(chunk_type.clone(), chunks[0].data().to_vec()));
+
+        let png = testing_png(chunks);
+        assert_eq!(
+            reassemble(&png, "ruSt"),
+            Err(PayloadError::Incomplete { missing: vec![2], duplicates: vec![0] })
+        );
+    }
+
+    #[test]
+    fn test_reassemble_checksum_mismatch() {
+        let mut segments = segment(PAYLOAD, 10).unwrap();
+        segments[1].data[0] ^= 0xFF;
+
+        assert!(matches!(join(segments), Err(PayloadError::ChecksumMismatch(_, _))));
+    }
+
+    #[test]
+    fn test_reassemble_without_segments() {
+        let png = testing_png(vec![Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"plain".to_vec())]);
+        assert!(!is_segmented(&png, "ruSt"));
+        assert_eq!(reassemble(&png, "ruSt"), Err(PayloadError::NoSegments("ruSt".to_string())));
+    }
+}
@@ -0,0 +1,533 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, limits::{self, Limits}, png::Png};
+use flate2::{write::ZlibEncoder, Compression};
+use std::{fmt, io::Write, str::FromStr};
+
+// Adam7 passes as (first column, first row, column step, row step)
+const ADAM7: [(u32, u32, u32, u32); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PixelError {
+    MissingHeader,
+    InvalidHeader(usize),
+    InvalidDimensions(u32, u32),
+    InvalidFormat { bit_depth: u8, colour_type: u8 },
+    UnsupportedMethod(&'static str, u8),
+    MissingPalette,
+    NoImageData,
+    CorruptData,
+    ShortImageData { expected: usize, found: usize },
+    InvalidFilter(u8),
+    PaletteIndex(u8, usize),
+}
+
+impl std::error::Error for PixelError {}
+
+impl fmt::Display for PixelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PixelError::MissingHeader => write!(f, "Image does not start with an IHDR chunk"),
+            PixelError::InvalidHeader(length) => write!(f, "IHDR holds {} bytes instead of 13", length),
+            PixelError::InvalidDimensions(width, height) => write!(f, "Image size {}x{} is not allowed", width, height),
+            PixelError::InvalidFormat { bit_depth, colour_type } => write!(f, "Bit depth {} is not allowed for colour type {}", bit_depth, colour_type),
+            PixelError::UnsupportedMethod(method, value) => write!(f, "Unknown {} method {}", method, value),
+            PixelError::MissingPalette => write!(f, "Indexed image has no PLTE chunk"),
+            PixelError::NoImageData => write!(f, "Image has no IDAT chunk"),
+            PixelError::CorruptData => write!(f, "Image data is not a valid zlib stream"),
+            PixelError::ShortImageData { expected, found } => write!(f, "Image data inflates to {} bytes, {} are needed", found, expected),
+            PixelError::InvalidFilter(filter) => write!(f, "Unknown scanline filter {}", filter),
+            PixelError::PaletteIndex(index, count) => write!(f, "Palette index {} is past the {} palette entries", index, count),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub colour_type: u8,
+    pub interlaced: bool,
+}
+
+impl TryFrom<&Chunk> for Header {
+    type Error = PixelError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if &chunk.chunk_type().bytes() != b"IHDR" {
+            return Err(PixelError::MissingHeader);
+        }
+        let data: &[u8; 13] = chunk.data().try_into().map_err(|_| PixelError::InvalidHeader(chunk.data().len()))?;
+
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+            return Err(PixelError::InvalidDimensions(width, height));
+        }
+
+        let [bit_depth, colour_type, compression, filter, interlace] = data[8..].try_into().unwrap();
+        let allowed: &[u8] = match colour_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => &[],
+        };
+        if !allowed.contains(&bit_depth) {
+            return Err(PixelError::InvalidFormat { bit_depth, colour_type });
+        }
+        if compression != 0 {
+            return Err(PixelError::UnsupportedMethod("compression", compression));
+        }
+        if filter != 0 {
+            return Err(PixelError::UnsupportedMethod("filter", filter));
+        }
+        if interlace > 1 {
+            return Err(PixelError::UnsupportedMethod("interlace", interlace));
+        }
+
+        Ok(Header { width, height, bit_depth, colour_type, interlaced: interlace == 1 })
+    }
+}
+
+impl Header {
+    pub fn channels(&self) -> usize {
+        match self.colour_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    pub fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.width.to_be_bytes().to_vec();
+        data.extend(self.height.to_be_bytes());
+        data.extend([self.bit_depth, self.colour_type, 0, 0, self.interlaced as u8]);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+
+    // the pass holding the pixel at `x`, `y`, with its column and row inside that pass
+    pub fn locate(&self, x: u32, y: u32) -> (usize, u32, u32) {
+        if !self.interlaced {
+            return (0, x, y);
+        }
+        let pass = ADAM7.iter().position(|&(x0, y0, dx, dy)| x >= x0 && y >= y0 && (x - x0).is_multiple_of(dx) && (y - y0).is_multiple_of(dy)).unwrap();
+        let (x0, y0, dx, dy) = ADAM7[pass];
+        (pass, (x - x0) / dx, (y - y0) / dy)
+    }
+
+    // the passes the image data is split into, a single one unless interlaced
+    pub fn passes(&self) -> Vec<Pass> {
+        let passes: &[(u32, u32, u32, u32)] = if self.interlaced { &ADAM7 } else { &[(0, 0, 1, 1)] };
+
+        passes
+            .iter()
+            .map(|&(x, y, dx, dy)| {
+                let width = self.width.saturating_sub(x).div_ceil(dx);
+                let height = self.height.saturating_sub(y).div_ceil(dy);
+                Pass { origin: (x, y), step: (dx, dy), width, height, rows: Vec::new() }
+            })
+            .collect()
+    }
+}
+
+// the unfiltered scanlines of one pass, `rows` holds `height` rows of `row_bytes(width)` bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pass {
+    pub origin: (u32, u32),
+    pub step: (u32, u32),
+    pub width: u32,
+    pub height: u32,
+    pub rows: Vec<u8>,
+}
+
+impl Pass {
+    // image coordinates of the pixel at `column`, `row` of this pass
+    pub fn position(&self, column: u32, row: u32) -> (u32, u32) {
+        (self.origin.0 + column * self.step.0, self.origin.1 + row * self.step.1)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// the byte filters predict from: left (a), above (b) and above left (c)
+fn predictor(filter: u8, a: u8, b: u8, c: u8) -> u8 {
+    match filter {
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => paeth(a, b, c),
+        _ => 0,
+    }
+}
+
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), PixelError> {
+    if filter > 4 {
+        return Err(PixelError::InvalidFilter(filter));
+    }
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+        row[i] = row[i].wrapping_add(predictor(filter, a, previous[i], c));
+    }
+    Ok(())
+}
+
+// each row gets the filter with the smallest sum of absolute differences, the usual encoder heuristic
+fn filter_row(row: &[u8], previous: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let filtered = |filter: u8| -> Vec<u8> {
+        (0..row.len())
+            .map(|i| {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let c = if i >= bpp { previous[i - bpp] } else { 0 };
+                row[i].wrapping_sub(predictor(filter, a, previous[i], c))
+            })
+            .collect()
+    };
+
+    let (filter, bytes) = (0..=4)
+        .map(|filter| (filter, filtered(filter)))
+        .min_by_key(|(_, bytes)| bytes.iter().map(|&byte| (byte as i8).unsigned_abs() as u64).sum::<u64>())
+        .unwrap();
+    out.push(filter);
+    out.extend(bytes);
+}
+
+// the compressed data of every IDAT chunk, in file order
+pub fn image_data(png: &Png) -> Result<Vec<u8>, PixelError> {
+    let chunks = png.chunks_by_type("IDAT");
+    if chunks.is_empty() {
+        return Err(PixelError::NoImageData);
+    }
+    Ok(chunks.into_iter().flat_map(|(_, chunk)| chunk.data().iter().copied()).collect())
+}
+
+pub fn inflate(header: &Header, compressed: &[u8], limits: &Limits) -> crate::Result<Vec<Pass>> {
+    let data = limits::decompress(compressed, limits).map_err(|err| match err {
+        crate::Error::Io(_) => PixelError::CorruptData.into(),
+        err => err,
+    })?;
+    let bpp = header.bits_per_pixel().div_ceil(8);
+    let mut passes = header.passes();
+
+    let expected = passes.iter().filter(|pass| !pass.is_empty()).map(|pass| pass.height as usize * (header.row_bytes(pass.width) + 1)).sum();
+    if data.len() < expected {
+        return Err(PixelError::ShortImageData { expected, found: data.len() }.into());
+    }
+
+    let mut offset = 0;
+    for pass in passes.iter_mut().filter(|pass| !pass.is_empty()) {
+        let row_bytes = header.row_bytes(pass.width);
+        let mut rows = vec![0u8; pass.height as usize * row_bytes];
+        let mut previous = vec![0u8; row_bytes];
+
+        for row in rows.chunks_exact_mut(row_bytes) {
+            let filter = data[offset];
+            row.copy_from_slice(&data[offset + 1..offset + 1 + row_bytes]);
+            unfilter(filter, row, &previous, bpp)?;
+            previous.copy_from_slice(row);
+            offset += row_bytes + 1;
+        }
+        pass.rows = rows;
+    }
+
+    Ok(passes)
+}
+
+pub fn deflate(header: &Header, passes: &[Pass]) -> Vec<u8> {
+    let bpp = header.bits_per_pixel().div_ceil(8);
+    let mut filtered = Vec::new();
+
+    for pass in passes.iter().filter(|pass| !pass.is_empty()) {
+        let row_bytes = header.row_bytes(pass.width);
+        let mut previous = vec![0u8; row_bytes];
+        for row in pass.rows.chunks_exact(row_bytes) {
+            filter_row(row, &previous, bpp, &mut filtered);
+            previous.copy_from_slice(row);
+        }
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&filtered).unwrap();
+    encoder.finish().unwrap()
+}
+
+// a copy of `png` with its image data replaced by `passes`, in a single IDAT where the first one was
+pub fn with_image_data(png: &Png, header: &Header, passes: &[Pass]) -> Png {
+    let first = png.chunks_by_type("IDAT")[0].0;
+    let mut chunks: Vec<Chunk> = Vec::with_capacity(png.chunks().len());
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        if index == first {
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), deflate(header, passes)));
+        } else if &chunk.chunk_type().bytes() != b"IDAT" {
+            chunks.push(Chunk::new(chunk.chunk_type().clone(), chunk.data().to_vec()));
+        }
+    }
+    Png::from_chunks(chunks)
+}
+
+// the `index`-th sample of a row, as stored
+pub fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        depth => {
+            let per_byte = 8 / depth as usize;
+            let shift = 8 - depth as usize * (index % per_byte + 1);
+            ((row[index / per_byte] >> shift) & ((1 << depth) - 1)) as u16
+        },
+    }
+}
+
+// stretches a sample to the full 16 bit range, so 0xF at depth 4 becomes 0xFFFF
+fn widen(value: u16, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => value,
+        depth => (value as u32 * 0xFFFF / ((1 << depth) - 1)) as u16,
+    }
+}
+
+// what PLTE and tRNS add to the samples
+#[derive(Debug, Clone, Default)]
+pub struct Colours {
+    pub palette: Vec<[u8; 3]>,
+    pub transparency: Vec<u8>,
+}
+
+impl Colours {
+    pub fn of(png: &Png) -> Colours {
+        let palette = png.chunk_by_type("PLTE").map(|chunk| chunk.data().chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect());
+        let transparency = png.chunk_by_type("tRNS").map(|chunk| chunk.data().to_vec());
+        Colours { palette: palette.unwrap_or_default(), transparency: transparency.unwrap_or_default() }
+    }
+
+    // the stored samples of one pixel as 16 bit RGBA
+    fn rgba(&self, header: &Header, samples: &[u16]) -> Result<[u16; 4], PixelError> {
+        let depth = header.bit_depth;
+        // tRNS names the single grey level or colour that is fully transparent
+        let keyed = |count: usize| {
+            self.transparency.len() == count * 2
+                && samples[..count].iter().enumerate().all(|(i, &value)| u16::from_be_bytes([self.transparency[i * 2], self.transparency[i * 2 + 1]]) == value)
+        };
+
+        Ok(match header.colour_type {
+            0 => {
+                let grey = widen(samples[0], depth);
+                [grey, grey, grey, if keyed(1) { 0 } else { 0xFFFF }]
+            },
+            2 => {
+                let alpha = if keyed(3) { 0 } else { 0xFFFF };
+                [widen(samples[0], depth), widen(samples[1], depth), widen(samples[2], depth), alpha]
+            },
+            3 => {
+                let index = samples[0] as u8;
+                let [r, g, b] = *self.palette.get(index as usize).ok_or(PixelError::PaletteIndex(index, self.palette.len()))?;
+                let alpha = self.transparency.get(index as usize).copied().unwrap_or(0xFF);
+                [r as u16 * 257, g as u16 * 257, b as u16 * 257, alpha as u16 * 257]
+            },
+            4 => {
+                let grey = widen(samples[0], depth);
+                [grey, grey, grey, widen(samples[1], depth)]
+            },
+            _ => [widen(samples[0], depth), widen(samples[1], depth), widen(samples[2], depth), widen(samples[3], depth)],
+        })
+    }
+}
+
+// pixels as 16 bit RGBA, row by row, whatever the format they were stored in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u16; 4]>,
+}
+
+impl Image {
+    pub fn from_passes(header: &Header, passes: &[Pass], colours: &Colours, limits: &Limits) -> crate::Result<Image> {
+        limits.check_decoded_size(header.width as u64 * header.height as u64 * 8)?;
+        if header.colour_type == 3 && colours.palette.is_empty() {
+            return Err(PixelError::MissingPalette.into());
+        }
+
+        let channels = header.channels();
+        let mut pixels = vec![[0u16; 4]; header.width as usize * header.height as usize];
+        let mut samples = vec![0u16; channels];
+
+        for pass in passes.iter().filter(|pass| !pass.is_empty()) {
+            let row_bytes = header.row_bytes(pass.width);
+            for (y, row) in pass.rows.chunks_exact(row_bytes).enumerate() {
+                for x in 0..pass.width {
+                    for (channel, value) in samples.iter_mut().enumerate() {
+                        *value = sample(row, x as usize * channels + channel, header.bit_depth);
+                    }
+                    let (column, line) = pass.position(x, y as u32);
+                    pixels[line as usize * header.width as usize + column as usize] = colours.rgba(header, &samples)?;
+                }
+            }
+        }
+
+        Ok(Image { width: header.width, height: header.height, pixels })
+    }
+
+    pub fn decode(png: &Png, limits: &Limits) -> crate::Result<Image> {
+        let header = Header::try_from(png.chunk_at(0).ok_or(PixelError::MissingHeader)?)?;
+        let passes = inflate(&header, &image_data(png)?, limits)?;
+        Image::from_passes(&header, &passes, &Colours::of(png), limits)
+    }
+
+    // 8 bits per sample when that loses nothing, 16 otherwise
+    pub fn bit_depth(&self) -> u8 {
+        if self.pixels.iter().flatten().all(|value| value % 257 == 0) { 8 } else { 16 }
+    }
+
+    // non-interlaced RGBA image data at the given bit depth, with the header it goes with
+    pub fn compress(&self, bit_depth: u8) -> (Header, Vec<u8>) {
+        let header = Header { width: self.width, height: self.height, bit_depth, colour_type: 6, interlaced: false };
+        let mut pass = header.passes().remove(0);
+        pass.rows = match bit_depth {
+            8 => self.pixels.iter().flatten().map(|value| (value / 257) as u8).collect(),
+            _ => self.pixels.iter().flatten().flat_map(|value| value.to_be_bytes()).collect(),
+        };
+        let data = deflate(&header, &[pass]);
+        (header, data)
+    }
+
+    // a non-interlaced RGBA file
+    pub fn to_png(&self) -> Png {
+        let (header, data) = self.compress(self.bit_depth());
+
+        Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), data),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::chunk;
+
+    // a PNG of the given format whose single pass holds `rows`
+    fn encoded(header: Header, rows: Vec<u8>, extra: Vec<Chunk>) -> Png {
+        let mut pass = header.passes().remove(0);
+        pass.rows = rows;
+        let mut chunks = vec![header.to_chunk()];
+        chunks.extend(extra);
+        chunks.push(chunk("IDAT", &deflate(&header, &[pass])));
+        chunks.push(chunk("IEND", &[]));
+        Png::from_chunks(chunks)
+    }
+
+    fn header(width: u32, height: u32, bit_depth: u8, colour_type: u8) -> Header {
+        Header { width, height, bit_depth, colour_type, interlaced: false }
+    }
+
+    #[test]
+    fn test_header() {
+        let header = header(3, 2, 4, 0);
+        assert_eq!(Header::try_from(&header.to_chunk()).unwrap(), header);
+        assert_eq!(header.row_bytes(3), 2);
+
+        let bad = |data: Vec<u8>| Header::try_from(&chunk("IHDR", &data)).unwrap_err();
+        assert_eq!(bad(vec![0; 12]), PixelError::InvalidHeader(12));
+        assert_eq!(bad(vec![0, 0, 0, 1, 0, 0, 0, 1, 16, 3, 0, 0, 0]), PixelError::InvalidFormat { bit_depth: 16, colour_type: 3 });
+        assert_eq!(bad(vec![0, 0, 0, 0, 0, 0, 0, 1, 8, 0, 0, 0, 0]), PixelError::InvalidDimensions(0, 1));
+    }
+
+    #[test]
+    fn test_round_trip_rgba16() {
+        let image = Image { width: 3, height: 2, pixels: (0..6).map(|i| [i * 1000, 65535 - i, i, 0x8000]).collect() };
+        assert_eq!(Image::decode(&image.to_png(), &Limits::default()).unwrap(), image);
+    }
+
+    #[test]
+    fn test_round_trip_rgba8() {
+        let image = Image { width: 2, height: 2, pixels: (0..4).map(|i| [i * 257, 0xFFFF, 0, 0x8080]).collect() };
+        let png = image.to_png();
+        assert_eq!(Header::try_from(&png.chunks()[0]).unwrap().bit_depth, 8);
+        assert_eq!(Image::decode(&png, &Limits::default()).unwrap(), image);
+    }
+
+    #[test]
+    fn test_formats_agree() {
+        // the same two pixels, red and half transparent grey, stored three ways
+        let rgba8 = encoded(header(2, 1, 8, 6), vec![255, 0, 0, 255, 128, 128, 128, 128], vec![]);
+        let palette = encoded(header(2, 1, 2, 3), vec![0b0001_0000], vec![
+            chunk("PLTE", &[255, 0, 0, 128, 128, 128]),
+            chunk("tRNS", &[255, 128]),
+        ]);
+        let rgba16 = encoded(header(2, 1, 16, 6), vec![255, 255, 0, 0, 0, 0, 255, 255, 128, 128, 128, 128, 128, 128, 128, 128], vec![]);
+
+        let expected = Image::decode(&rgba8, &Limits::default()).unwrap();
+        assert_eq!(expected.pixels, [[0xFFFF, 0, 0, 0xFFFF], [0x8080, 0x8080, 0x8080, 0x8080]]);
+        assert_eq!(Image::decode(&palette, &Limits::default()).unwrap(), expected);
+        assert_eq!(Image::decode(&rgba16, &Limits::default()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_low_bit_depth_and_transparency_key() {
+        let grey = encoded(header(4, 1, 2, 0), vec![0b0001_1011], vec![chunk("tRNS", &[0, 2])]);
+        let image = Image::decode(&grey, &Limits::default()).unwrap();
+
+        assert_eq!(image.pixels.iter().map(|pixel| pixel[0]).collect::<Vec<_>>(), [0, 0x5555, 0xAAAA, 0xFFFF]);
+        assert_eq!(image.pixels.iter().map(|pixel| pixel[3]).collect::<Vec<_>>(), [0xFFFF, 0xFFFF, 0, 0xFFFF]);
+    }
+
+    #[test]
+    fn test_interlaced() {
+        let image = Image { width: 5, height: 3, pixels: (0..15).map(|i| [i, i, i, 0xFFFF]).collect() };
+        let header = Header { interlaced: true, ..header(5, 3, 16, 6) };
+
+        let mut passes = header.passes();
+        for pass in &mut passes {
+            for y in 0..pass.height {
+                for x in 0..pass.width {
+                    let (column, line) = pass.position(x, y);
+                    pass.rows.extend(image.pixels[(line * 5 + column) as usize].iter().flat_map(|value| value.to_be_bytes()));
+                }
+            }
+        }
+        let png = Png::from_chunks(vec![header.to_chunk(), chunk("IDAT", &deflate(&header, &passes)), chunk("IEND", &[])]);
+
+        assert_eq!(Image::decode(&png, &Limits::default()).unwrap(), image);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let limits = Limits::default();
+        let indexed = encoded(header(1, 1, 8, 3), vec![3], vec![chunk("PLTE", &[0; 6])]);
+        assert!(matches!(Image::decode(&indexed, &limits), Err(crate::Error::Pixels(PixelError::PaletteIndex(3, 2)))));
+
+        let corrupt = Png::from_chunks(vec![header(1, 1, 8, 0).to_chunk(), chunk("IDAT", &[1, 2, 3]), chunk("IEND", &[])]);
+        assert!(matches!(Image::decode(&corrupt, &limits), Err(crate::Error::Pixels(PixelError::CorruptData))));
+
+        let no_data = Png::from_chunks(vec![header(1, 1, 8, 0).to_chunk(), chunk("IEND", &[])]);
+        assert!(matches!(Image::decode(&no_data, &limits), Err(crate::Error::Pixels(PixelError::NoImageData))));
+
+        let large = encoded(header(64, 64, 8, 0), vec![0; 64 * 64], vec![]);
+        assert!(matches!(Image::decode(&large, &Limits { max_decompressed_size: 8192, ..limits }), Err(crate::Error::LimitExceeded { .. })));
+    }
+}
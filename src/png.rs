@@ -1,34 +1,11 @@
-use crate::{chunk::{Chunk, ChunkError}};
-use std::{fmt, io::{Cursor, Read, SeekFrom, Seek}};
+use crate::{chunk::Chunk, chunk_type::ChunkType, error::Error, limits::Limits};
+use std::fmt;
 
 #[derive(Debug)]
 pub struct Png {
     header: [u8;8],
     chunks: Vec<Chunk>
 }
-#[derive(Debug)]
-pub enum PNGError {
-    InsufficientBytes(usize),
-    HeaderMismatch,
-    ReadErr(std::io::Error),
-    ChunkParse(ChunkError),
-    ExcessBytes
-}
-
-impl std::error::Error for PNGError {}
-
-impl fmt::Display for PNGError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            PNGError::InsufficientBytes(size) => write!(f, "Byte count insufficient to initialize a png struct (Found {}, require at least 8)", size),
-            PNGError::HeaderMismatch => write!(f, "Header does not match Png::STANDARD_HEADER"),
-            PNGError::ReadErr(err) => write!(f, "Byte reading failed. Returned: {}", err),
-            PNGError::ChunkParse(err) => write!(f, "Chunk parsing error: {}", err),
-            PNGError::ExcessBytes => write!(f, "Byte count excess the PNG spec"),
-        }
-    }
-}
-
 
 impl Png {
     pub const STANDARD_HEADER: [u8;8] = [137, 80, 78, 71, 13, 10, 26, 10];
@@ -36,23 +13,31 @@ impl Png {
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Png {
             header: Png::STANDARD_HEADER,
-            chunks: chunks
+            chunks
         }
     } 
 
     pub fn append_chunk(&mut self, chunk: Chunk) {
         // chunk should be not be appended at the end using .push
         // per the spec the final chunk should be the IEND one, integrated by default inside PNGs
-        self.chunks.insert(self.chunks.len()-1, chunk);
+        // (a png without any chunk just gets this one)
+        let is = |position: usize, code: &[u8; 4]| self.chunks.get(position).is_some_and(|chunk| &chunk.chunk_type().bytes() == code);
+        let mut position = self.chunks.iter().rposition(|chunk| &chunk.chunk_type().bytes() == b"IEND")
+            .unwrap_or(self.chunks.len().saturating_sub(1));
+        // never inside an animation frame, whose fcTL has to be followed by the frame's data
+        while position > 0 && (is(position, b"fdAT") || is(position - 1, b"fcTL")) {
+            position -= 1;
+        }
+        self.chunks.insert(position, chunk);
     }
 
-    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> Result<Chunk, ()> {
+    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> crate::Result<Chunk> {
         if let Some(i) = self.chunks.iter().position(|chunk| {
             str::from_utf8(chunk.chunk_type().bytes().as_ref()).unwrap() == chunk_type
         }) {
             Ok(self.chunks.remove(i))
         } else {
-            Err(())
+            Err(Error::ChunkNotFound(chunk_type.to_string()))
         }
     }
 
@@ -70,6 +55,38 @@ impl Png {
         })
     }
 
+    // every chunk of the given type along with its position in the file
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<(usize, &Chunk)> {
+        self.chunks.iter().enumerate().filter(|(_, chunk)| {
+            str::from_utf8(chunk.chunk_type().bytes().as_ref()).unwrap() == chunk_type
+        }).collect()
+    }
+
+    pub fn chunk_at(&self, index: usize) -> Option<&Chunk> {
+        self.chunks.get(index)
+    }
+
+    pub fn insert_at(&mut self, index: usize, chunk: Chunk) {
+        self.chunks.insert(index.min(self.chunks.len()), chunk);
+    }
+
+    pub fn remove_at(&mut self, index: usize) -> Option<Chunk> {
+        (index < self.chunks.len()).then(|| self.chunks.remove(index))
+    }
+
+    pub fn remove_all(&mut self, chunk_type: &str) -> Vec<Chunk> {
+        let (removed, kept) = std::mem::take(&mut self.chunks).into_iter().partition(|chunk| {
+            str::from_utf8(chunk.chunk_type().bytes().as_ref()).unwrap() == chunk_type
+        });
+        self.chunks = kept;
+        removed
+    }
+
+    // returns the chunk that was replaced
+    pub fn replace_at(&mut self, index: usize, chunk: Chunk) -> Option<Chunk> {
+        self.chunks.get_mut(index).map(|slot| std::mem::replace(slot, chunk))
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         self.header.iter()
             .copied()
@@ -80,46 +97,60 @@ impl Png {
 
 
 impl TryFrom<&[u8]> for Png {
-    type Error = PNGError;
+    type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Png::parse(bytes, true, &Limits::default())
+    }
+}
+
+impl Png {
+    pub fn try_from_with_limits(bytes: &[u8], limits: &Limits) -> crate::Result<Png> {
+        Png::parse(bytes, true, limits)
+    }
+
+    // accepts chunks whose CRC does not match their contents, see Chunk::try_from_unchecked
+    pub fn try_from_unchecked(bytes: &[u8], limits: &Limits) -> crate::Result<Png> {
+        Png::parse(bytes, false, limits)
+    }
+
+    fn parse(bytes: &[u8], verify_crc: bool, limits: &Limits) -> crate::Result<Png> {
+        limits.check_total_size(bytes.len() as u64)?;
         if bytes.len() < 8 {
-            return Err(PNGError::InsufficientBytes(bytes.len()));
+            return Err(Error::Truncated { needed: 8, available: bytes.len() });
         }
 
-        let mut cursor = Cursor::new(bytes);
-        let mut chunks: Vec<Chunk> = Vec::new();
+        let header: [u8;8] = bytes[..8].try_into().unwrap();
+        if header != Png::STANDARD_HEADER {
+            return Err(Error::InvalidSignature(header));
+        }
 
-        let mut header_buf: [u8;8] = [0;8];
-        let mut length_buf: [u8;4] = [0;4];
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut offset = 8;
 
-        cursor.read_exact(&mut header_buf).map_err(|e| PNGError::ReadErr(e))?;
-        if header_buf != Png::STANDARD_HEADER {
-            return Err(PNGError::HeaderMismatch);
-        }
-        
-        loop {
-            match cursor.read(&mut length_buf) {
-                Ok(0) => break,
-                Ok(n) => {
-                    if n < 4 {return Err(PNGError::ExcessBytes);}
-                },
-                Err(err) => return Err(PNGError::ReadErr(err))
+        while offset < bytes.len() {
+            let remaining = &bytes[offset..];
+            if remaining.len() < 4 {
+                return Err(Error::TrailingBytes(remaining.len()));
             }
 
-            let length: u32 = u32::from_be_bytes(length_buf);
-            let mut chunk_data: Vec<u8> = vec![0; (12 + length) as usize];
-            
-            cursor.seek(SeekFrom::Current(-(length_buf.len() as i64))).map_err(|e| PNGError::ReadErr(e))?;
-            cursor.read_exact(&mut chunk_data).map_err(|e| PNGError::ReadErr(e))?;
+            let index = chunks.len();
+            let chunk_type = remaining.get(4..8).and_then(|code| ChunkType::try_from(<[u8;4]>::try_from(code).unwrap()).ok());
+            limits.check_chunk_count(index + 1).map_err(|err| err.at_chunk(index, offset, chunk_type.clone()))?;
+            let length = u32::from_be_bytes(remaining[..4].try_into().unwrap()) as usize;
+
+            // the chunk is sliced off in place, nothing is allocated from the untrusted length field
+            let end = remaining.len().min(length.saturating_add(12));
+            let chunk = Chunk::parse(&remaining[..end], verify_crc, limits)
+                .map_err(|err| err.at_chunk(index, offset, chunk_type))?;
 
-            let chunk = Chunk::try_from(chunk_data.as_slice()).map_err(|s| PNGError::ChunkParse(s))?;
             chunks.push(chunk);
+            offset += end;
         }
 
         Ok(Png {
             header: Png::STANDARD_HEADER,
-            chunks: chunks
+            chunks
         })
     }
 }
@@ -236,6 +267,72 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_malformed_bytes_report_location() {
+        let mut bytes = Png::try_from(&PNG_FILE[..]).unwrap().as_bytes();
+
+        // corrupt the data of the second chunk
+        let second = 8 + 12 + png_chunk_length(&bytes, 8);
+        bytes[second + 9] ^= 0xFF;
+
+        match Png::try_from(bytes.as_slice()) {
+            Err(Error::AtChunk { index, offset, chunk_type, source }) => {
+                assert_eq!(index, 1);
+                assert_eq!(offset, second);
+                assert!(chunk_type.is_some());
+                assert!(matches!(*source, Error::CrcMismatch { .. }));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_malformed_bytes_do_not_panic() {
+        let header = Png::STANDARD_HEADER.to_vec();
+        let cases: Vec<Vec<u8>> = vec![
+            vec![137, 80],
+            header.iter().copied().chain([0, 0]).collect(),
+            header.iter().copied().chain([0xFF, 0xFF, 0xFF, 0xFF, b'I', b'D', b'A', b'T']).collect(),
+            header.iter().copied().chain([0, 0, 0, 0, 0, 1, 2, 3, 0, 0, 0, 0]).collect(),
+            header.iter().copied().chain([0xFF, 0xFF, 0xFF, 0xF8, b'I']).collect(),
+        ];
+
+        for bytes in cases {
+            assert!(Png::try_from(bytes.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = Png::try_from(&PNG_FILE[..]).unwrap().as_bytes();
+        let chunk_count = Png::try_from(&PNG_FILE[..]).unwrap().chunks().len();
+
+        let limits = Limits { max_total_size: bytes.len() - 1, ..Limits::default() };
+        assert!(matches!(Png::try_from_with_limits(&bytes, &limits), Err(Error::LimitExceeded { limit: "total size", .. })));
+
+        let limits = Limits { max_chunk_count: chunk_count - 1, ..Limits::default() };
+        let err = Png::try_from_with_limits(&bytes, &limits).unwrap_err();
+        assert!(matches!(err.root(), Error::LimitExceeded { limit: "chunk count", .. }));
+
+        let limits = Limits { max_chunk_length: 12, ..Limits::default() };
+        let err = Png::try_from_with_limits(&bytes, &limits).unwrap_err();
+        assert!(matches!(err, Error::AtChunk { index: 0, .. }));
+        assert!(matches!(err.root(), Error::LimitExceeded { limit: "chunk length", .. }));
+
+        assert!(Png::try_from_with_limits(&bytes, &Limits::UNLIMITED).is_ok());
+    }
+
+    #[test]
+    fn test_append_chunk_without_iend() {
+        let mut png = Png::from_chunks(Vec::new());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        assert_eq!(png.chunks().len(), 1);
+    }
+
+    fn png_chunk_length(bytes: &[u8], offset: usize) -> usize {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -270,6 +367,57 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "First").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Second").unwrap());
+
+        let chunks = png.chunks_by_type("TeSt");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].0, 3);
+        assert_eq!(&chunks[1].1.data_as_string().unwrap(), "Second");
+        assert!(png.chunks_by_type("NoNe").is_empty());
+    }
+
+    #[test]
+    fn test_chunk_at() {
+        let png = testing_png();
+        assert_eq!(&png.chunk_at(1).unwrap().chunk_type().to_string(), "miDl");
+        assert!(png.chunk_at(3).is_none());
+    }
+
+    #[test]
+    fn test_remove_at() {
+        let mut png = testing_png();
+        let removed = png.remove_at(1).unwrap();
+        assert_eq!(&removed.chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.remove_at(2).is_none());
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "First").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Second").unwrap());
+
+        let removed = png.remove_all("TeSt");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 3);
+        assert!(png.chunk_by_type("TeSt").is_none());
+    }
+
+    #[test]
+    fn test_replace_at() {
+        let mut png = testing_png();
+        let replaced = png.replace_at(1, chunk_from_strings("miDl", "Replaced").unwrap()).unwrap();
+
+        assert_eq!(&replaced.data_as_string().unwrap(), "I am another chunk");
+        assert_eq!(&png.chunk_at(1).unwrap().data_as_string().unwrap(), "Replaced");
+        assert!(png.replace_at(3, chunk_from_strings("miDl", "Nowhere").unwrap()).is_none());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
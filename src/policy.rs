@@ -0,0 +1,237 @@
+use crate::{limits::Limits, png::Png, selector::{Selector, SelectorError}, text};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt, fs, path::Path, str::FromStr};
+
+// An organisation-wide rule set kept in a TOML file, e.g.
+//
+//   version = 1
+//   allowed = ["gAMA", "sRGB", "pHYs", "tEXt"]     selectors, critical chunks are always allowed
+//   required = ["sRGB"]                            selectors at least one chunk has to match
+//   forbidden_keywords = ["Author", "GPS"]         text chunk keywords that may not appear
+//   max_chunk_size = 65536                         data length of any single chunk
+//   max_file_size = 10000000
+//
+//   [max_size]                                     data length per chunk type
+//   tEXt = 1024
+
+pub const POLICY_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPolicy {
+    version: Option<u32>,
+    allowed: Option<Vec<String>>,
+    #[serde(default)]
+    required: Vec<String>,
+    #[serde(default)]
+    forbidden_keywords: Vec<String>,
+    max_chunk_size: Option<u64>,
+    max_file_size: Option<u64>,
+    #[serde(default)]
+    max_size: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyFile {
+    // None allows every chunk type
+    allowed: Option<Vec<Selector>>,
+    required: Vec<(String, Selector)>,
+    forbidden_keywords: Vec<String>,
+    max_chunk_size: Option<u64>,
+    max_file_size: Option<u64>,
+    max_size: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Parse(toml::de::Error),
+    UnsupportedVersion(u32),
+    InvalidSelector(String, SelectorError),
+    Violated(Vec<Violation>),
+}
+
+impl std::error::Error for PolicyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PolicyError::Parse(err) => Some(err),
+            PolicyError::InvalidSelector(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::Parse(_) => write!(f, "Policy file is not valid"),
+            PolicyError::UnsupportedVersion(version) => write!(f, "Policy version {} is not supported (expected {})", version, POLICY_VERSION),
+            PolicyError::InvalidSelector(selector, _) => write!(f, "\"{}\" in the policy is not a valid selector", selector),
+            PolicyError::Violated(violations) => {
+                write!(f, "{} policy violations", violations.len())?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// a broken rule, `index` is the chunk at fault for rules about single chunks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "[{}] chunk #{}: {}", self.rule, index, self.message),
+            None => write!(f, "[{}] {}", self.rule, self.message),
+        }
+    }
+}
+
+fn selector(source: &str) -> Result<Selector, PolicyError> {
+    Selector::from_str(source).map_err(|err| PolicyError::InvalidSelector(source.to_string(), err))
+}
+
+impl FromStr for PolicyFile {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw: RawPolicy = toml::from_str(s).map_err(PolicyError::Parse)?;
+        if let Some(version) = raw.version.filter(|&version| version != POLICY_VERSION) {
+            return Err(PolicyError::UnsupportedVersion(version));
+        }
+
+        Ok(PolicyFile {
+            allowed: raw.allowed.map(|allowed| allowed.iter().map(|source| selector(source)).collect()).transpose()?,
+            required: raw.required.into_iter().map(|source| Ok((source.clone(), selector(&source)?))).collect::<Result<_, PolicyError>>()?,
+            forbidden_keywords: raw.forbidden_keywords,
+            max_chunk_size: raw.max_chunk_size,
+            max_file_size: raw.max_file_size,
+            max_size: raw.max_size,
+        })
+    }
+}
+
+impl PolicyFile {
+    pub fn load(path: &Path) -> crate::Result<PolicyFile> {
+        Ok(PolicyFile::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn is_allowed(&self, png: &Png, index: usize) -> bool {
+        png.chunks()[index].chunk_type().is_critical()
+            || self.allowed.as_ref().is_none_or(|allowed| allowed.iter().any(|selector| selector.matches(png, index)))
+    }
+
+    // whether the allow list names the chunk, as opposed to allowing everything
+    pub fn lists(&self, png: &Png, index: usize) -> bool {
+        self.allowed.as_ref().is_some_and(|allowed| allowed.iter().any(|selector| selector.matches(png, index)))
+    }
+
+    // the rules a single chunk breaks
+    pub fn check_chunk(&self, png: &Png, index: usize, limits: &Limits) -> Vec<Violation> {
+        let chunk = &png.chunks()[index];
+        let chunk_type = chunk.chunk_type().to_string();
+        let length = chunk.length() as u64;
+        let mut violations = Vec::new();
+        let mut violation = |rule, message| violations.push(Violation { rule, index: Some(index), message });
+
+        if !self.is_allowed(png, index) {
+            violation("allowed", format!("{} is not an allowed chunk type", chunk_type));
+        }
+        if let Some(max) = self.max_chunk_size.filter(|&max| length > max) {
+            violation("max-chunk-size", format!("{} holds {} bytes, more than {}", chunk_type, length, max));
+        }
+        if let Some(&max) = self.max_size.get(&chunk_type).filter(|&&max| length > max) {
+            violation("max-size", format!("{} holds {} bytes, more than {}", chunk_type, length, max));
+        }
+        if let Some(text) = text::decode(chunk, limits).filter(|text| self.forbidden_keywords.contains(&text.keyword)) {
+            violation("forbidden-keyword", format!("{} uses the forbidden keyword \"{}\"", chunk_type, text.keyword));
+        }
+
+        violations
+    }
+
+    pub fn check(&self, png: &Png, limits: &Limits) -> Vec<Violation> {
+        let mut violations: Vec<Violation> = (0..png.chunks().len())
+            .flat_map(|index| self.check_chunk(png, index, limits))
+            .collect();
+
+        for (source, selector) in &self.required {
+            if selector.select(png).is_empty() {
+                violations.push(Violation { rule: "required", index: None, message: format!("no chunk matches \"{}\"", source) });
+            }
+        }
+
+        let size = png.as_bytes().len() as u64;
+        if let Some(max) = self.max_file_size.filter(|&max| size > max) {
+            violations.push(Violation { rule: "max-file-size", index: None, message: format!("file is {} bytes, more than {}", size, max) });
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::chunk;
+
+    const POLICY: &str = r#"
+        version = 1
+        allowed = ["gAMA", "t*"]
+        required = ["sRGB | iCCP"]
+        forbidden_keywords = ["Author"]
+        max_chunk_size = 100
+
+        [max_size]
+        tEXt = 8
+    "#;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("gAMA", &[0; 4]),
+            chunk("tEXt", b"Author\0Jane"),
+            chunk("eXIf", &[0; 8]),
+            chunk("IDAT", &[0; 120]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = PolicyFile::from_str(POLICY).unwrap();
+        let violations = policy.check(&testing_png(), &Limits::default());
+
+        let rules: Vec<(&str, Option<usize>)> = violations.iter().map(|violation| (violation.rule, violation.index)).collect();
+        assert_eq!(rules, [
+            ("max-size", Some(2)),
+            ("forbidden-keyword", Some(2)),
+            ("allowed", Some(3)),
+            ("max-chunk-size", Some(4)),
+            ("required", None),
+        ]);
+    }
+
+    #[test]
+    fn test_empty_policy_allows_everything() {
+        let policy = PolicyFile::from_str("").unwrap();
+
+        assert!(policy.check(&testing_png(), &Limits::default()).is_empty());
+        assert!(policy.is_allowed(&testing_png(), 3));
+        assert!(!policy.lists(&testing_png(), 3));
+    }
+
+    #[test]
+    fn test_invalid_policies() {
+        assert!(matches!(PolicyFile::from_str("version = 2"), Err(PolicyError::UnsupportedVersion(2))));
+        assert!(matches!(PolicyFile::from_str("allowed = [\"(\"]"), Err(PolicyError::InvalidSelector(..))));
+        assert!(matches!(PolicyFile::from_str("alowed = []"), Err(PolicyError::Parse(_))));
+    }
+}
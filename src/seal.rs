@@ -0,0 +1,209 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, limits::Limits, pixels::Image, png::Png};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+
+// ancillary, private and safe to copy so editors carry the seal along with whatever they changed
+pub const SEAL_CHUNK_TYPE: &str = "seAl";
+pub const SEAL_VERSION: u8 = 1;
+
+// what an image looked like when it was sealed: the decoded pixels, which survive re-encoding, the
+// critical chunks as stored, and every other ancillary chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seal {
+    pixels: [u8; 32],
+    critical: [u8; 32],
+    ancillary: [u8; 32],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SealError {
+    Malformed(usize),
+    UnsupportedVersion(u8),
+    MetadataChanged { critical: bool, ancillary: bool },
+    PixelsChanged,
+}
+
+impl std::error::Error for SealError {}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SealError::Malformed(size) => write!(f, "Seal chunk holds {} bytes instead of {}", size, Seal::LENGTH),
+            SealError::UnsupportedVersion(version) => write!(f, "Seal version {} is not supported (expected {})", version, SEAL_VERSION),
+            SealError::MetadataChanged { critical, ancillary } => {
+                let changed: Vec<&str> = [(*critical, "critical chunks"), (*ancillary, "ancillary chunks")]
+                    .into_iter()
+                    .filter_map(|(changed, what)| changed.then_some(what))
+                    .collect();
+                write!(f, "Metadata changed only ({}), the pixels are as sealed", changed.join(", "))
+            },
+            SealError::PixelsChanged => write!(f, "Pixels changed since the image was sealed"),
+        }
+    }
+}
+
+fn hash_chunks<'a>(chunks: impl Iterator<Item = &'a Chunk>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for chunk in chunks {
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.data());
+    }
+    hasher.finalize().into()
+}
+
+// fully transparent pixels hash the same whatever colour they carry, as `compare` sees them
+fn hash_pixels(image: &Image) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(image.width.to_be_bytes());
+    hasher.update(image.height.to_be_bytes());
+    for pixel in &image.pixels {
+        let pixel = if pixel[3] == 0 { [0; 4] } else { *pixel };
+        for value in pixel {
+            hasher.update(value.to_be_bytes());
+        }
+    }
+    hasher.finalize().into()
+}
+
+fn is_seal(chunk: &Chunk) -> bool {
+    chunk.chunk_type().to_string() == SEAL_CHUNK_TYPE
+}
+
+impl Seal {
+    // version + the three hashes
+    pub const LENGTH: usize = 1 + 3 * 32;
+
+    pub fn of(png: &Png, limits: &Limits) -> crate::Result<Seal> {
+        let chunks = png.chunks().iter();
+        Ok(Seal {
+            pixels: hash_pixels(&Image::decode(png, limits)?),
+            critical: hash_chunks(chunks.clone().filter(|chunk| chunk.chunk_type().is_critical())),
+            ancillary: hash_chunks(chunks.filter(|chunk| !chunk.chunk_type().is_critical() && !is_seal(chunk))),
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [SEAL_VERSION].into_iter().chain(self.pixels).chain(self.critical).chain(self.ancillary).collect()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str(SEAL_CHUNK_TYPE).unwrap(), self.as_bytes())
+    }
+
+    // how `png` differs from the image this seal was made for
+    pub fn check(&self, png: &Png, limits: &Limits) -> crate::Result<()> {
+        let now = Seal::of(png, limits)?;
+        if now.pixels != self.pixels {
+            return Err(SealError::PixelsChanged.into());
+        }
+
+        let (critical, ancillary) = (now.critical != self.critical, now.ancillary != self.ancillary);
+        if critical || ancillary {
+            return Err(SealError::MetadataChanged { critical, ancillary }.into());
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for Seal {
+    type Error = SealError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.first().is_some_and(|&version| version != SEAL_VERSION) {
+            return Err(SealError::UnsupportedVersion(bytes[0]));
+        }
+        if bytes.len() != Seal::LENGTH {
+            return Err(SealError::Malformed(bytes.len()));
+        }
+
+        Ok(Seal {
+            pixels: bytes[1..33].try_into().unwrap(),
+            critical: bytes[33..65].try_into().unwrap(),
+            ancillary: bytes[65..].try_into().unwrap(),
+        })
+    }
+}
+
+pub fn find(png: &Png) -> Option<Result<Seal, SealError>> {
+    png.chunk_by_type(SEAL_CHUNK_TYPE)
+        .map(|chunk| Seal::try_from(chunk.data()))
+}
+
+// an earlier seal is replaced, the new one goes last so it also covers the chunks added before it
+pub fn seal(png: &mut Png, limits: &Limits) -> crate::Result<Seal> {
+    png.remove_all(SEAL_CHUNK_TYPE);
+    let seal = Seal::of(png, limits)?;
+    png.append_chunk(seal.to_chunk());
+    Ok(seal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, testing::chunk};
+
+    fn image(value: u16) -> Png {
+        Image { width: 2, height: 2, pixels: vec![[value, 0, 0, 0xFFFF]; 4] }.to_png()
+    }
+
+    fn sealed(value: u16) -> Png {
+        let mut png = image(value);
+        png.append_chunk(chunk("tEXt", b"Title\0Dice"));
+        seal(&mut png, &Limits::default()).unwrap();
+        png
+    }
+
+    fn seal_of(png: &Png) -> Chunk {
+        chunk(SEAL_CHUNK_TYPE, png.chunk_by_type(SEAL_CHUNK_TYPE).unwrap().data())
+    }
+
+    fn check(png: &Png) -> crate::Result<()> {
+        find(png).unwrap().unwrap().check(png, &Limits::default())
+    }
+
+    #[test]
+    fn test_intact() {
+        let mut png = sealed(7);
+        assert!(check(&png).is_ok());
+
+        // sealing again keeps a single seal
+        seal(&mut png, &Limits::default()).unwrap();
+        assert_eq!(png.chunks_by_type(SEAL_CHUNK_TYPE).len(), 1);
+        assert!(check(&png).is_ok());
+    }
+
+    #[test]
+    fn test_metadata_changed() {
+        let mut png = sealed(7);
+        png.append_chunk(chunk("ruSt", b"hi"));
+        assert!(matches!(check(&png), Err(Error::Seal(SealError::MetadataChanged { critical: false, ancillary: true }))));
+
+        // the same pixels written by another encoder
+        let mut reencoded = Image::decode(&png, &Limits::default()).unwrap().to_png();
+        let idat = reencoded.chunks_by_type("IDAT")[0].0;
+        let data = reencoded.chunks()[idat].data().to_vec();
+        reencoded.remove_at(idat);
+        for part in data.chunks(5) {
+            reencoded.append_chunk(chunk("IDAT", part));
+        }
+        reencoded.append_chunk(chunk("tEXt", b"Title\0Dice"));
+        reencoded.append_chunk(seal_of(&sealed(7)));
+        assert!(matches!(check(&reencoded), Err(Error::Seal(SealError::MetadataChanged { critical: true, ancillary: false }))));
+    }
+
+    #[test]
+    fn test_pixels_changed() {
+        let mut png = image(8);
+        png.append_chunk(chunk("tEXt", b"Title\0Dice"));
+        png.append_chunk(seal_of(&sealed(7)));
+
+        assert!(matches!(check(&png), Err(Error::Seal(SealError::PixelsChanged))));
+    }
+
+    #[test]
+    fn test_malformed_seal() {
+        assert_eq!(Seal::try_from(&[1, 2, 3][..]), Err(SealError::Malformed(3)));
+        assert_eq!(Seal::try_from(&[2; Seal::LENGTH][..]), Err(SealError::UnsupportedVersion(2)));
+    }
+}
@@ -0,0 +1,389 @@
+use crate::{chunk::Chunk, png::Png};
+use std::{fmt, str::FromStr};
+
+// Selects chunks with expressions such as `t*X* & ancillary`, `private & size:..1k` or
+// `!critical & before:IDAT`. Terms are combined with `&`, `|`, `!` and parentheses:
+//
+//   critical | ancillary | public | private | safe | unsafe    chunk type property bits
+//   type:GLOB or a bare GLOB                                   `*` and `?` wildcards over the type code
+//   size:RANGE                                                 data length, `k`/`m` suffixes allowed
+//   index:RANGE                                                position of the chunk in the file
+//   before:TYPE / after:TYPE                                   before the first / after the last TYPE chunk
+//
+// RANGE is `N`, `N..M` (inclusive), `N..`, `..M`, `>N`, `>=N`, `<N` or `<=N`.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Type(String),
+    Property(Property),
+    Size(Bounds),
+    Index(Bounds),
+    Before(String),
+    After(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Property {
+    Critical,
+    Ancillary,
+    Public,
+    Private,
+    SafeToCopy,
+    UnsafeToCopy,
+}
+
+// inclusive on both ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SelectorError {
+    Empty,
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UnknownKey(String),
+    InvalidGlob(String),
+    InvalidRange(String),
+}
+
+impl std::error::Error for SelectorError {}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelectorError::Empty => write!(f, "Selector is empty"),
+            SelectorError::UnexpectedToken(token) => write!(f, "Unexpected \"{}\" in selector", token),
+            SelectorError::UnexpectedEnd => write!(f, "Selector ended unexpectedly"),
+            SelectorError::UnknownKey(key) => write!(f, "Unknown selector key \"{}\" (expected type, size, index, before or after)", key),
+            SelectorError::InvalidGlob(glob) => write!(f, "\"{}\" is not a chunk type pattern (letters, * and ? only, 4 characters without *)", glob),
+            SelectorError::InvalidRange(range) => write!(f, "\"{}\" is not a valid range", range),
+        }
+    }
+}
+
+impl Bounds {
+    fn contains(&self, value: u64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+impl FromStr for Bounds {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SelectorError::InvalidRange(s.to_string());
+        let number = |n: &str| -> Result<u64, SelectorError> {
+            let (digits, scale) = match n.chars().last() {
+                Some('k' | 'K') => (&n[..n.len() - 1], 1024),
+                Some('m' | 'M') => (&n[..n.len() - 1], 1024 * 1024),
+                _ => (n, 1),
+            };
+            digits.parse::<u64>().ok().and_then(|value| value.checked_mul(scale)).ok_or_else(invalid)
+        };
+
+        let bounds = if let Some(rest) = s.strip_prefix(">=") {
+            Bounds { min: Some(number(rest)?), max: None }
+        } else if let Some(rest) = s.strip_prefix("<=") {
+            Bounds { min: None, max: Some(number(rest)?) }
+        } else if let Some(rest) = s.strip_prefix('>') {
+            Bounds { min: Some(number(rest)?.checked_add(1).ok_or_else(invalid)?), max: None }
+        } else if let Some(rest) = s.strip_prefix('<') {
+            Bounds { min: None, max: Some(number(rest)?.checked_sub(1).ok_or_else(invalid)?) }
+        } else if let Some((min, max)) = s.split_once("..") {
+            Bounds {
+                min: (!min.is_empty()).then(|| number(min)).transpose()?,
+                max: (!max.is_empty()).then(|| number(max)).transpose()?,
+            }
+        } else {
+            let value = number(s)?;
+            Bounds { min: Some(value), max: Some(value) }
+        };
+
+        if bounds.min.is_none() && bounds.max.is_none() {
+            return Err(invalid());
+        }
+        Ok(bounds)
+    }
+}
+
+fn validate_glob(glob: &str) -> Result<String, SelectorError> {
+    let valid_chars = glob.bytes().all(|byte| byte.is_ascii_alphabetic() || byte == b'*' || byte == b'?');
+    let valid_length = if glob.contains('*') { glob.len() <= 4 + glob.matches('*').count() } else { glob.len() == 4 };
+
+    if glob.is_empty() || !valid_chars || !valid_length {
+        return Err(SelectorError::InvalidGlob(glob.to_string()));
+    }
+    Ok(glob.to_string())
+}
+
+fn glob_matches(glob: &[u8], code: &[u8]) -> bool {
+    match (glob.first(), code.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => glob_matches(&glob[1..], code) || (!code.is_empty() && glob_matches(glob, &code[1..])),
+        (Some(b'?'), Some(_)) => glob_matches(&glob[1..], &code[1..]),
+        (Some(g), Some(c)) => g == c && glob_matches(&glob[1..], &code[1..]),
+        _ => false,
+    }
+}
+
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+
+    for c in s.chars() {
+        if c.is_whitespace() || "()&|!".contains(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<String, SelectorError> {
+        let token = self.tokens.get(self.position).cloned().ok_or(SelectorError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr, SelectorError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some("|") {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, SelectorError> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some("&") {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, SelectorError> {
+        let token = self.next()?;
+        match token.as_str() {
+            "!" => Ok(Expr::Not(Box::new(self.unary()?))),
+            "(" => {
+                let expr = self.or()?;
+                match self.next()?.as_str() {
+                    ")" => Ok(expr),
+                    other => Err(SelectorError::UnexpectedToken(other.to_string())),
+                }
+            },
+            ")" | "&" | "|" => Err(SelectorError::UnexpectedToken(token)),
+            _ => Parser::term(&token),
+        }
+    }
+
+    fn term(word: &str) -> Result<Expr, SelectorError> {
+        let property = match word {
+            "critical" => Some(Property::Critical),
+            "ancillary" => Some(Property::Ancillary),
+            "public" => Some(Property::Public),
+            "private" => Some(Property::Private),
+            "safe" => Some(Property::SafeToCopy),
+            "unsafe" => Some(Property::UnsafeToCopy),
+            _ => None,
+        };
+        if let Some(property) = property {
+            return Ok(Expr::Property(property));
+        }
+
+        match word.split_once(':') {
+            Some(("type", glob)) => Ok(Expr::Type(validate_glob(glob)?)),
+            Some(("size", range)) => Ok(Expr::Size(range.parse()?)),
+            Some(("index", range)) => Ok(Expr::Index(range.parse()?)),
+            Some(("before", code)) => Ok(Expr::Before(validate_glob(code)?)),
+            Some(("after", code)) => Ok(Expr::After(validate_glob(code)?)),
+            Some((key, _)) => Err(SelectorError::UnknownKey(key.to_string())),
+            None => Ok(Expr::Type(validate_glob(word)?)),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s), position: 0 };
+        if parser.tokens.is_empty() {
+            return Err(SelectorError::Empty);
+        }
+
+        let expr = parser.or()?;
+        match parser.peek() {
+            Some(token) => Err(SelectorError::UnexpectedToken(token.to_string())),
+            None => Ok(Selector { expr }),
+        }
+    }
+}
+
+impl Selector {
+    pub fn of_type(code: &str) -> Result<Selector, SelectorError> {
+        Ok(Selector { expr: Expr::Type(validate_glob(code)?) })
+    }
+
+    pub fn and(self, other: Selector) -> Selector {
+        Selector { expr: Expr::And(Box::new(self.expr), Box::new(other.expr)) }
+    }
+
+    pub fn matches(&self, png: &Png, index: usize) -> bool {
+        png.chunk_at(index).is_some_and(|chunk| Selector::eval(&self.expr, png, index, chunk))
+    }
+
+    pub fn select<'a>(&self, png: &'a Png) -> Vec<(usize, &'a Chunk)> {
+        png.chunks()
+            .iter()
+            .enumerate()
+            .filter(|&(index, chunk)| Selector::eval(&self.expr, png, index, chunk))
+            .collect()
+    }
+
+    fn eval(expr: &Expr, png: &Png, index: usize, chunk: &Chunk) -> bool {
+        let chunk_type = chunk.chunk_type();
+        let type_matches = |glob: &str, chunk: &Chunk| glob_matches(glob.as_bytes(), &chunk.chunk_type().bytes());
+
+        match expr {
+            Expr::Type(glob) => type_matches(glob, chunk),
+            Expr::Property(Property::Critical) => chunk_type.is_critical(),
+            Expr::Property(Property::Ancillary) => !chunk_type.is_critical(),
+            Expr::Property(Property::Public) => chunk_type.is_public(),
+            Expr::Property(Property::Private) => !chunk_type.is_public(),
+            Expr::Property(Property::SafeToCopy) => chunk_type.is_safe_to_copy(),
+            Expr::Property(Property::UnsafeToCopy) => !chunk_type.is_safe_to_copy(),
+            Expr::Size(bounds) => bounds.contains(chunk.length() as u64),
+            Expr::Index(bounds) => bounds.contains(index as u64),
+            Expr::Before(glob) => png.chunks()
+                .iter()
+                .position(|other| type_matches(glob, other))
+                .is_some_and(|first| index < first),
+            Expr::After(glob) => png.chunks()
+                .iter()
+                .rposition(|other| type_matches(glob, other))
+                .is_some_and(|last| index > last),
+            Expr::Not(inner) => !Selector::eval(inner, png, index, chunk),
+            Expr::And(lhs, rhs) => Selector::eval(lhs, png, index, chunk) && Selector::eval(rhs, png, index, chunk),
+            Expr::Or(lhs, rhs) => Selector::eval(lhs, png, index, chunk) || Selector::eval(rhs, png, index, chunk),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+
+    fn testing_png() -> Png {
+        let chunk = |code: &str, size: usize| Chunk::new(ChunkType::from_str(code).unwrap(), vec![b'x'; size]);
+
+        Png::from_chunks(vec![
+            chunk("IHDR", 13),
+            chunk("tEXt", 40),
+            chunk("ruSt", 5),
+            chunk("IDAT", 2000),
+            chunk("IDAT", 1000),
+            chunk("ruSt", 3000),
+            chunk("IEND", 0),
+        ])
+    }
+
+    fn selected(selector: &str) -> Vec<usize> {
+        let png = testing_png();
+        Selector::from_str(selector).unwrap().select(&png).into_iter().map(|(index, _)| index).collect()
+    }
+
+    #[test]
+    fn test_type_glob() {
+        assert_eq!(selected("ruSt"), vec![2, 5]);
+        assert_eq!(selected("I*"), vec![0, 3, 4, 6]);
+        assert_eq!(selected("type:?EXt"), vec![1]);
+        assert_eq!(selected("*D*"), vec![0, 3, 4, 6]);
+    }
+
+    #[test]
+    fn test_properties() {
+        assert_eq!(selected("ancillary & private"), vec![2, 5]);
+        assert_eq!(selected("critical & !type:IDAT"), vec![0, 6]);
+        assert_eq!(selected("public & ancillary"), vec![1]);
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(selected("size:>1k"), vec![3, 5]);
+        assert_eq!(selected("size:..13 & ancillary"), vec![2]);
+        assert_eq!(selected("index:2..4"), vec![2, 3, 4]);
+        assert_eq!(selected("index:5.."), vec![5, 6]);
+        assert_eq!(selected("index:<1"), vec![0]);
+    }
+
+    #[test]
+    fn test_before_after() {
+        assert_eq!(selected("before:IDAT"), vec![0, 1, 2]);
+        assert_eq!(selected("after:IDAT & !IEND"), vec![5]);
+        assert_eq!(selected("before:NoNe"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_precedence_and_grouping() {
+        assert_eq!(selected("IHDR | ruSt & size:<10"), vec![0, 2]);
+        assert_eq!(selected("(IHDR | ruSt) & size:<10"), vec![2]);
+        assert_eq!(selected("!(critical | size:>100)"), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_matches() {
+        let png = testing_png();
+        let selector = Selector::of_type("ruSt").unwrap().and(Selector::from_str("size:>100").unwrap());
+
+        assert!(selector.matches(&png, 5));
+        assert!(!selector.matches(&png, 2));
+        assert!(!selector.matches(&png, 99));
+    }
+
+    #[test]
+    fn test_invalid_selectors() {
+        assert_eq!(Selector::from_str(""), Err(SelectorError::Empty));
+        assert_eq!(Selector::from_str("ruSt &"), Err(SelectorError::UnexpectedEnd));
+        assert_eq!(Selector::from_str("(ruSt"), Err(SelectorError::UnexpectedEnd));
+        assert_eq!(Selector::from_str("ruSt)"), Err(SelectorError::UnexpectedToken(")".to_string())));
+        assert_eq!(Selector::from_str("colour:red"), Err(SelectorError::UnknownKey("colour".to_string())));
+        assert_eq!(Selector::from_str("rust1"), Err(SelectorError::InvalidGlob("rust1".to_string())));
+        assert_eq!(Selector::from_str("size:a..b"), Err(SelectorError::InvalidRange("a..b".to_string())));
+        assert_eq!(Selector::from_str("size:.."), Err(SelectorError::InvalidRange("..".to_string())));
+    }
+}
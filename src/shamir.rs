@@ -0,0 +1,223 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, gf256, png::Png};
+use std::{fmt, str::FromStr};
+
+// ancillary, private and safe to copy, one per carrier image
+pub const SHARE_CHUNK_TYPE: &str = "shRd";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    set_id: u64,
+    threshold: u8,
+    index: u8,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShamirError {
+    InvalidThreshold(usize, usize),
+    Random(getrandom::Error),
+    Malformed(usize),
+    MissingShare(usize),
+    MixedSets(u64, u64),
+    ConflictingShares(u8),
+    NotEnoughShares(usize, u8),
+}
+
+impl std::error::Error for ShamirError {}
+
+impl fmt::Display for ShamirError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShamirError::InvalidThreshold(threshold, count) => write!(f, "Cannot split into {} shares with threshold {} (need 2 <= threshold <= shares <= 255)", count, threshold),
+            ShamirError::Random(err) => write!(f, "Failed to gather random coefficients: {}", err),
+            ShamirError::Malformed(size) => write!(f, "Share chunk data too short to hold a share header ({} < {})", size, Share::HEADER_LENGTH),
+            ShamirError::MissingShare(index) => write!(f, "Carrier #{} does not contain a {} chunk", index, SHARE_CHUNK_TYPE),
+            ShamirError::MixedSets(expected, found) => write!(f, "Share from set {:016x} does not belong to set {:016x}", found, expected),
+            ShamirError::ConflictingShares(index) => write!(f, "Two different shares claim index {}", index),
+            ShamirError::NotEnoughShares(found, threshold) => write!(f, "Found {} distinct shares when {} are needed", found, threshold),
+        }
+    }
+}
+
+impl Share {
+    // set id + threshold + share index
+    pub const HEADER_LENGTH: usize = 8 + 1 + 1;
+
+    pub fn set_id(&self) -> u64 {
+        self.set_id
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.set_id
+            .to_be_bytes()
+            .into_iter()
+            .chain([self.threshold, self.index])
+            .chain(self.data.iter().copied())
+            .collect()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str(SHARE_CHUNK_TYPE).unwrap(), self.as_bytes())
+    }
+}
+
+impl TryFrom<&[u8]> for Share {
+    type Error = ShamirError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        // index 0 would be the secret itself and a threshold below 2 is never produced by split
+        if bytes.len() < Share::HEADER_LENGTH || bytes[8] < 2 || bytes[9] == 0 {
+            return Err(ShamirError::Malformed(bytes.len()));
+        }
+
+        Ok(Share {
+            set_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            threshold: bytes[8],
+            index: bytes[9],
+            data: bytes[Share::HEADER_LENGTH..].to_vec(),
+        })
+    }
+}
+
+fn random_bytes(count: usize) -> Result<Vec<u8>, ShamirError> {
+    let mut bytes = vec![0; count];
+    getrandom::fill(&mut bytes).map_err(ShamirError::Random)?;
+    Ok(bytes)
+}
+
+pub fn split(secret: &[u8], threshold: usize, count: usize) -> Result<Vec<Share>, ShamirError> {
+    if threshold < 2 || threshold > count || count > 255 {
+        return Err(ShamirError::InvalidThreshold(threshold, count));
+    }
+
+    let set_id = u64::from_be_bytes(random_bytes(8)?.try_into().unwrap());
+    let mut shares: Vec<Share> = (1..=count as u8)
+        .map(|index| Share {
+            set_id,
+            threshold: threshold as u8,
+            index,
+            data: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+
+    // every secret byte gets its own random polynomial of degree threshold - 1
+    let mut coefficients = vec![0; threshold];
+    for &byte in secret {
+        coefficients[0] = byte;
+        coefficients[1..].copy_from_slice(&random_bytes(threshold - 1)?);
+
+        for share in shares.iter_mut() {
+            share.data.push(gf256::eval_poly(&coefficients, share.index));
+        }
+    }
+
+    Ok(shares)
+}
+
+pub fn find(png: &Png) -> Option<Result<Share, ShamirError>> {
+    png.chunk_by_type(SHARE_CHUNK_TYPE)
+        .map(|chunk| Share::try_from(chunk.data()))
+}
+
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>, ShamirError> {
+    let first = match shares.first() {
+        Some(share) => share,
+        None => return Err(ShamirError::NotEnoughShares(0, 2)),
+    };
+
+    let mut distinct: Vec<&Share> = Vec::new();
+    for share in shares {
+        if share.set_id != first.set_id || share.threshold != first.threshold || share.data.len() != first.data.len() {
+            return Err(ShamirError::MixedSets(first.set_id, share.set_id));
+        }
+
+        match distinct.iter().find(|other| other.index == share.index) {
+            Some(other) if other.data != share.data => return Err(ShamirError::ConflictingShares(share.index)),
+            Some(_) => {},
+            None => distinct.push(share),
+        }
+    }
+
+    if distinct.len() < first.threshold as usize {
+        return Err(ShamirError::NotEnoughShares(distinct.len(), first.threshold));
+    }
+    distinct.truncate(first.threshold as usize);
+
+    // lagrange basis polynomials evaluated at x = 0
+    let basis: Vec<u8> = distinct
+        .iter()
+        .map(|share| {
+            distinct
+                .iter()
+                .filter(|other| other.index != share.index)
+                .fold(1, |acc, other| {
+                    gf256::mul(acc, gf256::div(other.index, gf256::add(other.index, share.index)))
+                })
+        })
+        .collect();
+
+    Ok((0..first.data.len())
+        .map(|i| {
+            distinct
+                .iter()
+                .zip(&basis)
+                .fold(0, |acc, (share, &weight)| gf256::add(acc, gf256::mul(share.data[i], weight)))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"correct horse battery staple";
+
+    #[test]
+    fn test_share_round_trip() {
+        let shares = split(SECRET, 2, 3).unwrap();
+        let chunk = shares[2].to_chunk();
+
+        assert_eq!(&chunk.chunk_type().to_string(), SHARE_CHUNK_TYPE);
+        assert_eq!(Share::try_from(chunk.data()).unwrap(), shares[2]);
+    }
+
+    #[test]
+    fn test_combine_any_threshold_subset() {
+        let shares = split(SECRET, 3, 5).unwrap();
+
+        assert_eq!(combine(&shares).unwrap(), SECRET);
+        assert_eq!(combine(&shares[2..]).unwrap(), SECRET);
+        assert_eq!(combine(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]).unwrap(), SECRET);
+    }
+
+    #[test]
+    fn test_combine_below_threshold() {
+        let shares = split(SECRET, 3, 5).unwrap();
+        let shares = [shares[0].clone(), shares[1].clone(), shares[1].clone()];
+
+        assert_eq!(combine(&shares), Err(ShamirError::NotEnoughShares(2, 3)));
+    }
+
+    #[test]
+    fn test_combine_mixed_sets() {
+        let mut shares = split(SECRET, 2, 2).unwrap();
+        shares[1] = split(SECRET, 2, 2).unwrap().remove(1);
+
+        assert!(matches!(combine(&shares), Err(ShamirError::MixedSets(_, _))));
+    }
+
+    #[test]
+    fn test_invalid_threshold() {
+        assert_eq!(split(SECRET, 1, 3), Err(ShamirError::InvalidThreshold(1, 3)));
+        assert_eq!(split(SECRET, 4, 3), Err(ShamirError::InvalidThreshold(4, 3)));
+        assert_eq!(split(SECRET, 2, 256), Err(ShamirError::InvalidThreshold(2, 256)));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{apng, chunk::Chunk, chunk_type::ChunkType, limits::Limits, png::Png, policy::PolicyFile, selector::Selector};
+
+pub const COLOUR_TYPES: [&str; 8] = ["gAMA", "cHRM", "sRGB", "iCCP", "sBIT", "cICP", "mDCV", "cLLi"];
+pub const ANIMATION_TYPES: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+// ancillary chunks kept when no --keep or --drop rule decides otherwise
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Preset {
+    // nothing but the critical chunks
+    #[default]
+    None,
+    // colour management, so the picture keeps its colours
+    Colour,
+    // everything that changes how the picture looks: colour management, transparency and animation
+    Render,
+    // every safe-to-copy chunk, the ones the PNG spec lets editors carry over without understanding them
+    Safe,
+}
+
+impl Preset {
+    pub fn keeps(&self, chunk_type: &ChunkType) -> bool {
+        let code = chunk_type.to_string();
+        match self {
+            Preset::None => false,
+            Preset::Colour => COLOUR_TYPES.contains(&code.as_str()),
+            Preset::Render => COLOUR_TYPES.contains(&code.as_str())
+                || ANIMATION_TYPES.contains(&code.as_str())
+                || code == "tRNS",
+            Preset::Safe => chunk_type.is_safe_to_copy(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub preset: Preset,
+    pub keep: Vec<Selector>,
+    pub drop: Vec<Selector>,
+    pub rules: Option<PolicyFile>,
+}
+
+impl Policy {
+    // critical chunks are never removed since the picture cannot be rendered without them, after that
+    // a chunk breaking the policy file goes, then --drop wins over --keep, which wins over the types
+    // the policy file allows by name, which win over the preset
+    pub fn keeps(&self, png: &Png, index: usize, limits: &Limits) -> bool {
+        let chunk_type = png.chunks()[index].chunk_type();
+        if chunk_type.is_critical() {
+            return true;
+        }
+
+        let breaks_rules = self.rules.as_ref().is_some_and(|rules| !rules.check_chunk(png, index, limits).is_empty());
+        if breaks_rules || self.drop.iter().any(|selector| selector.matches(png, index)) {
+            return false;
+        }
+
+        self.keep.iter().any(|selector| selector.matches(png, index))
+            || self.rules.as_ref().is_some_and(|rules| rules.lists(png, index))
+            || self.preset.keeps(chunk_type)
+    }
+}
+
+// removed chunks with their position in the original file
+pub fn strip(png: &mut Png, policy: &Policy, limits: &Limits) -> Vec<(usize, Chunk)> {
+    // every decision is made on the original file so positional selectors are not thrown off by removals
+    let doomed: Vec<usize> = (0..png.chunks().len()).filter(|&index| !policy.keeps(png, index, limits)).collect();
+
+    let kept: Vec<usize> = (0..png.chunks().len()).filter(|index| doomed.binary_search(index).is_err()).collect();
+
+    let mut removed: Vec<(usize, Chunk)> = doomed
+        .into_iter()
+        .rev()
+        .filter_map(|index| Some((index, png.remove_at(index)?)))
+        .collect();
+    removed.reverse();
+
+    // dropping part of an animation can leave frames behind that have to go as well
+    removed.extend(apng::repair(png).into_iter().map(|(index, chunk)| (kept[index], chunk)));
+    removed.sort_by_key(|(index, _)| *index);
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::chunk;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(["IHDR", "gAMA", "tEXt", "ruSt", "tRNS", "IDAT", "zTXt", "IEND"].map(|code| chunk(code, &[0; 4])).into())
+    }
+
+    fn remaining(policy: &Policy) -> Vec<String> {
+        let mut png = testing_png();
+        strip(&mut png, policy, &Limits::default());
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_strip_default_keeps_critical() {
+        let mut png = testing_png();
+        let removed = strip(&mut png, &Policy::default(), &Limits::default());
+
+        assert_eq!(removed.iter().map(|(index, _)| *index).collect::<Vec<_>>(), [1, 2, 3, 4, 6]);
+        assert_eq!(remaining(&Policy::default()), ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_presets() {
+        let policy = |preset| Policy { preset, ..Policy::default() };
+
+        assert_eq!(remaining(&policy(Preset::Colour)), ["IHDR", "gAMA", "IDAT", "IEND"]);
+        assert_eq!(remaining(&policy(Preset::Render)), ["IHDR", "gAMA", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(remaining(&policy(Preset::Safe)), ["IHDR", "tEXt", "ruSt", "IDAT", "zTXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_keep_and_drop() {
+        let policy = Policy {
+            preset: Preset::Render,
+            keep: vec![Selector::from_str("*Xt").unwrap()],
+            drop: vec![Selector::from_str("zTXt | gAMA | IDAT").unwrap()],
+            rules: None,
+        };
+
+        assert_eq!(remaining(&policy), ["IHDR", "tEXt", "tRNS", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_policy_file() {
+        let rules = PolicyFile::from_str("allowed = [\"t*\", \"ruSt\"]\n[max_size]\ntRNS = 2").unwrap();
+        let policy = Policy { rules: Some(rules), keep: vec![Selector::from_str("gAMA | tRNS").unwrap()], ..Policy::default() };
+
+        // --keep cannot save gAMA, which the policy does not allow, nor tRNS, which is too large for it
+        assert_eq!(remaining(&policy), ["IHDR", "tEXt", "ruSt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_positional_selectors() {
+        let policy = Policy { keep: vec![Selector::from_str("after:IDAT").unwrap()], ..Policy::default() };
+        assert_eq!(remaining(&policy), ["IHDR", "IDAT", "zTXt", "IEND"]);
+    }
+}
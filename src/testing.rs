@@ -0,0 +1,32 @@
+// scaffolding shared by the test modules
+use crate::{chunk::Chunk, chunk_type::ChunkType, pixels::Header, png::Png};
+use std::str::FromStr;
+
+pub fn chunk(code: &str, data: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str(code).unwrap(), data.to_vec())
+}
+
+// a 1x1 image header
+pub fn header(colour_type: u8, bit_depth: u8) -> Header {
+    Header { width: 1, height: 1, bit_depth, colour_type, interlaced: false }
+}
+
+// `chunks` between the IHDR of `header` and a one byte IDAT followed by IEND
+pub fn still(header: Header, chunks: Vec<Chunk>) -> Png {
+    let mut all = vec![header.to_chunk()];
+    all.extend(chunks);
+    all.extend([chunk("IDAT", &[1]), chunk("IEND", &[])]);
+    Png::from_chunks(all)
+}
+
+// the module error inside a failed result, as in `module_error!(colours(&png, &limits), Error::Colour)`
+macro_rules! module_error {
+    ($result:expr, $variant:path) => {
+        match $result {
+            Err($variant(err)) => err,
+            other => panic!("unexpected result {:?}", other),
+        }
+    };
+}
+
+pub(crate) use module_error;
@@ -0,0 +1,118 @@
+use crate::{chunk::Chunk, limits::{self, Limits}};
+
+// keyword and text of a tEXt, zTXt or iTXt chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    pub keyword: String,
+    pub text: String,
+}
+
+pub fn is_text(chunk: &Chunk) -> bool {
+    matches!(&chunk.chunk_type().bytes(), b"tEXt" | b"zTXt" | b"iTXt")
+}
+
+// tEXt and zTXt are Latin-1, every byte maps to the code point of the same value
+pub(crate) fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+// the spec's rules for keywords, which iCCP profile and sPLT palette names follow too: 1 to 79 printable
+// Latin-1 characters without leading, trailing or consecutive spaces
+pub(crate) fn is_keyword(name: &[u8]) -> bool {
+    (1..=79).contains(&name.len())
+        && name.iter().all(|&byte| (32..=126).contains(&byte) || byte >= 161)
+        && name.first() != Some(&b' ')
+        && name.last() != Some(&b' ')
+        && !name.windows(2).any(|pair| pair == b"  ")
+}
+
+fn split_nul(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let nul = bytes.iter().position(|&byte| byte == 0)?;
+    Some((&bytes[..nul], &bytes[nul + 1..]))
+}
+
+// None for other chunk types and for text chunks that are malformed or inflate past the limits
+pub fn decode(chunk: &Chunk, limits: &Limits) -> Option<Text> {
+    let data = chunk.data();
+
+    match &chunk.chunk_type().bytes() {
+        b"tEXt" => {
+            let (keyword, text) = split_nul(data)?;
+            Some(Text { keyword: latin1(keyword), text: latin1(text) })
+        },
+        b"zTXt" => {
+            let (keyword, rest) = split_nul(data)?;
+            let (&method, compressed) = rest.split_first()?;
+            if method != 0 {
+                return None;
+            }
+            let text = limits::decompress(compressed, limits).ok()?;
+            Some(Text { keyword: latin1(keyword), text: latin1(&text) })
+        },
+        b"iTXt" => {
+            let (keyword, rest) = split_nul(data)?;
+            let [flag, method, rest @ ..] = rest else {
+                return None;
+            };
+            let (_language, rest) = split_nul(rest)?;
+            let (_translated_keyword, text) = split_nul(rest)?;
+
+            let text = match (flag, method) {
+                (0, _) => text.to_vec(),
+                (1, 0) => limits::decompress(text, limits).ok()?,
+                _ => return None,
+            };
+            Some(Text { keyword: latin1(keyword), text: String::from_utf8(text).ok()? })
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::chunk;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let text = decode(&chunk("tEXt", b"Author\0Jos\xE9"), &Limits::default()).unwrap();
+        assert_eq!(text, Text { keyword: "Author".to_string(), text: "José".to_string() });
+    }
+
+    #[test]
+    fn test_decode_compressed_text() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(compress(b"hidden in plain sight"));
+
+        assert_eq!(decode(&chunk("zTXt", &data), &Limits::default()).unwrap().text, "hidden in plain sight");
+    }
+
+    #[test]
+    fn test_decode_international_text() {
+        let mut data = b"Title\0\x01\x00ja\0\xE9\xA1\x8C\0".to_vec();
+        data.extend(compress("サイコロ".as_bytes()));
+        assert_eq!(decode(&chunk("iTXt", &data), &Limits::default()).unwrap().text, "サイコロ");
+
+        let plain = b"Title\0\x00\x00\0\0dice".to_vec();
+        assert_eq!(decode(&chunk("iTXt", &plain), &Limits::default()).unwrap().text, "dice");
+    }
+
+    #[test]
+    fn test_decode_rejects() {
+        assert!(decode(&chunk("ruSt", b"a\0b"), &Limits::default()).is_none());
+        assert!(decode(&chunk("tEXt", b"no separator"), &Limits::default()).is_none());
+
+        let mut bomb = b"Comment\0\0".to_vec();
+        bomb.extend(compress(&[0; 4096]));
+        let limits = Limits { max_decompressed_size: 1024, ..Limits::default() };
+        assert!(decode(&chunk("zTXt", &bomb), &limits).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, limits::Limits, png::Png};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+// An unpacked image is a directory of NNN-TYPE.bin files, one per chunk holding its data, and a manifest
+// listing them in file order, e.g.
+//
+//   { "version": 1, "chunks": [ { "file": "000-IHDR.bin", "type": "IHDR", "crc": 2847183124 }, ... ] }
+//
+// packing follows the manifest, so chunks are dropped, reordered or retyped by editing it
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub file: String,
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+    // CRC of the chunk as unpacked, left out for chunks added by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crc: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub version: u32,
+    pub chunks: Vec<Entry>,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Parse(serde_json::Error),
+    UnsupportedVersion(u32),
+    InvalidChunkType(usize, String),
+    InvalidFileName(usize, String),
+}
+
+impl std::error::Error for ManifestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ManifestError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Parse(_) => write!(f, "Manifest is not valid"),
+            ManifestError::UnsupportedVersion(version) => write!(f, "Manifest version {} is not supported (expected {})", version, MANIFEST_VERSION),
+            ManifestError::InvalidChunkType(index, code) => write!(f, "Manifest entry #{} has \"{}\" as chunk type, not 4 ASCII letters", index, code),
+            ManifestError::InvalidFileName(index, file) => write!(f, "Manifest entry #{} names \"{}\", which is not a file inside the directory", index, file),
+        }
+    }
+}
+
+// a chunk whose data or type no longer matches the CRC recorded when it was unpacked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edited {
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub crcs: (Option<u32>, u32),
+}
+
+pub fn file_name(index: usize, chunk: &Chunk) -> String {
+    format!("{:03}-{}.bin", index, chunk.chunk_type())
+}
+
+pub fn unpack(png: &Png, dir: &Path) -> io::Result<Manifest> {
+    fs::create_dir_all(dir)?;
+
+    let mut chunks = Vec::with_capacity(png.chunks().len());
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let file = file_name(index, chunk);
+        fs::write(dir.join(&file), chunk.data())?;
+        chunks.push(Entry { file, chunk_type: chunk.chunk_type().to_string(), crc: Some(chunk.crc()) });
+    }
+
+    let manifest = Manifest { version: MANIFEST_VERSION, chunks };
+    let mut json = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+    json.push(b'\n');
+    fs::write(dir.join(MANIFEST_NAME), json)?;
+    Ok(manifest)
+}
+
+impl FromStr for Manifest {
+    type Err = ManifestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let manifest: Manifest = serde_json::from_str(s).map_err(ManifestError::Parse)?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(ManifestError::UnsupportedVersion(manifest.version));
+        }
+        Ok(manifest)
+    }
+}
+
+// chunk files have to sit directly inside the directory, a manifest cannot reach out of it
+fn is_plain_name(file: &str) -> bool {
+    let mut components = Path::new(file).components();
+    matches!((components.next(), components.next()), (Some(std::path::Component::Normal(_)), None))
+}
+
+// every CRC is computed afresh by `Chunk::new`, chunks left alone come out with the CRC they had
+pub fn pack(dir: &Path, limits: &Limits) -> crate::Result<(Png, Vec<Edited>)> {
+    let manifest = Manifest::from_str(&fs::read_to_string(dir.join(MANIFEST_NAME))?)?;
+    limits.check_chunk_count(manifest.chunks.len())?;
+
+    let mut chunks = Vec::with_capacity(manifest.chunks.len());
+    let mut edited = Vec::new();
+    let mut total: u64 = 0;
+
+    for (index, entry) in manifest.chunks.iter().enumerate() {
+        let chunk_type = ChunkType::from_str(&entry.chunk_type)
+            .map_err(|_| ManifestError::InvalidChunkType(index, entry.chunk_type.clone()))?;
+        if !is_plain_name(&entry.file) {
+            return Err(ManifestError::InvalidFileName(index, entry.file.clone()).into());
+        }
+
+        let path = dir.join(&entry.file);
+        let size = fs::metadata(&path)?.len();
+        limits.check_chunk_length(u32::try_from(size).unwrap_or(u32::MAX))?;
+        total += size + 12;
+        limits.check_total_size(total + 8)?;
+
+        let chunk = Chunk::new(chunk_type, fs::read(&path)?);
+        if entry.crc != Some(chunk.crc()) {
+            edited.push(Edited { index, chunk_type: chunk.chunk_type().clone(), crcs: (entry.crc, chunk.crc()) });
+        }
+        chunks.push(chunk);
+    }
+
+    Ok((Png::from_chunks(chunks), edited))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::chunk;
+    use tempfile::TempDir;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![chunk("IHDR", &[0; 13]), chunk("tEXt", b"Title\0Dice"), chunk("IDAT", &[1, 2, 3]), chunk("IEND", &[])])
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let manifest = unpack(&testing_png(), dir.path()).unwrap();
+
+        assert_eq!(manifest.chunks[1].file, "001-tEXt.bin");
+        assert_eq!(fs::read(dir.path().join("001-tEXt.bin")).unwrap(), b"Title\0Dice");
+
+        let (png, edited) = pack(dir.path(), &Limits::default()).unwrap();
+        assert!(edited.is_empty());
+        assert_eq!(png.as_bytes(), testing_png().as_bytes());
+    }
+
+    #[test]
+    fn test_edited_chunks() {
+        let dir = TempDir::new().unwrap();
+        let mut manifest = unpack(&testing_png(), dir.path()).unwrap();
+        fs::write(dir.path().join("001-tEXt.bin"), b"Title\0Two dice").unwrap();
+        fs::write(dir.path().join("extra.bin"), b"hi").unwrap();
+        manifest.chunks.insert(3, Entry { file: "extra.bin".to_string(), chunk_type: "ruSt".to_string(), crc: None });
+        fs::write(dir.path().join(MANIFEST_NAME), serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        let (png, edited) = pack(dir.path(), &Limits::default()).unwrap();
+        assert_eq!(edited.iter().map(|edit| edit.index).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(png.chunks()[1].data(), b"Title\0Two dice");
+        assert_eq!(png.chunks()[3].chunk_type().to_string(), "ruSt");
+        assert!(png.chunks().iter().all(Chunk::is_crc_valid));
+    }
+
+    #[test]
+    fn test_bad_manifests() {
+        let dir = TempDir::new().unwrap();
+        let pack_with = |json: &str| {
+            fs::write(dir.path().join(MANIFEST_NAME), json).unwrap();
+            pack(dir.path(), &Limits::default())
+        };
+
+        assert!(matches!(pack_with(r#"{"version": 2, "chunks": []}"#), Err(crate::Error::Manifest(ManifestError::UnsupportedVersion(2)))));
+        assert!(matches!(
+            pack_with(r#"{"version": 1, "chunks": [{"file": "../x.bin", "type": "ruSt"}]}"#),
+            Err(crate::Error::Manifest(ManifestError::InvalidFileName(0, _)))
+        ));
+        assert!(matches!(
+            pack_with(r#"{"version": 1, "chunks": [{"file": "x.bin", "type": "ru5t"}]}"#),
+            Err(crate::Error::Manifest(ManifestError::InvalidChunkType(0, _)))
+        ));
+    }
+}
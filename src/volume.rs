@@ -0,0 +1,212 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, payload::{self, PayloadError, Segment}, png::Png};
+use sha2::{Digest, Sha256};
+use std::{collections::hash_map::RandomState, fmt, hash::BuildHasher, str::FromStr, time::SystemTime};
+
+// ancillary, private and safe to copy so editors carry volumes along untouched
+pub const VOLUME_CHUNK_TYPE: &str = "voLm";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    id: u64,
+    hash: [u8; 32],
+    segment: Segment,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VolumeError {
+    NoCarriers,
+    Malformed(usize),
+    MissingVolume(usize),
+    MixedVolumes(u64, u64),
+    HashMismatch,
+    Segment(PayloadError),
+}
+
+impl std::error::Error for VolumeError {}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VolumeError::NoCarriers => write!(f, "At least one carrier is needed to split a payload into volumes"),
+            VolumeError::Malformed(size) => write!(f, "Volume chunk data too short to hold a volume header ({} < {})", size, Volume::HEADER_LENGTH + Segment::HEADER_LENGTH),
+            VolumeError::MissingVolume(index) => write!(f, "Carrier #{} does not contain a {} chunk", index, VOLUME_CHUNK_TYPE),
+            VolumeError::MixedVolumes(expected, found) => write!(f, "Volume {:016x} does not belong to volume set {:016x}", found, expected),
+            VolumeError::HashMismatch => write!(f, "Joined payload does not match the SHA-256 hash stored in its volumes"),
+            VolumeError::Segment(err) => write!(f, "Volume reassembly error: {}", err),
+        }
+    }
+}
+
+impl From<PayloadError> for VolumeError {
+    fn from(err: PayloadError) -> Self {
+        VolumeError::Segment(err)
+    }
+}
+
+impl Volume {
+    // volume id + SHA-256 of the whole payload, followed by a payload segment
+    pub const HEADER_LENGTH: usize = 8 + 32;
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn hash(&self) -> &[u8; 32] {
+        &self.hash
+    }
+
+    pub fn index(&self) -> u32 {
+        self.segment.sequence()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.segment.count()
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.id
+            .to_be_bytes()
+            .into_iter()
+            .chain(self.hash)
+            .chain(self.segment.as_bytes())
+            .collect()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str(VOLUME_CHUNK_TYPE).unwrap(), self.as_bytes())
+    }
+}
+
+impl TryFrom<&[u8]> for Volume {
+    type Error = VolumeError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < Volume::HEADER_LENGTH + Segment::HEADER_LENGTH {
+            return Err(VolumeError::Malformed(bytes.len()));
+        }
+
+        let segment = Segment::try_from(&bytes[Volume::HEADER_LENGTH..])
+            .map_err(|_| VolumeError::Malformed(bytes.len()))?;
+
+        Ok(Volume {
+            id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            hash: bytes[8..Volume::HEADER_LENGTH].try_into().unwrap(),
+            segment,
+        })
+    }
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn new_volume_id(hash: &[u8; 32]) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+
+    RandomState::new().hash_one((hash, nanos))
+}
+
+pub fn split(payload: &[u8], count: usize) -> Result<Vec<Volume>, VolumeError> {
+    if count == 0 {
+        return Err(VolumeError::NoCarriers);
+    }
+
+    let hash = sha256(payload);
+    let id = new_volume_id(&hash);
+    let count = u32::try_from(count).map_err(|_| PayloadError::TooManySegments(count))?;
+
+    Ok(payload::segment_evenly(payload, count)?
+        .into_iter()
+        .map(|segment| Volume { id, hash, segment })
+        .collect())
+}
+
+pub fn find(png: &Png) -> Option<Result<Volume, VolumeError>> {
+    png.chunk_by_type(VOLUME_CHUNK_TYPE)
+        .map(|chunk| Volume::try_from(chunk.data()))
+}
+
+pub fn join(volumes: Vec<Volume>) -> Result<Vec<u8>, VolumeError> {
+    let (id, hash) = match volumes.first() {
+        Some(volume) => (volume.id, volume.hash),
+        None => return Err(VolumeError::NoCarriers),
+    };
+
+    if let Some(stray) = volumes.iter().find(|volume| volume.id != id) {
+        return Err(VolumeError::MixedVolumes(id, stray.id));
+    }
+
+    let data = payload::join(volumes.into_iter().map(|volume| volume.segment).collect())?;
+
+    if sha256(&data) != hash {
+        return Err(VolumeError::HashMismatch);
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"A payload that gets spread over several carrier images";
+
+    #[test]
+    fn test_volume_round_trip() {
+        let volumes = split(PAYLOAD, 3).unwrap();
+        let chunk = volumes[1].to_chunk();
+
+        assert_eq!(&chunk.chunk_type().to_string(), VOLUME_CHUNK_TYPE);
+        assert_eq!(Volume::try_from(chunk.data()).unwrap(), volumes[1]);
+    }
+
+    #[test]
+    fn test_volumes_share_id() {
+        let volumes = split(PAYLOAD, 4).unwrap();
+        assert!(volumes.iter().all(|volume| volume.id() == volumes[0].id()));
+        assert!(volumes.iter().all(|volume| volume.count() == 4));
+    }
+
+    #[test]
+    fn test_join_any_order() {
+        let mut volumes = split(PAYLOAD, 5).unwrap();
+        volumes.swap(0, 4);
+        volumes.swap(1, 3);
+
+        assert_eq!(join(volumes).unwrap(), PAYLOAD);
+    }
+
+    #[test]
+    fn test_join_more_carriers_than_bytes() {
+        let volumes = split(b"ab", 4).unwrap();
+        assert_eq!(volumes.len(), 4);
+        assert_eq!(join(volumes).unwrap(), b"ab");
+    }
+
+    #[test]
+    fn test_join_missing_volume() {
+        let mut volumes = split(PAYLOAD, 3).unwrap();
+        volumes.remove(1);
+
+        assert_eq!(
+            join(volumes),
+            Err(VolumeError::Segment(PayloadError::Incomplete { missing: vec![1], duplicates: vec![] }))
+        );
+    }
+
+    #[test]
+    fn test_join_mixed_volumes() {
+        let mut volumes = split(PAYLOAD, 2).unwrap();
+        let other = split(PAYLOAD, 2).unwrap();
+        volumes[1] = other[1].clone();
+
+        assert!(matches!(join(volumes), Err(VolumeError::MixedVolumes(_, _))));
+    }
+
+    #[test]
+    fn test_split_without_carriers() {
+        assert_eq!(split(PAYLOAD, 0), Err(VolumeError::NoCarriers));
+    }
+}
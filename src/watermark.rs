@@ -0,0 +1,267 @@
+use crate::{limits::Limits, pixels::{self, Header, Pass, PixelError}, png::Png, volume::sha256};
+use std::{collections::HashSet, fmt};
+
+// every bit of a 64 bit fingerprint is written this many times, identify takes the majority
+const COPIES: usize = 32;
+const BITS: usize = 64;
+// how many fingerprint bits may be off before a copy no longer counts as a recipient's
+const MAX_DISTANCE: u32 = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WatermarkError {
+    Unsupported { bit_depth: u8, colour_type: u8 },
+    TooSmall,
+    NoMatch(u32),
+    NameClash(String, String),
+}
+
+impl std::error::Error for WatermarkError {}
+
+impl fmt::Display for WatermarkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatermarkError::Unsupported { bit_depth, colour_type } => write!(f, "Images of colour type {} at bit depth {} cannot carry a watermark", colour_type, bit_depth),
+            WatermarkError::TooSmall => write!(f, "Image has too few visible pixels to carry a watermark"),
+            WatermarkError::NoMatch(distance) => write!(f, "No recipient matches the watermark (closest is {} of {} bits off)", distance, BITS),
+            WatermarkError::NameClash(first, second) => write!(f, "Copies for \"{}\" and \"{}\" would have the same file name", first, second),
+        }
+    }
+}
+
+// SplitMix64, enough to spread the watermark over the image in a way only the key reproduces
+struct Keyed(u64);
+
+impl Keyed {
+    fn new(key: &str, purpose: &str) -> Keyed {
+        let hash = sha256(format!("pngme watermark {}\0{}", purpose, key).as_bytes());
+        Keyed(u64::from_be_bytes(hash[..8].try_into().unwrap()))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+pub fn fingerprint(key: &str, recipient: &str) -> u64 {
+    let hash = sha256(format!("{}\0{}", key, recipient).as_bytes());
+    u64::from_be_bytes(hash[..8].try_into().unwrap())
+}
+
+// the byte holding the least significant bit of a sample, inside the rows of its pass
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Spot {
+    pass: usize,
+    byte: usize,
+}
+
+// colour samples of visible pixels only: changing a transparent pixel is pointless as optimisers wipe
+// them, and a pixel that could turn into the tRNS colour key would vanish; both tests look past the
+// least significant bits so they come out the same before and after marking
+fn spot(header: &Header, passes: &[Pass], transparency: &[u8], x: u32, y: u32, channel: usize) -> Option<Spot> {
+    let (pass, column, row) = header.locate(x, y);
+    let bytes = header.bit_depth as usize / 8;
+    let channels = header.channels();
+    let pixel = &passes[pass].rows[row as usize * header.row_bytes(passes[pass].width) + column as usize * channels * bytes..][..channels * bytes];
+    let sample = |channel: usize| pixels::sample(pixel, channel, header.bit_depth);
+
+    let visible = match header.colour_type {
+        4 | 6 => sample(channels - 1) != 0,
+        _ if transparency.len() == (channels * 2) => (0..channels).any(|channel| {
+            sample(channel) >> 1 != u16::from_be_bytes([transparency[channel * 2], transparency[channel * 2 + 1]]) >> 1
+        }),
+        _ => true,
+    };
+
+    visible.then(|| Spot {
+        pass,
+        byte: row as usize * header.row_bytes(passes[pass].width) + (column as usize * channels + channel) * bytes + bytes - 1,
+    })
+}
+
+// the keyed spots of each fingerprint bit, COPIES of them per bit
+fn spots(header: &Header, passes: &[Pass], transparency: &[u8], key: &str) -> crate::Result<Vec<Spot>> {
+    if header.bit_depth < 8 || header.colour_type == 3 {
+        return Err(WatermarkError::Unsupported { bit_depth: header.bit_depth, colour_type: header.colour_type }.into());
+    }
+
+    let colour_channels = if header.colour_type == 2 || header.colour_type == 6 { 3 } else { 1 };
+    let mut keyed = Keyed::new(key, "positions");
+    let mut taken = HashSet::new();
+    let mut spots = Vec::with_capacity(BITS * COPIES);
+
+    // drawing stops after a fixed number of tries, so a mostly transparent image fails instead of looping
+    for _ in 0..BITS * COPIES * 64 {
+        if spots.len() == BITS * COPIES {
+            break;
+        }
+        let x = keyed.below(header.width as u64) as u32;
+        let y = keyed.below(header.height as u64) as u32;
+        let channel = keyed.below(colour_channels) as usize;
+        if let Some(spot) = spot(header, passes, transparency, x, y, channel).filter(|spot| taken.insert(*spot)) {
+            spots.push(spot);
+        }
+    }
+    if spots.len() < BITS * COPIES {
+        return Err(WatermarkError::TooSmall.into());
+    }
+    Ok(spots)
+}
+
+fn decode(png: &Png, limits: &Limits) -> crate::Result<(Header, Vec<Pass>, Vec<u8>)> {
+    let header = Header::try_from(png.chunk_at(0).ok_or(PixelError::MissingHeader)?)?;
+    let passes = pixels::inflate(&header, &pixels::image_data(png)?, limits)?;
+    let transparency = png.chunk_by_type("tRNS").map(|chunk| chunk.data().to_vec()).unwrap_or_default();
+    Ok((header, passes, transparency))
+}
+
+// a copy of `png` whose image data carries the recipient's fingerprint, every other chunk is kept as it is
+pub fn embed(png: &Png, key: &str, recipient: &str, limits: &Limits) -> crate::Result<Png> {
+    let (header, mut passes, transparency) = decode(png, limits)?;
+    let spots = spots(&header, &passes, &transparency, key)?;
+    // the bits are whitened so the marked samples do not follow the fingerprint's pattern
+    let bits = fingerprint(key, recipient) ^ Keyed::new(key, "whitening").next();
+
+    for (index, spot) in spots.iter().enumerate() {
+        let bit = (bits >> (index / COPIES)) as u8 & 1;
+        let byte = &mut passes[spot.pass].rows[spot.byte];
+        *byte = (*byte & !1) | bit;
+    }
+
+    Ok(pixels::with_image_data(png, &header, &passes))
+}
+
+// the fingerprint read back by majority, with the share of copies that agreed with it
+pub fn read(png: &Png, key: &str, limits: &Limits) -> crate::Result<(u64, f64)> {
+    let (header, passes, transparency) = decode(png, limits)?;
+    let spots = spots(&header, &passes, &transparency, key)?;
+
+    let mut bits = 0u64;
+    let mut agreeing = 0;
+    for (bit, copies) in spots.chunks(COPIES).enumerate() {
+        let ones = copies.iter().filter(|spot| passes[spot.pass].rows[spot.byte] & 1 == 1).count();
+        if ones * 2 > COPIES {
+            bits |= 1 << bit;
+        }
+        agreeing += ones.max(COPIES - ones);
+    }
+
+    Ok((bits ^ Keyed::new(key, "whitening").next(), agreeing as f64 / (BITS * COPIES) as f64))
+}
+
+// the recipient whose fingerprint is closest to the one read, with the number of bits it is off by
+pub fn identify<'a>(png: &Png, key: &str, recipients: &'a [String], limits: &Limits) -> crate::Result<(&'a str, u32, f64)> {
+    let (found, agreement) = read(png, key, limits)?;
+
+    let (recipient, distance) = recipients
+        .iter()
+        .map(|recipient| (recipient.as_str(), (fingerprint(key, recipient) ^ found).count_ones()))
+        .min_by_key(|&(_, distance)| distance)
+        .unwrap_or(("", BITS as u32));
+
+    if distance > MAX_DISTANCE {
+        return Err(WatermarkError::NoMatch(distance).into());
+    }
+    Ok((recipient, distance, agreement))
+}
+
+// recipient IDs become part of the file names, anything but letters, digits, '-' and '_' is replaced; two IDs
+// ending up with the same name, letter case aside, would overwrite each other's copy
+pub fn copy_names(stem: &str, recipients: &[String]) -> Result<Vec<String>, WatermarkError> {
+    let mut names: Vec<String> = Vec::with_capacity(recipients.len());
+    for (number, recipient) in recipients.iter().enumerate() {
+        let safe: String = recipient.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+        let name = format!("{}-{}.png", stem, safe);
+        if let Some(clash) = names.iter().position(|other| other.to_lowercase() == name.to_lowercase()) {
+            return Err(WatermarkError::NameClash(recipients[clash].clone(), recipients[number].clone()));
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compare::{self, Comparison}, error::Error, pixels::Image, testing::chunk};
+
+    fn photo() -> Png {
+        let pixels = (0..64 * 48).map(|i| [(i * 37 % 65536) as u16, (i * 91 % 65536) as u16, 0x4000, 0xFFFF]).collect();
+        let mut png = Image { width: 64, height: 48, pixels }.to_png();
+        png.append_chunk(chunk("tEXt", b"Title\0Screenshot"));
+        png
+    }
+
+    fn recipients() -> Vec<String> {
+        ["alice", "bob", "carol"].map(String::from).to_vec()
+    }
+
+    #[test]
+    fn test_identify_each_copy() {
+        let limits = Limits::default();
+        let recipients = recipients();
+        for recipient in &recipients {
+            let copy = embed(&photo(), "s3cret", recipient, &limits).unwrap();
+            let (found, distance, agreement) = identify(&copy, "s3cret", &recipients, &limits).unwrap();
+
+            assert_eq!((found, distance), (recipient.as_str(), 0));
+            assert_eq!(agreement, 1.0);
+            assert_eq!(copy.chunks_by_type("tEXt").len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_only_least_significant_bits_change() {
+        let limits = Limits::default();
+        let copy = embed(&photo(), "s3cret", "alice", &limits).unwrap();
+
+        let comparison = compare::compare(&Image::decode(&photo(), &limits).unwrap(), &Image::decode(&copy, &limits).unwrap());
+        assert!(matches!(comparison, Comparison::Different { max_error: 1, .. }));
+    }
+
+    #[test]
+    fn test_survives_damage() {
+        let limits = Limits::default();
+        let copy = embed(&photo(), "s3cret", "bob", &limits).unwrap();
+
+        // scribble over a band of the picture
+        let mut image = Image::decode(&copy, &limits).unwrap();
+        for pixel in image.pixels.iter_mut().skip(64 * 10).take(64 * 6) {
+            *pixel = [0, 0, 0, 0xFFFF];
+        }
+        let recipients = recipients();
+        let (found, _, agreement) = identify(&image.to_png(), "s3cret", &recipients, &limits).unwrap();
+        assert_eq!(found, "bob");
+        assert!(agreement < 1.0);
+    }
+
+    #[test]
+    fn test_wrong_key_or_unmarked() {
+        let limits = Limits::default();
+        let copy = embed(&photo(), "s3cret", "carol", &limits).unwrap();
+
+        assert!(matches!(identify(&copy, "guess", &recipients(), &limits), Err(Error::Watermark(WatermarkError::NoMatch(_)))));
+        assert!(matches!(identify(&photo(), "s3cret", &recipients(), &limits), Err(Error::Watermark(WatermarkError::NoMatch(_)))));
+    }
+
+    #[test]
+    fn test_copy_names() {
+        assert_eq!(copy_names("plan", &["alice".to_string(), "o'brien".to_string()]).unwrap(), ["plan-alice.png", "plan-o_brien.png"]);
+        assert_eq!(copy_names("plan", &["a.b".to_string(), "a_b".to_string()]), Err(WatermarkError::NameClash("a.b".to_string(), "a_b".to_string())));
+        assert_eq!(copy_names("plan", &["Bob".to_string(), "bob".to_string()]), Err(WatermarkError::NameClash("Bob".to_string(), "bob".to_string())));
+        assert!(copy_names("plan", &["bob".to_string(), "bob".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_transparent_images_are_too_small() {
+        let png = Image { width: 64, height: 48, pixels: vec![[0; 4]; 64 * 48] }.to_png();
+        assert!(matches!(embed(&png, "s3cret", "alice", &Limits::default()), Err(Error::Watermark(WatermarkError::TooSmall))));
+    }
+}